@@ -0,0 +1,402 @@
+//! # Alert channels
+//!
+//! The alerts are delivered through the `AlertChannel` trait, implement it to send them anywhere else.
+//!
+//! ### Implementations
+//! - `LogChannel` - Logs every alert as a warning.
+//! - `WebhookChannel` - `POST`s every alert as JSON to a URL, through an `HttpTransport`.
+//! - `CommandChannel` - Runs a program with the alert as JSON on its standard input, with a timeout.
+//!
+//! `Notifier` delivers the alerts through every channel, a channel that fails does not stop the others.
+
+use async_trait::async_trait;
+use std::error::Error as StdError;
+use std::fmt::Debug;
+use std::io::ErrorKind;
+use std::process::{
+    ExitStatus,
+    Stdio
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{
+    Child,
+    ChildStdin,
+    Command
+};
+
+use crate::alerts::rules::Alert;
+use crate::config::{
+    AlertChannelConfig,
+    AlertConfig
+};
+use crate::errors::HttpStatusError;
+use crate::utils::request_builder::shared_client;
+use crate::utils::transport::{
+    HttpRequest,
+    HttpResponse,
+    HttpTransport,
+    ReqwestTransport
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The `AlertChannel` trait delivers an alert.
+#[async_trait]
+pub trait AlertChannel: Debug + Send + Sync {
+    /// The name of the channel, for the logs.
+    fn name(&self) -> &str;
+
+    async fn send(
+        &self,
+        alert: &Alert
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>>;
+}
+
+
+/// The `LogChannel` struct logs every alert as a warning.
+#[derive(Debug, Clone, Default)]
+pub struct LogChannel;
+
+#[async_trait]
+impl AlertChannel for LogChannel {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    async fn send(
+        &self,
+        alert: &Alert
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        warn!(
+            "Alert {}: {} at {} is {} ({}), since {}",
+            alert.rule,
+            alert.symbol,
+            alert.broker,
+            alert.value,
+            alert.condition,
+            alert.since
+        );
+        Ok(())
+    }
+}
+
+
+/// The `WebhookChannel` struct `POST`s every alert as JSON to a URL.
+///
+/// ### Example
+///
+/// ```
+/// use std::sync::{ Arc, Mutex };
+/// use async_trait::async_trait;
+/// use chrono::{ TimeZone, Utc };
+/// use spread_tracker::alerts::channels::{ Notifier, WebhookChannel };
+/// use spread_tracker::alerts::rules::Alert;
+/// use spread_tracker::utils::transport::{ HttpRequest, HttpResponse, HttpTransport };
+///
+/// // A stand-in for the webhook that accepts every request and keeps it.
+/// #[derive(Debug, Default)]
+/// struct FakeWebhook {
+///     requests: Mutex<Vec<HttpRequest>>
+/// }
+///
+/// #[async_trait]
+/// impl HttpTransport for FakeWebhook {
+///     async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error + Send + Sync>> {
+///         self.requests.lock().unwrap().push(request);
+///         Ok(HttpResponse::new(204, ""))
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let webhook = Arc::new(FakeWebhook::default());
+/// let notifier = Notifier::new()
+///     .channel(Arc::new(WebhookChannel::with_transport("https://hooks.example.com/spreads", webhook.clone())));
+///
+/// let ts = Utc.with_ymd_and_hms(2024, 5, 1, 12, 5, 0).unwrap();
+/// let alert = Alert {
+///     rule: "pepperstone-eurusd-wide".to_string(),
+///     broker: "pepperstone".to_string(),
+///     symbol: "EURUSD".to_string(),
+///     ts,
+///     spread: 0.0002,
+///     value: 2.0,
+///     condition: "above 1.5 pips for 5m".to_string(),
+///     since: ts - chrono::Duration::minutes(5)
+/// };
+/// assert_eq!(notifier.notify(&[alert.clone()]).await, vec![alert]);
+///
+/// let requests = webhook.requests.lock().unwrap();
+/// assert_eq!(requests[0].method, "POST");
+/// assert!(requests[0].body.as_deref().unwrap().contains("\"rule\":\"pepperstone-eurusd-wide\""));
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct WebhookChannel {
+    url: String,
+    headers: Vec<(String, String)>,
+    transport: Arc<dyn HttpTransport>
+}
+
+impl WebhookChannel {
+    /// # Creates a `WebhookChannel` to the URL, using the process-wide HTTP client.
+    ///
+    /// ### Errors
+    /// `failed_to_read_yaml` will be returned if the `Http` section is not in the correct format.
+    pub fn new(
+        url: &str
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        Ok(Self::with_transport(url, Arc::new(ReqwestTransport::new(shared_client()?))))
+    }
+
+    /// # Creates a `WebhookChannel` to the URL that sends every request through the given transport.
+    pub fn with_transport(
+        url: &str,
+        transport: Arc<dyn HttpTransport>
+    ) -> Self {
+        Self {
+            url: url.to_string(),
+            headers: Vec::new(),
+            transport
+        }
+    }
+
+    /// Adds a header to every request, like an `Authorization`.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+#[async_trait]
+impl AlertChannel for WebhookChannel {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn send(
+        &self,
+        alert: &Alert
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        let mut request: HttpRequest = HttpRequest::with_body("POST", &self.url, serde_json::to_string(alert)?)
+            .header("Content-Type", "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        let response: HttpResponse = self.transport.send(request).await?;
+        if !response.is_success() {
+            error!("The webhook rejected an alert with status {}: {}", response.status, response.body);
+            return Err(Box::new(HttpStatusError { status: response.status, retry_after: None }));
+        }
+
+        Ok(())
+    }
+}
+
+
+/// The `CommandChannel` struct runs a program for every alert, with the alert as JSON on its standard input.
+///
+/// The rule, broker, symbol and value of the alert are also in the `SPREAD_ALERT_RULE`, `SPREAD_ALERT_BROKER`,
+/// `SPREAD_ALERT_SYMBOL` and `SPREAD_ALERT_VALUE` environment variables. A program that exits with a failure fails the delivery,
+/// so does a program still running after the timeout, which is killed. A program that exits without reading its standard input
+/// is judged by its exit status only.
+///
+/// ### Example
+///
+/// ```
+/// use std::time::Duration;
+/// use chrono::Utc;
+/// use spread_tracker::alerts::channels::{ AlertChannel, CommandChannel };
+/// use spread_tracker::alerts::rules::Alert;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let alert = Alert {
+///     rule: "eurusd-wide".to_string(),
+///     broker: "pepperstone".to_string(),
+///     symbol: "EURUSD".to_string(),
+///     ts: Utc::now(),
+///     spread: 0.0002,
+///     value: 2.0,
+///     condition: "above 1.5 pips".to_string(),
+///     since: Utc::now()
+/// };
+///
+/// // `true` exits without reading the alert, its exit status is what counts.
+/// assert!(CommandChannel::new("true", Vec::new()).send(&alert).await.is_ok());
+///
+/// // A hook that hangs is killed after the timeout.
+/// let hanging = CommandChannel::new("sleep", vec!["10".to_string()]).timeout(Duration::from_millis(100));
+/// assert!(hanging.send(&alert).await.is_err());
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CommandChannel {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration
+}
+
+impl CommandChannel {
+    /// # Creates a `CommandChannel` running the program with the arguments, killed after 30 seconds.
+    pub fn new(
+        program: &str,
+        args: Vec<String>
+    ) -> Self {
+        Self {
+            program: program.to_string(),
+            args,
+            timeout: Duration::from_secs(30)
+        }
+    }
+
+    /// Sets how long the program may run before it is killed.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[async_trait]
+impl AlertChannel for CommandChannel {
+    fn name(&self) -> &str {
+        "command"
+    }
+
+    async fn send(
+        &self,
+        alert: &Alert
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        let input: String = serde_json::to_string(alert)?;
+        let mut child: Child = Command::new(&self.program)
+            .args(&self.args)
+            .env("SPREAD_ALERT_RULE", &alert.rule)
+            .env("SPREAD_ALERT_BROKER", &alert.broker)
+            .env("SPREAD_ALERT_SYMBOL", &alert.symbol)
+            .env("SPREAD_ALERT_VALUE", alert.value.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdin: Option<ChildStdin> = child.stdin.take();
+        let exchange = async {
+            if let Some(mut stdin) = stdin {
+                match stdin.write_all(input.as_bytes()).await {
+                    // The program does not read the alert, its exit status tells how it went
+                    Err(err) if err.kind() == ErrorKind::BrokenPipe => {},
+                    result => result?
+                }
+                // Closing the standard input lets the program see the end of the alert
+                drop(stdin);
+            }
+            child.wait().await
+        };
+
+        let status: ExitStatus = match tokio::time::timeout(self.timeout, exchange).await {
+            Ok(status) => status?,
+            Err(_) => {
+                child.kill().await?;
+                return Err(format!("{} did not exit within {:?} and was killed", self.program, self.timeout).into());
+            }
+        };
+
+        match status.success() {
+            true => Ok(()),
+            false => Err(format!("{} exited with {}", self.program, status).into())
+        }
+    }
+}
+
+
+/// The `Notifier` struct delivers the alerts through every channel.
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    channels: Vec<Arc<dyn AlertChannel>>
+}
+
+impl Notifier {
+    /// # Creates a `Notifier` without channels.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Creates a `Notifier` with the channels of the `Alerts` section of the configuration.
+    ///
+    /// ### Errors
+    /// The errors of `WebhookChannel::new`.
+    pub fn from_config(
+        config: &AlertConfig
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut notifier: Self = Self::new();
+
+        for channel in &config.channels {
+            let channel: Arc<dyn AlertChannel> = match channel {
+                AlertChannelConfig::Log => Arc::new(LogChannel),
+                AlertChannelConfig::Webhook { url, headers } => {
+                    let mut webhook: WebhookChannel = WebhookChannel::new(url)?;
+                    for (name, value) in headers {
+                        webhook = webhook.header(name, value);
+                    }
+                    Arc::new(webhook)
+                },
+                AlertChannelConfig::Command { program, args, timeout_secs } => {
+                    Arc::new(CommandChannel::new(program, args.clone()).timeout(Duration::from_secs(*timeout_secs)))
+                }
+            };
+            notifier = notifier.channel(channel);
+        }
+
+        Ok(notifier)
+    }
+
+    /// Adds a channel.
+    pub fn channel(mut self, channel: Arc<dyn AlertChannel>) -> Self {
+        self.channels.push(channel);
+        self
+    }
+
+    /// # Delivers every alert through every channel, and returns the alerts delivered through at least one channel,
+    /// to acknowledge them, see `AlertEngine::acknowledge`.
+    ///
+    /// A failed delivery is logged, and does not stop the others.
+    pub async fn notify(
+        &self,
+        alerts: &[Alert]
+    ) -> Vec<Alert> {
+        let mut delivered: Vec<Alert> = Vec::new();
+        let mut deliveries: usize = 0;
+
+        for alert in alerts {
+            let mut sent: bool = false;
+            for channel in &self.channels {
+                match channel.send(alert).await {
+                    Ok(()) => {
+                        sent = true;
+                        deliveries += 1;
+                    },
+                    Err(err) => error!("Failed to deliver the alert {} through the {} channel: {}", alert.rule, channel.name(), err)
+                }
+            }
+            if sent {
+                delivered.push(alert.clone());
+            }
+        }
+
+        if deliveries > 0 {
+            info!("Delivered {} alert notification(s)", deliveries);
+        }
+        if delivered.len() < alerts.len() {
+            warn!("{} alert(s) were not delivered, they are retried on the next poll", alerts.len() - delivered.len());
+        }
+        delivered
+    }
+}
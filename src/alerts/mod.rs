@@ -0,0 +1,32 @@
+//! # Alerts
+//!
+//! Rule-based alerting on the spreads: the rules of the `Alerts` section of `spread_config.yaml` are evaluated on the quotes
+//! of every poll, and the alerts they fire are delivered through pluggable channels.
+//!
+//! ### Modules
+//! - `rules`: Evaluates the rules, holds their breaches, deduplicates the alerts and applies the cooldown.
+//! - `channels`: The `AlertChannel` trait and its log, webhook and command implementations.
+//!
+//! ### Example
+//!
+//! ```no_run
+//! use spread_tracker::alerts::channels::Notifier;
+//! use spread_tracker::alerts::rules::AlertEngine;
+//! use spread_tracker::config::AlertConfig;
+//! use spread_tracker::model::Quote;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let config = AlertConfig::load().unwrap();
+//! let mut engine = AlertEngine::from_config(&config).unwrap();
+//! let notifier = Notifier::from_config(&config).unwrap();
+//!
+//! let quotes: Vec<Quote> = Vec::new();
+//! let delivered = notifier.notify(&engine.evaluate(&quotes)).await;
+//! engine.acknowledge(&delivered);
+//! engine.state().save("alerts_state.json").unwrap();
+//! # }
+//! ```
+
+pub mod rules;
+pub mod channels;
@@ -0,0 +1,448 @@
+//! # Alert rules
+//!
+//! `AlertEngine` evaluates the rules of the `Alerts` section against the quotes of every poll, and returns the alerts to notify.
+//!
+//! - A pair breaches a rule when its spread is above or below the threshold, the breach ends with the first quote within it.
+//!   A breach also expires when the pair goes unquoted for longer than the `For` of the rule plus the poll interval,
+//!   a pair missing from the polls is not held through the gap.
+//! - A rule with `For` fires once the breach has lasted that long, measured on the times of the quotes.
+//! - A breach is notified once, however many polls it lasts: the alerts are deduplicated.
+//!   An alert only counts as notified once it is acknowledged, after a channel delivered it, see `AlertEngine::acknowledge`.
+//!   Until then, the breach fires again on the next poll.
+//! - After a notification, the same rule and pair is not notified again before the cooldown is over, even for a new breach.
+//!   A breach still running when the cooldown ends is notified then.
+//!
+//! The breaches and notifications are an `AlertState`, saved between runs so a rule can be held over several polls.
+
+use chrono::{
+    DateTime,
+    Duration,
+    Utc
+};
+use serde_derive::{
+    Deserialize,
+    Serialize
+};
+use std::collections::{
+    BTreeMap,
+    BTreeSet
+};
+use std::error::Error as StdError;
+use std::fs::{
+    self,
+    File
+};
+use std::io::{
+    BufReader,
+    BufWriter,
+    ErrorKind,
+    Write
+};
+use std::path::{
+    Path,
+    PathBuf
+};
+
+use crate::config::{
+    AlertConfig,
+    AlertRule,
+    SpreadUnit
+};
+use crate::db::history::parse_interval;
+use crate::model::{
+    Quote,
+    pip_size
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// Struct that holds the notification of a rule breached by the spread of a broker and symbol.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    /// The name of the rule.
+    pub rule: String,
+    pub broker: String,
+    pub symbol: String,
+    /// The time of the quote that fired the rule.
+    pub ts: DateTime<Utc>,
+    pub spread: f64,
+    /// The spread in the unit of the rule.
+    pub value: f64,
+    /// The condition of the rule, like `above 1.5 pips for 5m`.
+    pub condition: String,
+    /// The time of the first quote of the breach.
+    pub since: DateTime<Utc>
+}
+
+
+/// Struct that holds a running breach of a rule by a pair.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Breach {
+    /// The time of the first quote of the breach.
+    pub since: DateTime<Utc>,
+    /// The time of the last quote of the breach.
+    pub last_seen: DateTime<Utc>,
+    /// Whether the breach was notified.
+    pub notified: bool
+}
+
+
+/// Struct that holds the running breaches and the last notification of every rule and pair, keyed `rule/broker/symbol`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AlertState {
+    pub breaches: BTreeMap<String, Breach>,
+    pub notified: BTreeMap<String, DateTime<Utc>>
+}
+
+impl AlertState {
+    /// # Loads a state file, or returns an empty state when the file does not exist.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be read or is not a state.
+    pub fn load(
+        path: impl AsRef<Path>
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let file: File = match File::open(path.as_ref()) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(Box::new(err))
+        };
+
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// # Writes the state to a file, creating the parent directory when needed.
+    ///
+    /// The file is written next to its destination first, then renamed over it.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be written.
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        let path: &Path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temporary_path: PathBuf = path.with_extension("json.tmp");
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(&temporary_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temporary_path, path)?;
+
+        Ok(())
+    }
+}
+
+
+/// The `AlertEngine` struct evaluates the alert rules on the quotes of every poll.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, TimeZone, Utc };
+/// use spread_tracker::alerts::rules::AlertEngine;
+/// use spread_tracker::config::{ AlertRule, SpreadUnit };
+/// use spread_tracker::model::Quote;
+///
+/// let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+/// let quote = |minute: i64, pips: f64| Quote::sample("pepperstone", "EURUSD", start + Duration::minutes(minute), pips * 0.0001);
+///
+/// let rule = AlertRule {
+///     name: "pepperstone-eurusd-wide".to_string(),
+///     broker: Some("pepperstone".to_string()),
+///     symbol: "EURUSD".to_string(),
+///     above: Some(1.5),
+///     below: None,
+///     unit: SpreadUnit::Pips,
+///     r#for: Some("5m".to_string())
+/// };
+/// let mut engine = AlertEngine::new(vec![rule], Duration::minutes(30)).unwrap();
+///
+/// // Above 1.5 pips from minute 0, the rule fires at minute 5. The delivery failed, it fires again at minute 6.
+/// assert!((0..5).all(|minute| engine.evaluate(&[quote(minute, 2.0)]).is_empty()));
+/// assert_eq!(engine.evaluate(&[quote(5, 2.0)]).len(), 1);
+/// let alerts = engine.evaluate(&[quote(6, 2.0)]);
+/// assert_eq!(alerts.len(), 1);
+///
+/// // Once delivered and acknowledged, the breach is not notified again.
+/// engine.acknowledge(&alerts);
+/// assert!((7..10).all(|minute| engine.evaluate(&[quote(minute, 2.0)]).is_empty()));
+///
+/// // The breach ends, a new one held for 5 minutes is still in the cooldown.
+/// assert!(engine.evaluate(&[quote(10, 1.0)]).is_empty());
+/// assert!((11..36).all(|minute| engine.evaluate(&[quote(minute, 2.0)]).is_empty()));
+///
+/// // Once the cooldown is over, the running breach is notified.
+/// let alerts = engine.evaluate(&[quote(36, 2.0)]);
+/// assert_eq!(alerts.len(), 1);
+/// assert_eq!(alerts[0].since, start + Duration::minutes(11));
+/// assert_eq!(alerts[0].condition, "above 1.5 pips for 5m");
+/// engine.acknowledge(&alerts);
+///
+/// // Missing from the polls for longer than 5 minutes plus the poll interval, the pair starts a new breach.
+/// assert!(engine.evaluate(&[quote(70, 2.0)]).is_empty());
+/// assert!(engine.evaluate(&[quote(80, 2.0)]).is_empty());
+/// let alerts = engine.evaluate(&[quote(85, 2.0)]);
+/// assert_eq!(alerts[0].since, start + Duration::minutes(80));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AlertEngine {
+    rules: Vec<(AlertRule, Duration)>,
+    cooldown: Duration,
+    poll_interval: Duration,
+    state: AlertState
+}
+
+impl AlertEngine {
+    /// # Creates an `AlertEngine` with the rules and the cooldown between two notifications of a rule and pair,
+    /// for quotes polled every minute.
+    ///
+    /// ### Errors
+    /// An error will be returned if a rule has neither `above` nor `below`, or its `for` is not a number followed by a unit.
+    pub fn new(
+        rules: Vec<AlertRule>,
+        cooldown: Duration
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let rules: Vec<(AlertRule, Duration)> = rules.into_iter()
+            .map(|rule| {
+                if rule.above.is_none() && rule.below.is_none() {
+                    return Err(format!("The alert rule {:?} has neither Above nor Below", rule.name).into());
+                }
+                let held: Duration = match &rule.r#for {
+                    Some(held) => parse_interval(held)?,
+                    None => Duration::zero()
+                };
+
+                Ok((rule, held))
+            })
+            .collect::<Result<Vec<(AlertRule, Duration)>, Box<dyn StdError + Send + Sync + 'static>>>()?;
+
+        Ok(Self {
+            rules,
+            cooldown,
+            poll_interval: Duration::minutes(1),
+            state: AlertState::default()
+        })
+    }
+
+    /// # Creates an `AlertEngine` with the rules and the cooldown of the `Alerts` section of the configuration,
+    /// and the state of its `State` file.
+    ///
+    /// A run of the tracker is a single poll, the breaches and notifications only outlive it in the state file.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use spread_tracker::alerts::rules::AlertEngine;
+    /// use spread_tracker::config::AlertConfig;
+    ///
+    /// let config: AlertConfig = serde_yaml::from_str("Rules: [{ Name: wide, Symbol: EURUSD, Above: 1.5, For: 5m }]").unwrap();
+    /// assert_eq!(config.state.as_deref(), Some("alerts_state.json"));
+    ///
+    /// // Without a state file, the rule could never be held for 5 minutes.
+    /// let config = AlertConfig { state: None, ..config };
+    /// assert!(AlertEngine::from_config(&config).is_err());
+    /// ```
+    ///
+    /// ### Errors
+    /// The errors of `new`, an error will also be returned if the cooldown or the poll interval is not a number followed by a unit,
+    /// the state file could not be read, or there are rules without a state file.
+    pub fn from_config(
+        config: &AlertConfig
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let engine: Self = Self::new(config.rules.clone(), parse_interval(&config.cooldown)?)?
+            .poll_interval(parse_interval(&config.poll_interval)?);
+
+        match &config.state {
+            Some(path) => Ok(engine.with_state(AlertState::load(path)?)),
+            None if engine.rules.is_empty() => Ok(engine),
+            None => Err("The alert rules need a State file, For, the cooldown and the dedupe would not last beyond the run".into())
+        }
+    }
+
+    /// Sets the time between two polls, a breach expires once its pair is unquoted for longer than its `For` plus it.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Sets the state, saved by a previous run.
+    pub fn with_state(mut self, state: AlertState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Returns the running breaches and the last notifications, to save them for the next run.
+    pub fn state(&self) -> &AlertState {
+        &self.state
+    }
+
+    /// # Evaluates every rule on the quotes, in the order of their time, and returns the alerts to notify.
+    ///
+    /// A rule and pair fires once per call. The alerts are not notified before they are acknowledged, see `acknowledge`.
+    pub fn evaluate(
+        &mut self,
+        quotes: &[Quote]
+    ) -> Vec<Alert> {
+        let mut sorted: Vec<&Quote> = quotes.iter().collect();
+        sorted.sort_by_key(|quote| quote.ts);
+
+        let latest: Option<DateTime<Utc>> = sorted.last().map(|quote| quote.ts);
+
+        let mut alerts: Vec<Alert> = Vec::new();
+        let mut fired: BTreeSet<String> = BTreeSet::new();
+        for quote in sorted {
+            for index in 0..self.rules.len() {
+                if let Some(alert) = self.evaluate_rule(index, quote, &fired) {
+                    fired.insert(state_key(&alert.rule, &alert.broker, &alert.symbol));
+                    alerts.push(alert);
+                }
+            }
+        }
+        if let Some(latest) = latest {
+            self.expire(latest);
+        }
+
+        if !alerts.is_empty() {
+            info!("{} alert rule(s) fired", alerts.len());
+        }
+        alerts
+    }
+
+    /// # Marks the alerts as notified, once a channel delivered them, so their breaches are not notified again
+    /// and the cooldown of their rule and pair starts.
+    ///
+    /// The alerts of `evaluate` left unacknowledged fire again on the next poll, while their breach runs.
+    pub fn acknowledge(
+        &mut self,
+        alerts: &[Alert]
+    ) {
+        for alert in alerts {
+            let key: String = state_key(&alert.rule, &alert.broker, &alert.symbol);
+            if let Some(breach) = self.state.breaches.get_mut(&key) {
+                breach.notified = true;
+            }
+            self.state.notified.insert(key, alert.ts);
+        }
+    }
+
+    /// The alert of a rule on a quote, updating the breach of the pair. A pair fired earlier in the call does not fire again.
+    fn evaluate_rule(
+        &mut self,
+        index: usize,
+        quote: &Quote,
+        fired: &BTreeSet<String>
+    ) -> Option<Alert> {
+        let (rule, held): &(AlertRule, Duration) = &self.rules[index];
+        let matches: bool = quote.symbol.eq_ignore_ascii_case(&rule.symbol)
+            && rule.broker.as_ref().is_none_or(|broker| quote.broker.eq_ignore_ascii_case(broker));
+        if !matches {
+            return None;
+        }
+
+        let key: String = state_key(&rule.name, &quote.broker, &quote.symbol);
+        let value: f64 = spread_in(quote, rule.unit);
+        let breached: bool = rule.above.is_some_and(|above| value > above) || rule.below.is_some_and(|below| value < below);
+        if !breached {
+            self.state.breaches.remove(&key);
+            return None;
+        }
+
+        let expires_after: Duration = *held + self.poll_interval;
+        let breach: &mut Breach = self.state.breaches.entry(key.clone())
+            .and_modify(|breach| {
+                // The pair went unquoted for too long, the breach starts over
+                if quote.ts - breach.last_seen > expires_after {
+                    *breach = Breach { since: quote.ts, last_seen: quote.ts, notified: false };
+                }
+            })
+            .or_insert(Breach { since: quote.ts, last_seen: quote.ts, notified: false });
+        breach.last_seen = breach.last_seen.max(quote.ts);
+        let cooling: bool = self.state.notified.get(&key).is_some_and(|notified| quote.ts - *notified < self.cooldown);
+        if breach.notified || quote.ts - breach.since < *held || cooling || fired.contains(&key) {
+            return None;
+        }
+
+        Some(Alert {
+            rule: rule.name.clone(),
+            broker: quote.broker.clone(),
+            symbol: quote.symbol.clone(),
+            ts: quote.ts,
+            spread: quote.spread,
+            value,
+            condition: condition(rule),
+            since: breach.since
+        })
+    }
+
+    /// Drops the breaches of the pairs unquoted for longer than the `For` of their rule plus the poll interval.
+    fn expire(
+        &mut self,
+        now: DateTime<Utc>
+    ) {
+        for (rule, held) in &self.rules {
+            let prefix: String = format!("{}/", rule.name);
+            let expires_after: Duration = *held + self.poll_interval;
+
+            self.state.breaches.retain(|key, breach| !key.starts_with(&prefix) || now - breach.last_seen <= expires_after);
+        }
+    }
+}
+
+
+/// The key of a rule and pair in the state, `rule/broker/symbol`.
+fn state_key(
+    rule: &str,
+    broker: &str,
+    symbol: &str
+) -> String {
+    format!("{}/{}/{}", rule, broker, symbol)
+}
+
+
+/// The spread of the quote in the unit.
+fn spread_in(
+    quote: &Quote,
+    unit: SpreadUnit
+) -> f64 {
+    match unit {
+        SpreadUnit::Pips => quote.spread / pip_size(&quote.symbol),
+        SpreadUnit::Points => quote.spread / pip_size(&quote.symbol) * 10.0,
+        SpreadUnit::Price => quote.spread
+    }
+}
+
+
+/// The condition of the rule, like `above 1.5 pips for 5m`.
+fn condition(
+    rule: &AlertRule
+) -> String {
+    let unit: &str = match rule.unit {
+        SpreadUnit::Pips => " pips",
+        SpreadUnit::Points => " points",
+        SpreadUnit::Price => ""
+    };
+
+    let mut parts: Vec<String> = Vec::new();
+    if let Some(above) = rule.above {
+        parts.push(format!("above {}{}", above, unit));
+    }
+    if let Some(below) = rule.below {
+        parts.push(format!("below {}{}", below, unit));
+    }
+    let mut condition: String = parts.join(" or ");
+    if let Some(held) = &rule.r#for {
+        condition.push_str(&format!(" for {}", held));
+    }
+
+    condition
+}
@@ -0,0 +1,342 @@
+//! # Spread spike detection
+//!
+//! Brokers widen their spreads on news, at the rollover or when their liquidity dries up, for seconds to minutes.
+//! `SpikeDetector` watches the incoming quotes of every broker and symbol and flags the spreads far outside their recent
+//! distribution, the last window of the pair before the quote, see `analytics::stats::RollingStats`.
+//!
+//! - Z-score: the spread is a number of standard deviations above the mean of the window.
+//! - Percentile: the spread is a multiple of a percentile of the window, like one and a half times the 99th.
+//!
+//! Nothing is flagged until the window of the pair holds the minimum number of quotes, the warm-up.
+//! Spikes stay in the window and widen the distribution the next quotes are compared with.
+//! A window without deviation, every spread the same, only flags through the percentile.
+//!
+//! Every spike is returned by `push`, logged, and sent to the subscribers of the detector.
+
+use chrono::{
+    DateTime,
+    Duration,
+    Utc
+};
+use serde_derive::Serialize;
+use std::error::Error as StdError;
+use tokio::sync::broadcast;
+
+use crate::analytics::stats::{
+    RollingStats,
+    SpreadStats,
+    pips
+};
+use crate::config::SpikeConfig;
+use crate::db::history::parse_interval;
+use crate::db::storage::{
+    Db,
+    QuoteFilter
+};
+use crate::model::Quote;
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// Enum of the thresholds a spike crossed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpikeTrigger {
+    ZScore,
+    Percentile
+}
+
+
+/// Struct that holds a spread far outside the recent distribution of its broker and symbol.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpikeEvent {
+    pub broker: String,
+    pub symbol: String,
+    /// When the quote was fetched.
+    pub ts: DateTime<Utc>,
+    /// The spread of the quote in pips, not rounded.
+    pub pips: f64,
+    /// The statistics of the window before the quote, in pips.
+    pub baseline: SpreadStats,
+    /// The spread minus the mean of the baseline, in pips.
+    pub deviation_pips: f64,
+    /// The deviation in standard deviations of the baseline, `None` without deviation.
+    pub z_score: Option<f64>,
+    /// The percentile of the baseline the spread was compared with, `None` without a percentile threshold.
+    pub percentile_pips: Option<f64>,
+    pub triggers: Vec<SpikeTrigger>
+}
+
+
+/// The `SpikeDetector` struct flags the spreads far outside the recent distribution of their broker and symbol.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, TimeZone, Utc };
+/// use spread_tracker::analytics::anomaly::{ SpikeDetector, SpikeTrigger };
+/// use spread_tracker::model::Quote;
+///
+/// let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+/// let quote = |minute: i64, pips: f64| Quote::sample("fxpro", "EURUSD", start + Duration::minutes(minute), pips * 0.0001);
+///
+/// let mut detector = SpikeDetector::new(Duration::hours(1)).min_samples(30);
+/// let mut events = detector.subscribe();
+///
+/// // 1.0 and 1.2 pips in turn, a 5 pip spread during the warm-up is not flagged.
+/// for minute in 0..40 {
+///     let pips = if minute == 10 { 5.0 } else if minute % 2 == 0 { 1.0 } else { 1.2 };
+///     assert!(detector.push(&quote(minute, pips)).is_none());
+/// }
+///
+/// // 10 pips, far above the window, the spike of the warm-up included.
+/// let spike = detector.push(&quote(40, 10.0)).unwrap();
+/// assert_eq!(spike.baseline.count, 40);
+/// assert!(spike.z_score.unwrap() > 4.0);
+/// assert_eq!(spike.triggers, vec![SpikeTrigger::ZScore, SpikeTrigger::Percentile]);
+/// assert_eq!(events.try_recv().unwrap(), spike);
+///
+/// assert!(detector.push(&quote(41, 1.2)).is_none());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpikeDetector {
+    rolling: RollingStats,
+    min_samples: usize,
+    z_score: Option<f64>,
+    percentile: Option<f64>,
+    percentile_factor: f64,
+    events: broadcast::Sender<SpikeEvent>
+}
+
+impl SpikeDetector {
+    /// # Creates a `SpikeDetector` over windows of the given length, flagging spreads 4 standard deviations above the mean
+    /// or one and a half times the 99th percentile, after a warm-up of 30 quotes.
+    pub fn new(
+        window: Duration
+    ) -> Self {
+        let (events, _): (broadcast::Sender<SpikeEvent>, broadcast::Receiver<SpikeEvent>) = broadcast::channel(1024);
+
+        Self {
+            rolling: RollingStats::new(window),
+            min_samples: 30,
+            z_score: Some(4.0),
+            percentile: Some(99.0),
+            percentile_factor: 1.5,
+            events
+        }
+    }
+
+    /// # Creates a `SpikeDetector` with the settings of the `Spikes` section of the configuration.
+    ///
+    /// ### Errors
+    /// An error will be returned if the window is not a number followed by a unit.
+    pub fn from_config(
+        config: &SpikeConfig
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        Ok(Self::new(parse_interval(&config.window)?)
+            .min_samples(config.min_samples)
+            .z_score(Some(config.z_score).filter(|z_score| *z_score > 0.0))
+            .percentile(Some(config.percentile).filter(|percentile| *percentile > 0.0), config.percentile_factor))
+    }
+
+    /// Sets the number of quotes the window of a pair holds before its spreads are compared with it.
+    pub fn min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = min_samples;
+        self
+    }
+
+    /// Sets the standard deviations above the mean from which a spread is a spike, `None` disables the z-score.
+    pub fn z_score(mut self, z_score: Option<f64>) -> Self {
+        self.z_score = z_score;
+        self
+    }
+
+    /// Sets the percentile, and the multiple of it from which a spread is a spike, `None` disables the percentile.
+    pub fn percentile(mut self, percentile: Option<f64>, factor: f64) -> Self {
+        self.percentile = percentile;
+        self.percentile_factor = factor;
+        self
+    }
+
+    /// # Returns a receiver of every spike flagged from now on.
+    ///
+    /// A receiver that falls more than 1024 events behind loses the oldest ones.
+    pub fn subscribe(&self) -> broadcast::Receiver<SpikeEvent> {
+        self.events.subscribe()
+    }
+
+    /// # Adds the quotes to the windows without comparing them, to warm up the detector from the history.
+    pub fn warm_up(
+        &mut self,
+        quotes: &[Quote]
+    ) {
+        self.rolling.push_all(quotes);
+    }
+
+    /// # Warms up the detector with the stored quotes matching the filter over the window ending at `to`, see `warm_up`.
+    ///
+    /// The time range of the filter is replaced by the window, and returns the number of quotes read.
+    ///
+    /// ### Errors
+    /// An error will be returned if the query failed.
+    pub async fn warm_up_from(
+        &mut self,
+        db: &dyn Db,
+        filter: &QuoteFilter,
+        to: DateTime<Utc>
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let filter: QuoteFilter = QuoteFilter {
+            from: Some(to - self.rolling.window()),
+            to: Some(to),
+            limit: None,
+            ..filter.clone()
+        };
+        let quotes: Vec<Quote> = db.query_quotes(&filter).await?;
+        self.warm_up(&quotes);
+
+        info!("Warmed up the spike detection with {} stored quote(s)", quotes.len());
+        Ok(quotes.len())
+    }
+
+    /// # Compares the quotes of a poll with the stored quotes of the previous polls, and returns the spikes.
+    ///
+    /// The detector warms up from the window ending at the earliest quote, see `warm_up_from`, then pushes the quotes,
+    /// see `push_all`. A detector created for every run of the tracker compares each poll with the polls before,
+    /// once their quotes are saved to a storage that outlives the run.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use chrono::{ DateTime, Duration, TimeZone, Utc };
+    /// use spread_tracker::analytics::anomaly::{ SpikeDetector, SpikeEvent, SpikeTrigger };
+    /// use spread_tracker::config::SpikeConfig;
+    /// use spread_tracker::db::sqlite::SqliteDb;
+    /// use spread_tracker::db::storage::Db;
+    /// use spread_tracker::model::Quote;
+    /// use std::path::Path;
+    ///
+    /// // A run of the tracker: saves the polled quotes, then compares them with the stored ones.
+    /// async fn run(path: &Path, ts: DateTime<Utc>, pips: f64) -> Vec<SpikeEvent> {
+    ///     let db = SqliteDb::open(path).unwrap();
+    ///     let quotes = vec![Quote::sample("tickmill", "GBPUSD", ts, pips * 0.0001)];
+    ///     db.save_quotes(&quotes).await.unwrap();
+    ///
+    ///     let config = SpikeConfig { enabled: true, min_samples: 1, ..SpikeConfig::default() };
+    ///     SpikeDetector::from_config(&config).unwrap().push_poll(&db, &quotes).await.unwrap()
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let path = std::env::temp_dir().join(format!("spread_spikes_doc_{}.db", std::process::id()));
+    /// let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+    ///
+    /// assert!(run(&path, start, 0.8).await.is_empty());
+    ///
+    /// // The next run compares its 4 pip spread with the quote of the first one.
+    /// let spikes = run(&path, start + Duration::minutes(1), 4.0).await;
+    /// assert_eq!(spikes.len(), 1);
+    /// assert_eq!(spikes[0].baseline.count, 1);
+    /// assert_eq!(spikes[0].triggers, vec![SpikeTrigger::Percentile]);
+    /// # for suffix in ["", "-wal", "-shm"] { let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix)); }
+    /// # }
+    /// ```
+    ///
+    /// ### Errors
+    /// An error will be returned if the query failed.
+    pub async fn push_poll(
+        &mut self,
+        db: &dyn Db,
+        quotes: &[Quote]
+    ) -> Result<Vec<SpikeEvent>, Box<dyn StdError + Send + Sync + 'static>> {
+        let first: DateTime<Utc> = match quotes.iter().map(|quote| quote.ts).min() {
+            Some(first) => first,
+            None => return Ok(Vec::new())
+        };
+        self.warm_up_from(db, &QuoteFilter::default(), first).await?;
+
+        Ok(self.push_all(quotes))
+    }
+
+    /// # Compares the quote with the window of its pair, then adds it to the window.
+    ///
+    /// Returns the spike, also logged and sent to the subscribers, or `None` when the spread is within the distribution
+    /// or the window is still warming up.
+    pub fn push(
+        &mut self,
+        quote: &Quote
+    ) -> Option<SpikeEvent> {
+        let spike: Option<SpikeEvent> = self.compare(quote);
+        self.rolling.push(quote);
+
+        if let Some(spike) = &spike {
+            warn!(
+                "Spread spike of {} at {}: {:.1} pips against a mean of {:.1} over {} quote(s), z-score {}",
+                spike.symbol,
+                spike.broker,
+                spike.pips,
+                spike.baseline.mean,
+                spike.baseline.count,
+                spike.z_score.map_or_else(|| "-".to_string(), |z_score| format!("{:.1}", z_score))
+            );
+            // Without subscribers, the event is only returned
+            let _ = self.events.send(spike.clone());
+        }
+
+        spike
+    }
+
+    /// # Pushes every quote, in the order of their time, and returns the spikes, see `push`.
+    pub fn push_all(
+        &mut self,
+        quotes: &[Quote]
+    ) -> Vec<SpikeEvent> {
+        let mut sorted: Vec<&Quote> = quotes.iter().collect();
+        sorted.sort_by_key(|quote| quote.ts);
+
+        sorted.into_iter().filter_map(|quote| self.push(quote)).collect()
+    }
+
+    /// The spike of the quote against the window of its pair, before the quote is added.
+    fn compare(
+        &self,
+        quote: &Quote
+    ) -> Option<SpikeEvent> {
+        if self.rolling.count(&quote.broker, &quote.symbol) < self.min_samples.max(1) {
+            return None;
+        }
+        let baseline: SpreadStats = self.rolling.stats(&quote.broker, &quote.symbol)?;
+
+        let pips: f64 = pips(quote);
+        let deviation_pips: f64 = pips - baseline.mean;
+        let z_score: Option<f64> = Some(deviation_pips / baseline.std_dev).filter(|_| baseline.std_dev > 0.0);
+        let percentile_pips: Option<f64> = self.percentile
+            .and_then(|percent| self.rolling.percentile(&quote.broker, &quote.symbol, percent));
+
+        let mut triggers: Vec<SpikeTrigger> = Vec::new();
+        if self.z_score.zip(z_score).is_some_and(|(threshold, z_score)| z_score >= threshold) {
+            triggers.push(SpikeTrigger::ZScore);
+        }
+        if percentile_pips.is_some_and(|percentile| pips > percentile && pips >= percentile * self.percentile_factor) {
+            triggers.push(SpikeTrigger::Percentile);
+        }
+        if triggers.is_empty() {
+            return None;
+        }
+
+        Some(SpikeEvent {
+            broker: quote.broker.clone(),
+            symbol: quote.symbol.clone(),
+            ts: quote.ts,
+            pips,
+            baseline,
+            deviation_pips,
+            z_score,
+            percentile_pips,
+            triggers
+        })
+    }
+}
@@ -0,0 +1,284 @@
+//! # All-in trading cost
+//!
+//! Raw-spread brokers charge a commission on top of a tight spread, comparing spreads alone makes them look cheaper
+//! than they are. `CostModel` adds the commission schedule of every broker, from the `Commissions` section,
+//! to the spread cost of `analytics::cost`, and reports the all-in cost of a round turn in pips and in money.
+//!
+//! - `PerLot`: an amount per lot, converted from its currency.
+//! - `Percentage`: a percentage of the notional, `lots × contract size × mid`.
+//! - `Tiered`: an amount per lot picked by the monthly volume of the account at the broker, its `MonthlyVolumeLots`,
+//!   or the one of `CostModel::monthly_volume`.
+//!
+//! Per-side commissions count twice per round turn. A commission in pips is its value in the quote currency
+//! over the value of a pip, `pip size × contract size × lots`, so it adds up with the spread.
+//! A broker without a schedule charges no commission, a commission that cannot be converted leaves the all-in cost unknown.
+
+use serde_derive::Serialize;
+use std::collections::HashMap;
+
+use crate::analytics::cost::{
+    CostCalculator,
+    Mids,
+    TradeCost
+};
+use crate::config::{
+    CommissionConfig,
+    CommissionSchedule
+};
+use crate::model::{
+    BrokerSnapshot,
+    Quote,
+    pip_size,
+    symbol_currencies
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// Struct that holds the all-in cost of a round-turn trade at a broker, spread and commission.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AllInCost {
+    /// The spread cost, see `analytics::cost`.
+    #[serde(flatten)]
+    pub spread_cost: TradeCost,
+    /// The commission in pips, `0` without a schedule, `None` when it could not be converted.
+    pub commission_pips: Option<f64>,
+    /// The commission in the account currency.
+    pub commission: Option<f64>,
+    /// The spread plus the commission, in pips.
+    pub all_in_pips: Option<f64>,
+    /// The spread plus the commission, in the account currency.
+    pub all_in: Option<f64>
+}
+
+
+/// The `CostModel` struct computes the all-in cost of a round-turn trade, spread and commission, at every broker.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::analytics::commission::CostModel;
+/// use spread_tracker::config::CommissionSchedule;
+/// use spread_tracker::model::Quote;
+///
+/// let ts = "2024-05-01T12:00:00Z".parse().unwrap();
+/// let quote = |broker: &str, spread: f64| Quote {
+///     ts,
+///     broker: broker.to_string(),
+///     symbol: "EURUSD".to_string(),
+///     ask: 1.0712,
+///     bid: 1.0712 - spread,
+///     spread
+/// };
+/// let quotes = vec![quote("ic-markets", 0.00002), quote("vantage", 0.0001)];
+///
+/// // 3.5 dollars a lot per side on top of a 0.2 pip spread.
+/// let model = CostModel::new().schedule("ic-markets", CommissionSchedule::PerLot {
+///     amount: 3.5,
+///     currency: "USD".to_string(),
+///     per_side: true
+/// });
+/// let costs = model.all_in("EURUSD", 1.0, "USD", &quotes);
+///
+/// // The raw spread broker is 0.7 pips of commission more expensive than it looks, and still the cheapest.
+/// assert_eq!(costs[0].spread_cost.broker, "ic-markets");
+/// assert_eq!(costs[0].commission_pips, Some(0.7));
+/// assert_eq!(costs[0].all_in_pips, Some(0.9));
+/// assert_eq!(costs[0].all_in.map(|cost| cost.round()), Some(9.0));
+/// assert_eq!(costs[1].all_in_pips, Some(1.0));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CostModel {
+    calculator: CostCalculator,
+    schedules: HashMap<String, CommissionSchedule>,
+    monthly_volume_lots: Option<f64>
+}
+
+impl CostModel {
+    /// # Creates a `CostModel` without commissions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Creates a `CostModel` with the schedules of the `Commissions` section of the configuration.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use spread_tracker::analytics::commission::CostModel;
+    /// use spread_tracker::config::CommissionConfig;
+    /// use spread_tracker::model::Quote;
+    ///
+    /// let config: CommissionConfig = serde_yaml::from_str("
+    /// pepperstone:
+    ///   Type: Tiered
+    ///   Currency: USD
+    ///   MonthlyVolumeLots: 150
+    ///   Tiers: [{ FromLots: 0, Amount: 3 }, { FromLots: 100, Amount: 2 }]
+    /// ").unwrap();
+    /// let quote = Quote {
+    ///     ts: "2024-05-01T12:00:00Z".parse().unwrap(),
+    ///     broker: "pepperstone".to_string(),
+    ///     symbol: "EURUSD".to_string(),
+    ///     ask: 1.0712,
+    ///     bid: 1.0711,
+    ///     spread: 0.0001
+    /// };
+    ///
+    /// // 150 lots a month pay the second tier, 2 dollars a lot per side.
+    /// let model = CostModel::from_config(&config);
+    /// assert_eq!(model.commission_pips(&quote, &[quote.clone()]), Some(0.4));
+    /// assert_eq!(model.monthly_volume(20.0).commission_pips(&quote, &[quote.clone()]), Some(0.6));
+    /// ```
+    pub fn from_config(
+        config: &CommissionConfig
+    ) -> Self {
+        Self {
+            schedules: config.brokers.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Sets the commission schedule of the broker.
+    pub fn schedule(mut self, broker: &str, schedule: CommissionSchedule) -> Self {
+        self.schedules.insert(broker.to_string(), schedule);
+        self
+    }
+
+    /// Sets the monthly volume of the account in lots, which picks the tier of `Tiered` schedules, instead of their `MonthlyVolumeLots`.
+    pub fn monthly_volume(mut self, lots: f64) -> Self {
+        self.monthly_volume_lots = Some(lots);
+        self
+    }
+
+    /// Sets the calculator of the spread cost, for non-standard contract sizes.
+    pub fn calculator(mut self, calculator: CostCalculator) -> Self {
+        self.calculator = calculator;
+        self
+    }
+
+    /// # Returns the all-in cost of a round turn of `lots` of the symbol at every broker quoting it,
+    /// cheapest first, the costs that could not be converted last.
+    ///
+    /// No cost is returned when `lots` is not a positive number, see `CostCalculator::round_turn`.
+    pub fn all_in(
+        &self,
+        symbol: &str,
+        lots: f64,
+        account_currency: &str,
+        quotes: &[Quote]
+    ) -> Vec<AllInCost> {
+        let mids: Mids = Mids::from_quotes(quotes);
+
+        let mut costs: Vec<AllInCost> = self.calculator.round_turn(symbol, lots, account_currency, quotes)
+            .into_iter()
+            .map(|spread_cost| {
+                let commission_quote: Option<f64> = self.commission_in_quote(&spread_cost.broker, &spread_cost.symbol, spread_cost.lots, &mids);
+                let pip_value: f64 = pip_size(&spread_cost.symbol) * spread_cost.contract_size * spread_cost.lots;
+
+                // A pip worth nothing, like a contract size of 0, has no commission in pips
+                let commission_pips: Option<f64> = commission_quote
+                    .filter(|_| pip_value > 0.0)
+                    .map(|commission| round_pips(commission / pip_value));
+                let commission: Option<f64> = commission_quote
+                    .zip(spread_cost.conversion_rate)
+                    .map(|(commission, rate)| commission * rate);
+
+                AllInCost {
+                    commission_pips,
+                    commission,
+                    all_in_pips: commission_pips.map(|commission| round_pips(spread_cost.pips + commission)),
+                    all_in: spread_cost.cost.zip(commission).map(|(cost, commission)| cost + commission),
+                    spread_cost
+                }
+            })
+            .collect();
+
+        costs.sort_by(|a, b| match (a.all_in, b.all_in) {
+            (Some(a_cost), Some(b_cost)) => a_cost.total_cmp(&b_cost),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => a.all_in_pips.unwrap_or(f64::INFINITY).total_cmp(&b.all_in_pips.unwrap_or(f64::INFINITY))
+        }.then_with(|| a.spread_cost.broker.cmp(&b.spread_cost.broker)));
+
+        costs
+    }
+
+    /// # Returns the all-in cost at every broker of the snapshots, see `all_in`.
+    pub fn all_in_snapshots(
+        &self,
+        symbol: &str,
+        lots: f64,
+        account_currency: &str,
+        snapshots: &[BrokerSnapshot]
+    ) -> Vec<AllInCost> {
+        let quotes: Vec<Quote> = snapshots.iter().flat_map(Quote::from_snapshot).collect();
+
+        self.all_in(symbol, lots, account_currency, &quotes)
+    }
+
+    /// # Returns the round-turn commission of the broker on the quote, in pips, with the mids of `quotes`
+    /// to convert it. `None` when the broker has no schedule or the commission could not be converted.
+    pub fn commission_pips(
+        &self,
+        quote: &Quote,
+        quotes: &[Quote]
+    ) -> Option<f64> {
+        self.schedules.get(&quote.broker)?;
+
+        let symbol: String = quote.symbol.to_ascii_uppercase();
+        let commission: f64 = self.commission_in_quote(&quote.broker, &symbol, 1.0, &Mids::from_quotes(quotes))?;
+
+        Some(round_pips(commission / (pip_size(&symbol) * self.calculator.contract_size_of(&symbol))))
+    }
+
+    /// The round-turn commission of `lots` of the symbol at the broker, in the quote currency, `0` without a schedule.
+    fn commission_in_quote(
+        &self,
+        broker: &str,
+        symbol: &str,
+        lots: f64,
+        mids: &Mids
+    ) -> Option<f64> {
+        let sides = |per_side: bool| if per_side { 2.0 } else { 1.0 };
+        let per_lot = |amount: f64, currency: &str, per_side: bool| -> Option<f64> {
+            let (_, quote_currency): (&str, &str) = symbol_currencies(symbol)?;
+            let rate: f64 = mids.rate(broker, &currency.to_ascii_uppercase(), quote_currency)?;
+            Some(amount * lots * sides(per_side) * rate)
+        };
+
+        match self.schedules.get(broker) {
+            None => Some(0.0),
+            Some(CommissionSchedule::PerLot { amount, currency, per_side }) => per_lot(*amount, currency, *per_side),
+            Some(CommissionSchedule::Tiered { currency, per_side, monthly_volume_lots, tiers }) => {
+                let volume: f64 = self.monthly_volume_lots.unwrap_or(*monthly_volume_lots);
+                let tier = tiers.iter()
+                    .filter(|tier| tier.from_lots <= volume)
+                    .max_by(|a, b| a.from_lots.total_cmp(&b.from_lots));
+                match tier {
+                    Some(tier) => per_lot(tier.amount, currency, *per_side),
+                    None => {
+                        warn!("No commission tier of {} starts at {} lot(s)", broker, volume);
+                        None
+                    }
+                }
+            },
+            Some(CommissionSchedule::Percentage { percent, per_side }) => {
+                let mid: f64 = mids.mid(broker, symbol)?;
+                Some(percent / 100.0 * lots * self.calculator.contract_size_of(symbol) * mid * sides(*per_side))
+            }
+        }
+    }
+}
+
+/// Rounds to a hundredth of a pip, commissions are finer than the tenth spreads are quoted at.
+fn round_pips(
+    pips: f64
+) -> f64 {
+    (pips * 100.0).round() / 100.0
+}
+
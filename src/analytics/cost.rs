@@ -0,0 +1,312 @@
+//! # Trade cost
+//!
+//! What a round-turn trade costs in spread, in the currency of the account, at every broker.
+//!
+//! Buying at the ask and selling at the bid costs the spread once per round turn:
+//! `spread × contract size × lots`, in the quote currency of the symbol. The cost is then converted to the account
+//! currency at the mid price, `(ask + bid) / 2`, of a pair quoted by the same broker in the same snapshot:
+//!
+//! - the quote currency is the account currency, nothing to convert;
+//! - the broker quotes `<quote><account>` (`GBPUSD` for the cost of `EURGBP` in USD), the cost is multiplied by its mid;
+//! - the broker quotes `<account><quote>` (`USDJPY` for the cost of `EURJPY` in USD), the cost is divided by its mid;
+//! - otherwise through USD, with the two pairs above.
+//!
+//! When the broker does not quote a pair the conversion needs, the mean mid of the brokers quoting it is used, see `Mids`.
+//! Symbols that are not six letters, like indices, have no currency to convert from, their cost stays in quote units.
+
+use chrono::{
+    DateTime,
+    Utc
+};
+use serde_derive::Serialize;
+use std::collections::{
+    BTreeMap,
+    HashMap
+};
+
+use crate::model::{
+    BrokerSnapshot,
+    Quote,
+    contract_size,
+    symbol_currencies
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// Struct that holds the spread cost of a round-turn trade at a broker.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TradeCost {
+    pub broker: String,
+    pub symbol: String,
+    pub lots: f64,
+    pub spread: f64,
+    /// The spread in pips, see `Quote::pips`.
+    pub pips: f64,
+    /// The units of the symbol in a standard lot.
+    pub contract_size: f64,
+    /// The currency the spread is quoted in, `None` for symbols that are not a pair of currencies.
+    pub quote_currency: Option<String>,
+    /// The cost in the quote currency.
+    pub quote_cost: f64,
+    pub account_currency: String,
+    /// The price of a unit of the quote currency in the account currency, `None` when no quote allows the conversion.
+    pub conversion_rate: Option<f64>,
+    /// The cost in the account currency, `None` when it could not be converted.
+    pub cost: Option<f64>,
+    /// When the quote of the symbol was fetched.
+    pub ts: DateTime<Utc>
+}
+
+
+/// The `CostCalculator` struct computes the spread cost of a round-turn trade at every broker.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::analytics::cost::CostCalculator;
+/// use spread_tracker::model::Quote;
+///
+/// let ts = "2024-05-01T12:00:00Z".parse().unwrap();
+/// let quote = |broker: &str, symbol: &str, ask: f64, spread: f64| Quote {
+///     ts,
+///     broker: broker.to_string(),
+///     symbol: symbol.to_string(),
+///     ask,
+///     bid: ask - spread,
+///     spread
+/// };
+/// let quotes = vec![
+///     quote("fxpro", "EURJPY", 167.52, 0.02),
+///     quote("fxpro", "USDJPY", 156.01, 0.02),
+///     quote("vantage", "EURJPY", 167.51, 0.03)
+/// ];
+///
+/// // 2 pips of EURJPY on a lot is 2000 yen, about 12.82 dollars at 156 yen a dollar.
+/// let costs = CostCalculator::new().round_turn("EURJPY", 1.0, "USD", &quotes);
+/// assert_eq!(costs[0].broker, "fxpro");
+/// assert!((costs[0].quote_cost - 2000.0).abs() < 1e-6);
+/// assert_eq!((costs[0].cost.unwrap() * 100.0).round() / 100.0, 12.82);
+///
+/// // Vantage does not quote USDJPY, the conversion uses the one of FxPro.
+/// assert_eq!((costs[1].cost.unwrap() * 100.0).round() / 100.0, 19.23);
+///
+/// // A trade of no lots costs nothing to compare.
+/// assert!(CostCalculator::new().round_turn("EURJPY", 0.0, "USD", &quotes).is_empty());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CostCalculator {
+    contract_sizes: HashMap<String, f64>
+}
+
+impl CostCalculator {
+    /// # Creates a `CostCalculator` with the standard contract sizes, see `model::contract_size`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the contract size of the symbol, for brokers or instruments that do not use the standard lot.
+    pub fn contract_size(mut self, symbol: &str, units: f64) -> Self {
+        self.contract_sizes.insert(symbol.to_ascii_uppercase(), units);
+        self
+    }
+
+    /// # Returns the cost of a round turn of `lots` of the symbol at every broker quoting it, in the account currency,
+    /// cheapest first, the costs that could not be converted last.
+    ///
+    /// Only the latest quote of every broker and symbol counts. No cost is returned when `lots` is not a positive number.
+    pub fn round_turn(
+        &self,
+        symbol: &str,
+        lots: f64,
+        account_currency: &str,
+        quotes: &[Quote]
+    ) -> Vec<TradeCost> {
+        if !(lots.is_finite() && lots > 0.0) {
+            warn!("A trade of {} lot(s) has no cost, the lots must be a positive number", lots);
+            return Vec::new();
+        }
+        let symbol: String = symbol.trim().to_ascii_uppercase();
+        let account_currency: String = account_currency.trim().to_ascii_uppercase();
+        let mids: Mids = Mids::from_quotes(quotes);
+
+        let size: f64 = self.contract_size_of(&symbol);
+        let quote_currency: Option<&str> = symbol_currencies(&symbol).map(|(_, quote)| quote);
+
+        let mut costs: Vec<TradeCost> = latest_quotes(quotes, &symbol).into_iter()
+            .map(|quote| {
+                let quote_cost: f64 = quote.spread * size * lots;
+                let conversion_rate: Option<f64> = quote_currency
+                    .and_then(|currency| mids.rate(&quote.broker, currency, &account_currency));
+
+                TradeCost {
+                    broker: quote.broker.clone(),
+                    symbol: symbol.clone(),
+                    lots,
+                    spread: quote.spread,
+                    pips: quote.pips(),
+                    contract_size: size,
+                    quote_currency: quote_currency.map(str::to_string),
+                    quote_cost,
+                    account_currency: account_currency.clone(),
+                    conversion_rate,
+                    cost: conversion_rate.map(|rate| quote_cost * rate),
+                    ts: quote.ts
+                }
+            })
+            .collect();
+
+        costs.sort_by(|a, b| match (a.cost, b.cost) {
+            (Some(a_cost), Some(b_cost)) => a_cost.total_cmp(&b_cost),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => a.quote_cost.total_cmp(&b.quote_cost)
+        }.then_with(|| a.broker.cmp(&b.broker)));
+
+        costs
+    }
+
+    /// # Returns the units of the symbol in a lot, the one set with `contract_size` or the standard one.
+    pub fn contract_size_of(
+        &self,
+        symbol: &str
+    ) -> f64 {
+        self.contract_sizes.get(&symbol.to_ascii_uppercase()).copied().unwrap_or_else(|| contract_size(symbol))
+    }
+
+    /// # Returns the cost of a round turn at every broker of the snapshots, see `round_turn`.
+    pub fn round_turn_snapshots(
+        &self,
+        symbol: &str,
+        lots: f64,
+        account_currency: &str,
+        snapshots: &[BrokerSnapshot]
+    ) -> Vec<TradeCost> {
+        let quotes: Vec<Quote> = snapshots.iter().flat_map(Quote::from_snapshot).collect();
+
+        self.round_turn(symbol, lots, account_currency, &quotes)
+    }
+}
+
+
+/// The `Mids` struct holds the latest mid price of every pair quoted by every broker, to convert between currencies.
+#[derive(Debug, Clone, Default)]
+pub struct Mids {
+    brokers: HashMap<String, HashMap<String, f64>>,
+    all: HashMap<String, f64>
+}
+
+impl Mids {
+    /// # Collects the mid of the latest quote of every broker and pair.
+    ///
+    /// The mid of a pair across brokers is the mean of their mids.
+    pub fn from_quotes(
+        quotes: &[Quote]
+    ) -> Self {
+        let mut latest: BTreeMap<(&str, String), &Quote> = BTreeMap::new();
+        for quote in quotes {
+            let entry: &mut &Quote = latest.entry((quote.broker.as_str(), quote.symbol.to_ascii_uppercase())).or_insert(quote);
+            if quote.ts > entry.ts {
+                *entry = quote;
+            }
+        }
+
+        let mut brokers: HashMap<String, HashMap<String, f64>> = HashMap::new();
+        let mut sums: HashMap<String, (f64, usize)> = HashMap::new();
+        for ((broker, pair), quote) in latest {
+            let mid: f64 = (quote.ask + quote.bid) / 2.0;
+            brokers.entry(broker.to_string()).or_default().insert(pair.clone(), mid);
+
+            let sum: &mut (f64, usize) = sums.entry(pair).or_default();
+            sum.0 += mid;
+            sum.1 += 1;
+        }
+
+        Self {
+            brokers,
+            all: sums.into_iter().map(|(pair, (sum, count))| (pair, sum / count as f64)).collect()
+        }
+    }
+
+    /// # Returns the mid of the pair at the broker, or across brokers when the broker does not quote it.
+    pub fn mid(
+        &self,
+        broker: &str,
+        pair: &str
+    ) -> Option<f64> {
+        self.brokers.get(broker).and_then(|mids| mids.get(pair)).or_else(|| self.all.get(pair)).copied()
+    }
+
+    /// # Returns the price of a unit of `from` in `to` at the broker, or across brokers when the broker cannot convert.
+    pub fn rate(
+        &self,
+        broker: &str,
+        from: &str,
+        to: &str
+    ) -> Option<f64> {
+        self.brokers.get(broker)
+            .and_then(|mids| conversion_rate(from, to, mids))
+            .or_else(|| conversion_rate(from, to, &self.all))
+    }
+}
+
+
+/// # Returns the latest quote of the symbol from every broker, ordered by broker.
+pub fn latest_quotes<'a>(
+    quotes: &'a [Quote],
+    symbol: &str
+) -> Vec<&'a Quote> {
+    let mut latest: BTreeMap<&str, &Quote> = BTreeMap::new();
+    for quote in quotes.iter().filter(|quote| quote.symbol.eq_ignore_ascii_case(symbol)) {
+        let entry: &mut &Quote = latest.entry(quote.broker.as_str()).or_insert(quote);
+        if quote.ts > entry.ts {
+            *entry = quote;
+        }
+    }
+
+    latest.into_values().collect()
+}
+
+
+/// # Returns the price of a unit of `from` in `to`, from the mids of the pairs, through USD when no pair joins them.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use spread_tracker::analytics::cost::conversion_rate;
+///
+/// let mids = HashMap::from([("GBPUSD".to_string(), 1.25), ("USDJPY".to_string(), 156.0)]);
+///
+/// assert_eq!(conversion_rate("GBP", "USD", &mids), Some(1.25));
+/// assert_eq!(conversion_rate("JPY", "GBP", &mids), Some(1.0 / 156.0 / 1.25));
+/// assert_eq!(conversion_rate("CHF", "USD", &mids), None);
+/// ```
+pub fn conversion_rate(
+    from: &str,
+    to: &str,
+    mids: &HashMap<String, f64>
+) -> Option<f64> {
+    direct_rate(from, to, mids).or_else(|| match from != "USD" && to != "USD" {
+        true => Some(direct_rate(from, "USD", mids)? * direct_rate("USD", to, mids)?),
+        false => None
+    })
+}
+
+
+/// The price of a unit of `from` in `to`, from the pair joining them.
+fn direct_rate(
+    from: &str,
+    to: &str,
+    mids: &HashMap<String, f64>
+) -> Option<f64> {
+    if from == to {
+        return Some(1.0);
+    }
+
+    mids.get(&format!("{}{}", from, to)).copied()
+        .or_else(|| mids.get(&format!("{}{}", to, from)).map(|mid| 1.0 / mid))
+        .filter(|rate| rate.is_finite() && *rate > 0.0)
+}
@@ -0,0 +1,18 @@
+//! # Spread analytics
+//!
+//! Typed answers to the questions asked of the spreads, computed from `Quote`s, live or stored.
+//!
+//! ### Modules
+//! - `ranking`: Ranks the brokers of a symbol by spread in pips, with ties, missing brokers and stale quotes made explicit.
+//! - `stats`: Mean, median, percentiles and deviation of the spread of every broker and symbol over time windows.
+//! - `cost`: The spread cost of a round-turn trade at every broker, in the currency of the account.
+//! - `commission`: The all-in cost of a round-turn trade, spread and commission, from per-broker commission schedules.
+//! - `sessions`: The spread statistics by FX session and their overlaps, and the widening around the 17:00 New York rollover.
+//! - `anomaly`: Flags the spreads far outside the recent distribution of their broker and symbol, by z-score or percentile.
+
+pub mod ranking;
+pub mod stats;
+pub mod cost;
+pub mod commission;
+pub mod sessions;
+pub mod anomaly;
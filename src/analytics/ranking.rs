@@ -0,0 +1,307 @@
+//! # Best-broker ranking
+//!
+//! Answers "who is cheapest on XAUUSD right now?" without walking the `{"spread": {...}}` JSON by hand.
+//!
+//! `Ranker` ranks the brokers quoting a symbol by cost in pips: the spread, plus the commission of the broker when one
+//! is given, by hand or from the schedules of a `CostModel`, see `analytics::commission`. `RankBy::Spread` ranks
+//! by the raw spread instead. Every broker in the ranking carries the age of its quote.
+//!
+//! - Brokers with the same cost share a rank, the next rank skips: `1, 1, 3`.
+//! - Quotes older than the maximum age are flagged stale and ranked after every fresh quote.
+//! - Brokers expected in the ranking but without a quote of the symbol are listed as missing.
+
+use chrono::{
+    DateTime,
+    Duration,
+    Utc
+};
+use serde_derive::Serialize;
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+    HashMap
+};
+
+use crate::analytics::commission::CostModel;
+use crate::analytics::cost::latest_quotes;
+use crate::model::{
+    BrokerSnapshot,
+    Quote
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// Enum of what the brokers are ranked by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RankBy {
+    /// The spread alone, in pips.
+    Spread,
+    /// The spread plus the commission, in pips.
+    #[default]
+    AllIn
+}
+
+
+/// Struct that holds the place of a broker in the ranking of a symbol.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BrokerRank {
+    /// The rank, starting at 1, shared by tied brokers.
+    pub rank: usize,
+    /// Whether another broker has the same rank.
+    pub tied: bool,
+    pub broker: String,
+    pub spread: f64,
+    /// The spread in pips, see `Quote::pips`.
+    pub pips: f64,
+    /// The round-turn commission in pips, `None` when none is known for the broker.
+    pub commission_pips: Option<f64>,
+    /// The spread plus the commission, in pips, what the ranking is sorted by with `RankBy::AllIn`.
+    pub cost_pips: f64,
+    /// When the quote was fetched.
+    pub ts: DateTime<Utc>,
+    /// The age of the quote at the time of the ranking, in seconds.
+    pub age_secs: i64,
+    /// Whether the quote is older than the maximum age.
+    pub stale: bool
+}
+
+
+/// Struct that holds the brokers of a symbol, cheapest first.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SymbolRanking {
+    pub symbol: String,
+    /// The time the ranking was made at, the ages are relative to it.
+    pub as_of: DateTime<Utc>,
+    pub by: RankBy,
+    pub brokers: Vec<BrokerRank>,
+    /// The brokers expected in the ranking without a quote of the symbol.
+    pub missing: Vec<String>
+}
+
+impl SymbolRanking {
+    /// # Returns the cheapest fresh brokers, every one of them when they are tied.
+    pub fn best(&self) -> Vec<&BrokerRank> {
+        self.brokers.iter()
+            .filter(|broker| broker.rank == 1 && !broker.stale)
+            .collect()
+    }
+}
+
+
+/// The `Ranker` struct ranks the brokers of a symbol by spread in pips.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, TimeZone, Utc };
+/// use spread_tracker::analytics::ranking::Ranker;
+/// use spread_tracker::model::Quote;
+///
+/// let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+/// let quote = |broker: &str, spread: f64, age_secs: i64| Quote::sample(broker, "XAUUSD", now - Duration::seconds(age_secs), spread);
+/// let quotes = vec![
+///     quote("fxpro", 0.25, 10),
+///     quote("ic-markets", 0.12, 20),
+///     quote("pepperstone", 0.14, 5),
+///     quote("tickmill", 0.05, 3600)
+/// ];
+///
+/// // IC Markets charges 0.2 pips of commission, the ranking adds it to the spread.
+/// let ranking = Ranker::new()
+///     .max_age(Some(Duration::minutes(5)))
+///     .commission("ic-markets", 0.2)
+///     .expect_brokers(["fxpro", "ic-markets", "pepperstone", "tickmill", "vantage"])
+///     .rank("xauusd", &quotes, now);
+///
+/// let order: Vec<(usize, bool, &str)> = ranking.brokers.iter()
+///     .map(|broker| (broker.rank, broker.tied, broker.broker.as_str()))
+///     .collect();
+/// assert_eq!(order, vec![
+///     (1, true, "ic-markets"),
+///     (1, true, "pepperstone"),
+///     (3, false, "fxpro"),
+///     (4, false, "tickmill")
+/// ]);
+/// assert!(ranking.brokers[3].stale);
+/// assert_eq!(ranking.missing, vec!["vantage".to_string()]);
+/// assert_eq!(ranking.best().len(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Ranker {
+    max_age: Option<Duration>,
+    by: RankBy,
+    commissions: HashMap<String, f64>,
+    cost_model: Option<CostModel>,
+    expected: BTreeSet<String>
+}
+
+impl Default for Ranker {
+    fn default() -> Self {
+        Self {
+            max_age: Some(Duration::minutes(5)),
+            by: RankBy::AllIn,
+            commissions: HashMap::new(),
+            cost_model: None,
+            expected: BTreeSet::new()
+        }
+    }
+}
+
+impl Ranker {
+    /// # Creates a `Ranker` by all-in cost, flagging quotes older than five minutes as stale, without commissions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the age after which a quote is stale, `None` never flags a quote.
+    pub fn max_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Sets what the brokers are ranked by, the all-in cost by default.
+    pub fn rank_by(mut self, by: RankBy) -> Self {
+        self.by = by;
+        self
+    }
+
+    /// Sets the round-turn commission of the broker, in pips. It takes precedence over the cost model.
+    pub fn commission(mut self, broker: &str, pips: f64) -> Self {
+        self.commissions.insert(broker.to_string(), pips);
+        self
+    }
+
+    /// Takes the commission of every broker from the schedules of the cost model.
+    pub fn cost_model(mut self, cost_model: CostModel) -> Self {
+        self.cost_model = Some(cost_model);
+        self
+    }
+
+    /// Sets the brokers expected in every ranking, the ones without a quote of the symbol are listed as missing.
+    pub fn expect_brokers<I, S>(mut self, brokers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>
+    {
+        self.expected.extend(brokers.into_iter().map(|broker| broker.as_ref().to_string()));
+        self
+    }
+
+    /// # Ranks the brokers quoting the symbol, ignoring case, as of `now`.
+    ///
+    /// Only the latest quote of every broker counts.
+    pub fn rank(
+        &self,
+        symbol: &str,
+        quotes: &[Quote],
+        now: DateTime<Utc>
+    ) -> SymbolRanking {
+        let symbol: String = symbol.trim().to_ascii_uppercase();
+
+        let mut brokers: Vec<BrokerRank> = latest_quotes(quotes, &symbol).into_iter()
+            .map(|quote| self.broker_rank(quote, quotes, now))
+            .collect();
+        brokers.sort_by(|a, b| a.stale.cmp(&b.stale)
+            .then_with(|| self.key(a).total_cmp(&self.key(b)))
+            .then_with(|| a.broker.cmp(&b.broker)));
+
+        // Competition ranking, tied brokers share the rank of the first of them
+        let same_place = |a: &BrokerRank, b: &BrokerRank| a.stale == b.stale && self.key(a) == self.key(b);
+        for index in 0..brokers.len() {
+            let tied_with_previous: bool = index > 0 && same_place(&brokers[index - 1], &brokers[index]);
+            brokers[index].rank = match tied_with_previous {
+                true => brokers[index - 1].rank,
+                false => index + 1
+            };
+            brokers[index].tied = tied_with_previous
+                || brokers.get(index + 1).is_some_and(|next| same_place(&brokers[index], next));
+        }
+
+        let missing: Vec<String> = self.expected.iter()
+            .filter(|broker| !brokers.iter().any(|rank| &rank.broker == *broker))
+            .cloned()
+            .collect();
+
+        SymbolRanking {
+            symbol,
+            as_of: now,
+            by: self.by,
+            brokers,
+            missing
+        }
+    }
+
+    /// # Ranks the brokers of the snapshots quoting the symbol, as of `now`.
+    ///
+    /// The broker of every snapshot is expected, a broker page without the symbol lists it as missing.
+    pub fn rank_snapshots(
+        &self,
+        symbol: &str,
+        snapshots: &[BrokerSnapshot],
+        now: DateTime<Utc>
+    ) -> SymbolRanking {
+        let quotes: Vec<Quote> = snapshots.iter().flat_map(Quote::from_snapshot).collect();
+
+        self.clone()
+            .expect_brokers(snapshots.iter().map(|snapshot| &snapshot.broker))
+            .rank(symbol, &quotes, now)
+    }
+
+    /// # Ranks the brokers of every symbol of the quotes, ordered by symbol.
+    pub fn rank_all(
+        &self,
+        quotes: &[Quote],
+        now: DateTime<Utc>
+    ) -> Vec<SymbolRanking> {
+        let symbols: BTreeSet<String> = quotes.iter().map(|quote| quote.symbol.to_ascii_uppercase()).collect();
+
+        symbols.iter()
+            .map(|symbol| self.rank(symbol, quotes, now))
+            .collect()
+    }
+
+    /// What the ranking is sorted by.
+    fn key(
+        &self,
+        rank: &BrokerRank
+    ) -> f64 {
+        match self.by {
+            RankBy::Spread => rank.pips,
+            RankBy::AllIn => rank.cost_pips
+        }
+    }
+
+    /// The place of a single quote, before it is ranked. The other quotes convert the commission of the cost model.
+    fn broker_rank(
+        &self,
+        quote: &Quote,
+        quotes: &[Quote],
+        now: DateTime<Utc>
+    ) -> BrokerRank {
+        let pips: f64 = quote.pips();
+        let commission_pips: Option<f64> = self.commissions.get(&quote.broker).copied()
+            .or_else(|| self.cost_model.as_ref().and_then(|cost_model| cost_model.commission_pips(quote, quotes)));
+        let age: Duration = now - quote.ts;
+
+        BrokerRank {
+            rank: 0,
+            tied: false,
+            broker: quote.broker.clone(),
+            spread: quote.spread,
+            pips,
+            commission_pips,
+            // Rounded like the pips, so brokers a rounding error apart tie
+            cost_pips: ((pips + commission_pips.unwrap_or(0.0)) * 10.0).round() / 10.0,
+            ts: quote.ts,
+            age_secs: age.num_seconds(),
+            stale: self.max_age.is_some_and(|max_age| age > max_age)
+        }
+    }
+}
+
@@ -0,0 +1,468 @@
+//! # Trading sessions
+//!
+//! The spread of every broker and symbol by time of the trading day.
+//!
+//! `SessionCalendar` splits the spread statistics of every broker and symbol by FX session: Sydney, Tokyo, London and
+//! New York, and their overlaps. Every session opens and closes at a local time of its city, on the weekdays of its city,
+//! so the hours in UTC follow daylight saving time: London opens at 07:00 UTC in summer and 08:00 UTC in winter.
+//!
+//! - A quote counts in every session open at its time, and in the overlap of those sessions, like `london+new_york`.
+//! - A quote outside every session, in the gaps between them, counts in `closed`.
+//!
+//! `RolloverDetector` finds the widening of the spread around the daily rollover, at 17:00 New York time, when liquidity
+//! providers close their books: the spread around the rollover is compared with its median over a baseline before it.
+
+use chrono::{
+    DateTime,
+    Datelike,
+    Duration,
+    NaiveDate,
+    NaiveTime,
+    TimeZone,
+    Utc,
+    Weekday
+};
+use chrono_tz::Tz;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+
+use crate::analytics::stats::{
+    SpreadStats,
+    pips
+};
+use crate::config::RolloverConfig;
+use crate::model::Quote;
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// Struct that holds a trading session, open on the weekdays of its time zone between two local times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradingSession {
+    /// The name of the session, like `london`.
+    pub name: String,
+    pub tz: Tz,
+    /// The local time the session opens at, included.
+    pub open: NaiveTime,
+    /// The local time the session closes at, excluded. A close before the open closes on the next day.
+    pub close: NaiveTime
+}
+
+impl TradingSession {
+    /// # Creates a `TradingSession`.
+    pub fn new(
+        name: &str,
+        tz: Tz,
+        open: NaiveTime,
+        close: NaiveTime
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            tz,
+            open,
+            close
+        }
+    }
+
+    /// # Returns whether the session is open at the time, in the local time of the session.
+    pub fn is_open(
+        &self,
+        ts: DateTime<Utc>
+    ) -> bool {
+        let local: DateTime<Tz> = ts.with_timezone(&self.tz);
+        let time: NaiveTime = local.time();
+
+        // A session over midnight belongs to the day it opened on
+        let (day, open): (Weekday, bool) = match self.open <= self.close {
+            true => (local.weekday(), time >= self.open && time < self.close),
+            false if time >= self.open => (local.weekday(), true),
+            false => (local.weekday().pred(), time < self.close)
+        };
+
+        open && !matches!(day, Weekday::Sat | Weekday::Sun)
+    }
+}
+
+
+/// Struct that holds the statistics of the spread of a broker and symbol during a session, or an overlap of sessions.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionStats {
+    /// The name of the session, the names of the overlapping sessions joined by `+`, or `closed`.
+    pub session: String,
+    #[serde(flatten)]
+    pub stats: SpreadStats
+}
+
+
+/// The `SessionCalendar` struct tells the sessions open at a time, and splits the spread statistics by session.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ TimeZone, Utc };
+/// use spread_tracker::analytics::sessions::SessionCalendar;
+///
+/// let calendar = SessionCalendar::new();
+///
+/// // 07:30 UTC is 08:30 in London in summer, London is open with Tokyo. In winter, it is 07:30 and London is not open yet.
+/// assert_eq!(calendar.label(Utc.with_ymd_and_hms(2024, 7, 1, 7, 30, 0).unwrap()), "tokyo+london");
+/// assert_eq!(calendar.label(Utc.with_ymd_and_hms(2024, 1, 8, 7, 30, 0).unwrap()), "tokyo");
+///
+/// assert_eq!(calendar.label(Utc.with_ymd_and_hms(2024, 7, 1, 13, 0, 0).unwrap()), "london+new_york");
+/// assert_eq!(calendar.label(Utc.with_ymd_and_hms(2024, 7, 6, 13, 0, 0).unwrap()), "closed");
+/// ```
+#[derive(Debug, Clone)]
+pub struct SessionCalendar {
+    sessions: Vec<TradingSession>
+}
+
+impl Default for SessionCalendar {
+    fn default() -> Self {
+        let time = |hour: u32| NaiveTime::from_hms_opt(hour, 0, 0).unwrap();
+
+        Self {
+            sessions: vec![
+                TradingSession::new("sydney", chrono_tz::Australia::Sydney, time(7), time(16)),
+                TradingSession::new("tokyo", chrono_tz::Asia::Tokyo, time(9), time(18)),
+                TradingSession::new("london", chrono_tz::Europe::London, time(8), time(17)),
+                TradingSession::new("new_york", chrono_tz::America::New_York, time(8), time(17))
+            ]
+        }
+    }
+}
+
+impl SessionCalendar {
+    /// # Creates a `SessionCalendar` with the Sydney, Tokyo, London and New York sessions, in that order.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Creates a `SessionCalendar` with the given sessions. Overlaps are named in their order.
+    pub fn with_sessions(
+        sessions: Vec<TradingSession>
+    ) -> Self {
+        Self {
+            sessions
+        }
+    }
+
+    /// Returns the sessions of the calendar.
+    pub fn sessions(&self) -> &[TradingSession] {
+        &self.sessions
+    }
+
+    /// # Returns the sessions open at the time.
+    pub fn open_at(
+        &self,
+        ts: DateTime<Utc>
+    ) -> Vec<&TradingSession> {
+        self.sessions.iter().filter(|session| session.is_open(ts)).collect()
+    }
+
+    /// # Returns the name of the session open at the time, the names of the overlapping sessions joined by `+`,
+    /// or `closed`.
+    pub fn label(
+        &self,
+        ts: DateTime<Utc>
+    ) -> String {
+        self.name(&self.open_indices(ts))
+    }
+
+    /// # Computes the statistics of every broker and symbol by session, over the quotes from `from` excluded to `to` included,
+    /// see `SpreadStats`.
+    ///
+    /// Ordered by broker, then symbol, then session: `closed` first, then every session followed by its overlap with the next.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use chrono::{ Duration, TimeZone, Utc };
+    /// use spread_tracker::analytics::sessions::SessionCalendar;
+    /// use spread_tracker::model::Quote;
+    ///
+    /// let day = Utc.with_ymd_and_hms(2024, 7, 1, 0, 0, 0).unwrap();
+    /// let quotes: Vec<Quote> = (0..24)
+    ///     .map(|hour| Quote::sample("fxpro", "EURUSD", day + Duration::hours(hour), 0.0001))
+    ///     .collect();
+    ///
+    /// let stats = SessionCalendar::new().session_stats(&quotes, day, day + Duration::days(1));
+    /// let london = stats.iter().find(|stats| stats.session == "london").unwrap();
+    /// let overlap = stats.iter().find(|stats| stats.session == "london+new_york").unwrap();
+    ///
+    /// // London is open from 07:00 to 16:00 UTC in summer, New York from 12:00.
+    /// assert_eq!(london.stats.count, 9);
+    /// assert_eq!(overlap.stats.count, 4);
+    /// ```
+    pub fn session_stats(
+        &self,
+        quotes: &[Quote],
+        from: DateTime<Utc>,
+        to: DateTime<Utc>
+    ) -> Vec<SessionStats> {
+        let mut samples: BTreeMap<(&str, &str, Vec<usize>), Vec<f64>> = BTreeMap::new();
+        for quote in quotes.iter().filter(|quote| quote.ts > from && quote.ts <= to) {
+            let open: Vec<usize> = self.open_indices(quote.ts);
+
+            let mut keys: Vec<Vec<usize>> = open.iter().map(|index| vec![*index]).collect();
+            if open.len() != 1 {
+                keys.push(open);
+            }
+            for key in keys {
+                samples.entry((&quote.broker, &quote.symbol, key)).or_default().push(pips(quote));
+            }
+        }
+
+        samples.into_iter()
+            .filter_map(|((broker, symbol, key), samples)| Some(SessionStats {
+                session: self.name(&key),
+                stats: SpreadStats::from_samples(broker, symbol, from, to, &samples)?
+            }))
+            .collect()
+    }
+
+    /// The indices of the sessions open at the time.
+    fn open_indices(
+        &self,
+        ts: DateTime<Utc>
+    ) -> Vec<usize> {
+        (0..self.sessions.len()).filter(|index| self.sessions[*index].is_open(ts)).collect()
+    }
+
+    /// The name of the sessions at the indices.
+    fn name(
+        &self,
+        indices: &[usize]
+    ) -> String {
+        match indices.is_empty() {
+            true => "closed".to_string(),
+            false => indices.iter().map(|index| self.sessions[*index].name.as_str()).collect::<Vec<&str>>().join("+")
+        }
+    }
+}
+
+
+/// Struct that holds the spread of a broker and symbol around a rollover, compared with its baseline.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RolloverWidening {
+    pub broker: String,
+    pub symbol: String,
+    /// The time of the rollover, 17:00 New York time.
+    pub rollover: DateTime<Utc>,
+    /// The median spread over the baseline before the rollover window, in pips.
+    pub baseline_pips: f64,
+    /// The widest spread in the rollover window, in pips.
+    pub peak_pips: f64,
+    /// When the widest spread was quoted.
+    pub peak_at: DateTime<Utc>,
+    /// The peak over the baseline, `None` with a baseline of zero.
+    pub ratio: Option<f64>,
+    /// Whether the peak reached the minimum ratio of the baseline.
+    pub widened: bool,
+    /// The first quote of the window at the minimum ratio of the baseline or more.
+    pub from: Option<DateTime<Utc>>,
+    /// The last quote of the window at the minimum ratio of the baseline or more.
+    pub to: Option<DateTime<Utc>>,
+    /// The number of quotes in the rollover window.
+    pub count: usize
+}
+
+
+/// The `RolloverDetector` struct compares the spread around every 17:00 New York rollover with the spread before it.
+///
+/// The rollover window runs from `before` the rollover to `after` it, the baseline is the `baseline` before the window.
+/// Pairs without quotes in the baseline or in the window around a rollover are skipped.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, TimeZone, Utc };
+/// use spread_tracker::analytics::sessions::RolloverDetector;
+/// use spread_tracker::model::Quote;
+///
+/// // 17:00 in New York is 21:00 UTC in summer, 22:00 UTC in winter.
+/// let summer = Utc.with_ymd_and_hms(2024, 7, 1, 21, 0, 0).unwrap();
+/// let winter = Utc.with_ymd_and_hms(2024, 1, 8, 22, 0, 0).unwrap();
+/// assert_eq!(RolloverDetector::rollovers(summer - Duration::hours(1), summer + Duration::hours(1)), vec![summer]);
+/// assert_eq!(RolloverDetector::rollovers(winter - Duration::hours(1), winter + Duration::hours(1)), vec![winter]);
+///
+/// // A quote every 5 minutes at 1 pip, 5 pips for the ten minutes after the rollover.
+/// let quotes: Vec<Quote> = (-36..24)
+///     .map(|step| {
+///         let ts = summer + Duration::minutes(5 * step);
+///         let pips = if (0..=2).contains(&step) { 5.0 } else { 1.0 };
+///         Quote::sample("fxpro", "EURUSD", ts, pips * 0.0001)
+///     })
+///     .collect();
+///
+/// let widenings = RolloverDetector::new().detect(&quotes);
+/// assert_eq!(widenings.len(), 1);
+/// assert!(widenings[0].widened);
+/// assert!((widenings[0].baseline_pips - 1.0).abs() < 1e-9);
+/// assert!((widenings[0].peak_pips - 5.0).abs() < 1e-9);
+/// assert_eq!(widenings[0].from, Some(summer));
+/// assert_eq!(widenings[0].to, Some(summer + Duration::minutes(10)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RolloverDetector {
+    before: Duration,
+    after: Duration,
+    baseline: Duration,
+    min_ratio: f64
+}
+
+impl Default for RolloverDetector {
+    fn default() -> Self {
+        Self {
+            before: Duration::minutes(15),
+            after: Duration::hours(1),
+            baseline: Duration::hours(2),
+            min_ratio: 2.0
+        }
+    }
+}
+
+impl RolloverDetector {
+    /// # Creates a `RolloverDetector` over the 15 minutes before to the hour after the rollover, with a baseline of two hours,
+    /// flagging a spread twice the baseline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Creates a `RolloverDetector` with the windows of the `Rollover` section of the configuration.
+    ///
+    /// ### Errors
+    /// An error will be returned if a window is not a number followed by a unit.
+    pub fn from_config(
+        config: &RolloverConfig
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let (before, after, baseline): (Duration, Duration, Duration) = config.windows()?;
+
+        Ok(Self {
+            before,
+            after,
+            baseline,
+            min_ratio: config.min_ratio
+        })
+    }
+
+    /// Sets how long before the rollover the window starts.
+    pub fn before(mut self, before: Duration) -> Self {
+        self.before = before;
+        self
+    }
+
+    /// Sets how long after the rollover the window ends.
+    pub fn after(mut self, after: Duration) -> Self {
+        self.after = after;
+        self
+    }
+
+    /// Sets the length of the baseline before the window.
+    pub fn baseline(mut self, baseline: Duration) -> Self {
+        self.baseline = baseline;
+        self
+    }
+
+    /// Sets the ratio of the baseline from which the spread is widened.
+    pub fn min_ratio(mut self, min_ratio: f64) -> Self {
+        self.min_ratio = min_ratio;
+        self
+    }
+
+    /// # Returns the rollovers from `from` to `to`, both included: 17:00 New York time, Monday to Friday.
+    pub fn rollovers(
+        from: DateTime<Utc>,
+        to: DateTime<Utc>
+    ) -> Vec<DateTime<Utc>> {
+        let new_york: Tz = chrono_tz::America::New_York;
+        let rollover_time: NaiveTime = NaiveTime::from_hms_opt(17, 0, 0).unwrap();
+        let first: NaiveDate = from.with_timezone(&new_york).date_naive();
+        let last: NaiveDate = to.with_timezone(&new_york).date_naive();
+
+        first.iter_days()
+            .take_while(|day| *day <= last)
+            .filter(|day| !matches!(day.weekday(), Weekday::Sat | Weekday::Sun))
+            // 17:00 is never skipped nor repeated by a change of time in New York
+            .filter_map(|day| new_york.from_local_datetime(&day.and_time(rollover_time)).single())
+            .map(|rollover| rollover.with_timezone(&Utc))
+            .filter(|rollover| *rollover >= from && *rollover <= to)
+            .collect()
+    }
+
+    /// # Compares the spread around every rollover covered by the quotes with its baseline, for every broker and symbol.
+    ///
+    /// Ordered by broker, then symbol, then rollover.
+    pub fn detect(
+        &self,
+        quotes: &[Quote]
+    ) -> Vec<RolloverWidening> {
+        let mut pairs: BTreeMap<(&str, &str), Vec<&Quote>> = BTreeMap::new();
+        for quote in quotes {
+            pairs.entry((&quote.broker, &quote.symbol)).or_default().push(quote);
+        }
+
+        let mut widenings: Vec<RolloverWidening> = Vec::new();
+        for ((broker, symbol), mut quotes) in pairs {
+            quotes.sort_by_key(|quote| quote.ts);
+            let (first, last): (DateTime<Utc>, DateTime<Utc>) = (quotes[0].ts, quotes[quotes.len() - 1].ts);
+
+            for rollover in Self::rollovers(first, last + self.before) {
+                if let Some(widening) = self.compare(broker, symbol, rollover, &quotes) {
+                    widenings.push(widening);
+                }
+            }
+        }
+
+        widenings
+    }
+
+    /// The spread of the sorted quotes of a pair around the rollover, `None` without quotes in the baseline or the window.
+    fn compare(
+        &self,
+        broker: &str,
+        symbol: &str,
+        rollover: DateTime<Utc>,
+        quotes: &[&Quote]
+    ) -> Option<RolloverWidening> {
+        let start: DateTime<Utc> = rollover - self.before;
+        let end: DateTime<Utc> = rollover + self.after;
+
+        let baseline: Vec<f64> = quotes.iter()
+            .filter(|quote| quote.ts >= start - self.baseline && quote.ts < start)
+            .map(|quote| pips(quote))
+            .collect();
+        let baseline_pips: f64 = SpreadStats::from_samples(broker, symbol, start - self.baseline, start, &baseline)?.median;
+
+        let window: Vec<(DateTime<Utc>, f64)> = quotes.iter()
+            .filter(|quote| quote.ts >= start && quote.ts < end)
+            .map(|quote| (quote.ts, pips(quote)))
+            .collect();
+        let (peak_at, peak_pips): (DateTime<Utc>, f64) = window.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1))?;
+
+        let threshold: f64 = baseline_pips * self.min_ratio;
+        let widened: bool = peak_pips >= threshold && peak_pips > baseline_pips;
+        let above: Vec<DateTime<Utc>> = match widened {
+            true => window.iter().filter(|(_, pips)| *pips >= threshold).map(|(ts, _)| *ts).collect(),
+            false => Vec::new()
+        };
+
+        Some(RolloverWidening {
+            broker: broker.to_string(),
+            symbol: symbol.to_string(),
+            rollover,
+            baseline_pips,
+            peak_pips,
+            peak_at,
+            ratio: Some(peak_pips / baseline_pips).filter(|_| baseline_pips > 0.0),
+            widened,
+            from: above.first().copied(),
+            to: above.last().copied(),
+            count: window.len()
+        })
+    }
+}
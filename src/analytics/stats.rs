@@ -0,0 +1,333 @@
+//! # Spread statistics
+//!
+//! Descriptive statistics of the spread of every broker and symbol over a time window, in pips.
+//! `SpreadStats` holds the mean, median, 5th and 95th percentiles, standard deviation, minimum, maximum and sample count
+//! of the spread of a broker and symbol over a window, which runs from its `from` excluded to its `to` included.
+//!
+//! - `window_stats` computes them over the quotes of a window, `stored_stats` over the stored quotes of a `Db`.
+//! - `RollingStats` keeps them up to date over streamed quotes, for the last window of every pair.
+//!
+//! The pips are not rounded like `Quote::pips`, so the percentiles keep the precision of the quotes.
+//! Percentiles interpolate linearly between the closest samples, the standard deviation is the population one.
+//! The stored statistics read raw quotes only, windows past the raw retention of `db::retention` cover what is left.
+
+use chrono::{
+    DateTime,
+    Duration,
+    Utc
+};
+use serde_derive::Serialize;
+use std::collections::{
+    BTreeMap,
+    HashMap,
+    VecDeque
+};
+use std::error::Error as StdError;
+
+use crate::db::storage::{
+    Db,
+    QuoteFilter
+};
+use crate::model::{
+    Quote,
+    pip_size
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The timestamped pips of a pair, oldest first.
+type Samples = VecDeque<(DateTime<Utc>, f64)>;
+
+
+/// Struct that holds the statistics of the spread of a broker and symbol over a window, in pips.
+///
+/// The window holds the quotes newer than `from` up to `to`, a quote at `to` counts and a quote at `from` does not,
+/// the same for `window_stats`, `stored_stats`, `RollingStats` and the session statistics of `analytics::sessions`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpreadStats {
+    pub broker: String,
+    pub symbol: String,
+    /// The start of the window, excluded.
+    pub from: DateTime<Utc>,
+    /// The end of the window, included.
+    pub to: DateTime<Utc>,
+    pub count: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub p5: f64,
+    pub p95: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64
+}
+
+impl SpreadStats {
+    /// # Computes the statistics of the samples, in any order. Returns `None` without samples.
+    pub fn from_samples(
+        broker: &str,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        samples: &[f64]
+    ) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<f64> = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+
+        let count: usize = sorted.len();
+        let mean: f64 = sorted.iter().sum::<f64>() / count as f64;
+        let variance: f64 = sorted.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>() / count as f64;
+
+        Some(Self {
+            broker: broker.to_string(),
+            symbol: symbol.to_string(),
+            from,
+            to,
+            count,
+            mean,
+            median: percentile(&sorted, 50.0),
+            p5: percentile(&sorted, 5.0),
+            p95: percentile(&sorted, 95.0),
+            std_dev: variance.sqrt(),
+            min: sorted[0],
+            max: sorted[count - 1]
+        })
+    }
+}
+
+
+/// # Computes the statistics of every broker and symbol over the window ending at `to`, from its start excluded to `to` included,
+/// ordered by broker, then symbol.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, TimeZone, Utc };
+/// use spread_tracker::analytics::stats::window_stats;
+/// use spread_tracker::model::Quote;
+///
+/// let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+/// // 1 to 10 pips, twice
+/// let quotes: Vec<Quote> = (1..=20)
+///     .map(|minute| Quote::sample("fxpro", "EURUSD", now - Duration::minutes(minute), (minute % 10 + 1) as f64 * 0.0001))
+///     .collect();
+///
+/// let stats = window_stats(&quotes, now, Duration::hours(1));
+/// assert_eq!(stats.len(), 1);
+/// assert_eq!(stats[0].count, 20);
+/// assert!((stats[0].mean - 5.5).abs() < 1e-9);
+/// assert!((stats[0].median - 5.5).abs() < 1e-9);
+/// assert!((stats[0].p95 - 10.0).abs() < 1e-9);
+/// assert!((stats[0].min - 1.0).abs() < 1e-9);
+///
+/// // The last five minutes only, the quote at the start of the window is excluded.
+/// assert_eq!(window_stats(&quotes, now, Duration::minutes(5))[0].count, 4);
+/// ```
+pub fn window_stats(
+    quotes: &[Quote],
+    to: DateTime<Utc>,
+    window: Duration
+) -> Vec<SpreadStats> {
+    let from: DateTime<Utc> = to - window;
+
+    let mut samples: BTreeMap<(&str, &str), Vec<f64>> = BTreeMap::new();
+    for quote in quotes.iter().filter(|quote| quote.ts > from && quote.ts <= to) {
+        samples.entry((&quote.broker, &quote.symbol)).or_default().push(pips(quote));
+    }
+
+    samples.into_iter()
+        .filter_map(|((broker, symbol), samples)| SpreadStats::from_samples(broker, symbol, from, to, &samples))
+        .collect()
+}
+
+
+/// # Computes the statistics of the stored quotes matching the filter over the window ending at `to`, see `window_stats`.
+///
+/// The time range of the filter is replaced by the window.
+///
+/// ### Errors
+/// An error will be returned if the query failed.
+pub async fn stored_stats(
+    db: &dyn Db,
+    filter: &QuoteFilter,
+    to: DateTime<Utc>,
+    window: Duration
+) -> Result<Vec<SpreadStats>, Box<dyn StdError + Send + Sync + 'static>> {
+    // The end of a filter is excluded, the quotes are stored to the microsecond
+    let filter: QuoteFilter = QuoteFilter {
+        from: Some(to - window),
+        to: Some(to + Duration::microseconds(1)),
+        limit: None,
+        ..filter.clone()
+    };
+    let quotes: Vec<Quote> = db.query_quotes(&filter).await?;
+
+    Ok(window_stats(&quotes, to, window))
+}
+
+
+/// The `RollingStats` struct keeps the last window of the spread of every broker and symbol,
+/// over quotes pushed as they come.
+///
+/// The window of a pair ends at its latest quote, included, and holds the quotes newer than the window before it.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, TimeZone, Utc };
+/// use spread_tracker::analytics::stats::RollingStats;
+/// use spread_tracker::model::Quote;
+///
+/// let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+/// let mut rolling = RollingStats::new(Duration::minutes(10));
+///
+/// for minute in 0..30 {
+///     rolling.push(&Quote::sample("fxpro", "XAUUSD", start + Duration::minutes(minute), 0.1 * minute as f64));
+/// }
+///
+/// // Minutes 20 to 29, 20 to 29 pips.
+/// let stats = rolling.stats("fxpro", "XAUUSD").unwrap();
+/// assert_eq!(stats.count, 10);
+/// assert!((stats.min - 20.0).abs() < 1e-9);
+/// assert!((stats.max - 29.0).abs() < 1e-9);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RollingStats {
+    window: Duration,
+    samples: HashMap<(String, String), Samples>
+}
+
+impl RollingStats {
+    /// # Creates `RollingStats` over windows of the given length.
+    pub fn new(
+        window: Duration
+    ) -> Self {
+        Self {
+            window,
+            samples: HashMap::new()
+        }
+    }
+
+    /// Returns the length of the windows.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// # Adds a quote to the window of its pair, and drops the quotes that fell out of it.
+    ///
+    /// A quote older than the window of its pair is ignored.
+    pub fn push(
+        &mut self,
+        quote: &Quote
+    ) {
+        let samples: &mut Samples = self.samples
+            .entry((quote.broker.clone(), quote.symbol.clone()))
+            .or_default();
+
+        // Quotes come in order most of the time, a late one is inserted at its place
+        let latest: DateTime<Utc> = samples.back().map_or(quote.ts, |(ts, _)| quote.ts.max(*ts));
+        if quote.ts <= latest - self.window {
+            return;
+        }
+        let position: usize = samples.partition_point(|(ts, _)| *ts <= quote.ts);
+        samples.insert(position, (quote.ts, pips(quote)));
+
+        while samples.front().is_some_and(|(ts, _)| *ts <= latest - self.window) {
+            samples.pop_front();
+        }
+    }
+
+    /// # Adds every quote, see `push`.
+    pub fn push_all(
+        &mut self,
+        quotes: &[Quote]
+    ) {
+        for quote in quotes {
+            self.push(quote);
+        }
+    }
+
+    /// # Returns the number of quotes in the window of the pair.
+    pub fn count(
+        &self,
+        broker: &str,
+        symbol: &str
+    ) -> usize {
+        self.samples.get(&(broker.to_string(), symbol.to_string())).map_or(0, VecDeque::len)
+    }
+
+    /// # Returns the statistics of the window of the pair, `None` before its first quote.
+    pub fn stats(
+        &self,
+        broker: &str,
+        symbol: &str
+    ) -> Option<SpreadStats> {
+        let samples: &Samples = self.samples.get(&(broker.to_string(), symbol.to_string()))?;
+        self.window_of(broker, symbol, samples)
+    }
+
+    /// # Returns a percentile of the window of the pair, from 0 to 100, `None` before its first quote.
+    pub fn percentile(
+        &self,
+        broker: &str,
+        symbol: &str,
+        percent: f64
+    ) -> Option<f64> {
+        let samples: &Samples = self.samples.get(&(broker.to_string(), symbol.to_string()))?;
+        let mut sorted: Vec<f64> = samples.iter().map(|(_, pips)| *pips).collect();
+        sorted.sort_by(f64::total_cmp);
+
+        (!sorted.is_empty()).then(|| percentile(&sorted, percent.clamp(0.0, 100.0)))
+    }
+
+    /// # Returns the statistics of the window of every pair, ordered by broker, then symbol.
+    pub fn all(&self) -> Vec<SpreadStats> {
+        let mut stats: Vec<SpreadStats> = self.samples.iter()
+            .filter_map(|((broker, symbol), samples)| self.window_of(broker, symbol, samples))
+            .collect();
+        stats.sort_by(|a, b| (&a.broker, &a.symbol).cmp(&(&b.broker, &b.symbol)));
+
+        stats
+    }
+
+    /// The statistics of the samples of a pair, over the window ending at its latest quote.
+    fn window_of(
+        &self,
+        broker: &str,
+        symbol: &str,
+        samples: &Samples
+    ) -> Option<SpreadStats> {
+        let (to, _): &(DateTime<Utc>, f64) = samples.back()?;
+        let values: Vec<f64> = samples.iter().map(|(_, pips)| *pips).collect();
+
+        SpreadStats::from_samples(broker, symbol, *to - self.window, *to, &values)
+    }
+}
+
+
+/// The spread of the quote in pips, not rounded.
+pub(crate) fn pips(
+    quote: &Quote
+) -> f64 {
+    quote.spread / pip_size(&quote.symbol)
+}
+
+
+/// The percentile of sorted samples, interpolated linearly between the closest ranks.
+fn percentile(
+    sorted: &[f64],
+    percent: f64
+) -> f64 {
+    let rank: f64 = percent / 100.0 * (sorted.len() - 1) as f64;
+    let (lower, upper): (usize, usize) = (rank.floor() as usize, rank.ceil() as usize);
+
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
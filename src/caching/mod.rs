@@ -1 +1,11 @@
-//! # Caching spread intelligently
\ No newline at end of file
+//! # Caching spread intelligently
+//!
+//! Every broker page is a few thousand lines of HTML, and myfxbook does not like being polled.
+//! This module keeps the parsed quotes of each broker, so repeated calls to `get_spread` are served without a request.
+//!
+//! ### Modules
+//! - `snapshot_cache`: A TTL cache of broker snapshots, with stale-while-revalidate and coalesced fetches.
+//! - `store`: Where the cache keeps its entries, in memory or on disk across restarts.
+
+pub mod snapshot_cache;
+pub mod store;
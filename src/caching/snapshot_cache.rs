@@ -0,0 +1,341 @@
+//! # Broker snapshot cache
+//!
+//! A cache of the parsed quotes of each broker, keyed by broker name.
+//! Entries are kept in memory by default, or on disk with a `DiskStore`, see `caching::store`.
+//!
+//! - A snapshot younger than the TTL is served as is.
+//! - A snapshot past the TTL, but within the stale-while-revalidate window, is served right away
+//!   while a refresh runs in the background.
+//! - Anything older, or missing, is fetched before returning.
+//!
+//! Concurrent requests for the same broker are coalesced, only one of them fetches the page
+//! and the others are served the snapshot it stored.
+
+use chrono::Utc;
+use serde_derive::Serialize;
+use std::collections::{
+    HashMap,
+    HashSet
+};
+use std::error::Error as StdError;
+use std::future::Future;
+use std::sync::{
+    Arc,
+    Mutex,
+    OnceLock
+};
+use std::time::Duration;
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::caching::store::{ CacheStore, DiskStore, MemoryStore };
+use crate::config::{ CacheBackend, CacheConfig };
+use crate::model::BrokerSnapshot;
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The cache shared by every `SpreadTracker` built with `SpreadTracker::new`.
+static GLOBAL_SNAPSHOT_CACHE: OnceLock<Arc<SnapshotCache>> = OnceLock::new();
+
+
+/// The `CacheStatus` enum tells where the snapshot returned by the cache came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStatus {
+    /// Served from the cache, within the TTL.
+    Fresh,
+    /// Served from the cache past the TTL, a refresh runs in the background.
+    Stale,
+    /// Fetched from the broker before returning.
+    Miss
+}
+
+
+/// Struct that holds a snapshot returned by the cache, and where it came from.
+#[derive(Debug, Clone)]
+pub struct CacheLookup {
+    pub snapshot: BrokerSnapshot,
+    pub status: CacheStatus
+}
+
+
+/// The `SnapshotCache` struct keeps the last snapshot of every broker.
+///
+/// ### Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::sync::atomic::{ AtomicUsize, Ordering };
+/// use std::time::Duration;
+/// use spread_tracker::caching::snapshot_cache::{ CacheStatus, SnapshotCache };
+/// use spread_tracker::model::BrokerSnapshot;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let cache = Arc::new(SnapshotCache::new(Duration::from_secs(60)));
+/// let fetches = Arc::new(AtomicUsize::new(0));
+///
+/// let fetch = |fetches: Arc<AtomicUsize>| async move {
+///     fetches.fetch_add(1, Ordering::SeqCst);
+///     tokio::time::sleep(Duration::from_millis(50)).await;
+///     Ok(BrokerSnapshot {
+///         broker: "fxpro".to_string(),
+///         url: "https://www.myfxbook.com/forex-broker-quotes/fxpro/5168".to_string(),
+///         quotes: serde_json::json!([]),
+///         fetched_at: chrono::Utc::now()
+///     })
+/// };
+///
+/// // Two concurrent requests for the same broker result in a single fetch.
+/// let (first, second) = tokio::join!(
+///     cache.get_or_fetch("fxpro", { let fetches = fetches.clone(); move || fetch(fetches) }),
+///     cache.get_or_fetch("fxpro", { let fetches = fetches.clone(); move || fetch(fetches) })
+/// );
+/// assert_eq!(fetches.load(Ordering::SeqCst), 1);
+/// assert_eq!(first.unwrap().snapshot, second.unwrap().snapshot);
+///
+/// // Within the TTL, the snapshot is served without a fetch.
+/// let lookup = cache.get_or_fetch("fxpro", { let fetches = fetches.clone(); move || fetch(fetches) }).await.unwrap();
+/// assert_eq!(lookup.status, CacheStatus::Fresh);
+/// assert_eq!(fetches.load(Ordering::SeqCst), 1);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SnapshotCache {
+    ttl: Duration,
+    stale_while_revalidate: Duration,
+    store: Arc<dyn CacheStore>,
+    locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+    refreshing: Mutex<HashSet<String>>
+}
+
+impl SnapshotCache {
+    /// # Creates an in-memory `SnapshotCache` with the given TTL, and no stale-while-revalidate window.
+    pub fn new(
+        ttl: Duration
+    ) -> Self {
+        Self::with_store(ttl, Arc::new(MemoryStore::new()))
+    }
+
+    /// # Creates a `SnapshotCache` with the given TTL, that keeps its entries in the given store.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use spread_tracker::caching::snapshot_cache::SnapshotCache;
+    /// use spread_tracker::caching::store::DiskStore;
+    ///
+    /// let cache = SnapshotCache::with_store(Duration::from_secs(60), Arc::new(DiskStore::new("cache")));
+    /// ```
+    pub fn with_store(
+        ttl: Duration,
+        store: Arc<dyn CacheStore>
+    ) -> Self {
+        Self {
+            ttl,
+            stale_while_revalidate: Duration::ZERO,
+            store,
+            locks: Mutex::new(HashMap::new()),
+            refreshing: Mutex::new(HashSet::new())
+        }
+    }
+
+    /// # Creates a `SnapshotCache` from the `Cache` section of the configuration.
+    pub fn from_config(
+        config: &CacheConfig
+    ) -> Self {
+        let store: Arc<dyn CacheStore> = match config.backend {
+            CacheBackend::Memory => Arc::new(MemoryStore::new()),
+            CacheBackend::Disk => {
+                info!("Caching broker snapshots on disk in {}", config.dir);
+                Arc::new(DiskStore::new(&config.dir))
+            }
+        };
+
+        Self::with_store(Duration::from_secs(config.ttl_secs), store)
+            .stale_while_revalidate(Duration::from_secs(config.stale_while_revalidate_secs))
+    }
+
+    /// # Returns the process-wide cache, built from the `Cache` section of the configuration on first use.
+    pub fn global() -> Arc<SnapshotCache> {
+        GLOBAL_SNAPSHOT_CACHE.get_or_init(|| {
+            let config: CacheConfig = CacheConfig::load().unwrap_or_else(|error| {
+                error!("Failed to read the Cache section, using the default cache: {}", error);
+                CacheConfig::default()
+            });
+
+            Arc::new(SnapshotCache::from_config(&config))
+        }).clone()
+    }
+
+    /// Sets how long past the TTL a snapshot is still served, while it is refreshed in the background.
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.stale_while_revalidate = window;
+        self
+    }
+
+    /// Returns the TTL of the cache.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Returns the store that keeps the entries of the cache.
+    pub fn store(&self) -> &dyn CacheStore {
+        self.store.as_ref()
+    }
+
+    /// # Returns the snapshot of the broker, whatever its age.
+    ///
+    /// A store that fails to read is logged and treated as empty, the broker is then fetched again.
+    pub fn get(
+        &self,
+        key: &str
+    ) -> Option<BrokerSnapshot> {
+        self.store.load(key).unwrap_or_else(|error| {
+            warn!("Failed to read the cached snapshot of {}: {}", key, error);
+            None
+        })
+    }
+
+    /// # Stores the snapshot of the broker, replacing the previous one.
+    ///
+    /// A store that fails to write is logged, the snapshot is still returned to the caller.
+    pub fn insert(
+        &self,
+        key: &str,
+        snapshot: BrokerSnapshot
+    ) {
+        if let Err(error) = self.store.save(key, &snapshot) {
+            error!("Failed to cache the snapshot of {}: {}", key, error);
+        }
+    }
+
+    /// # Removes the snapshot of the broker, the next lookup fetches it again.
+    pub fn invalidate(
+        &self,
+        key: &str
+    ) {
+        if let Err(error) = self.store.remove(key) {
+            error!("Failed to remove the cached snapshot of {}: {}", key, error);
+        }
+    }
+
+    /// # Returns the snapshot of the broker, fetching it when it is missing or too old.
+    ///
+    /// `fetch` is only called when the cache cannot answer, and at most once at a time per broker.
+    /// Callers that arrive while a fetch is running wait for it, and are served the snapshot it stored.
+    ///
+    /// ### Errors
+    /// The error of `fetch` will be returned if the broker had to be fetched and the fetch failed.
+    /// A failed background refresh is only logged, the stale snapshot stays in the cache.
+    pub async fn get_or_fetch<F, Fut>(
+        self: &Arc<Self>,
+        key: &str,
+        fetch: F
+    ) -> Result<CacheLookup, Box<dyn StdError + Send + Sync + 'static>>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<BrokerSnapshot, Box<dyn StdError + Send + Sync + 'static>>> + Send + 'static
+    {
+        if let Some(snapshot) = self.get(key) {
+            match self.status_of(&snapshot) {
+                Some(CacheStatus::Fresh) => {
+                    return Ok(CacheLookup { snapshot, status: CacheStatus::Fresh });
+                },
+                Some(CacheStatus::Stale) => {
+                    self.spawn_refresh(key, fetch);
+                    return Ok(CacheLookup { snapshot, status: CacheStatus::Stale });
+                },
+                _ => {}
+            }
+        }
+
+        let lock: Arc<AsyncMutex<()>> = self.lock_for(key);
+        let _guard = lock.lock().await;
+
+        // Another caller may have stored the snapshot while this one was waiting
+        if let Some(snapshot) = self.get(key) {
+            if self.status_of(&snapshot) == Some(CacheStatus::Fresh) {
+                return Ok(CacheLookup { snapshot, status: CacheStatus::Fresh });
+            }
+        }
+
+        let snapshot: BrokerSnapshot = fetch().await?;
+        self.insert(key, snapshot.clone());
+        info!("Cached the snapshot of {}", key);
+
+        Ok(CacheLookup { snapshot, status: CacheStatus::Miss })
+    }
+
+    /// Returns `Fresh` within the TTL, `Stale` within the stale-while-revalidate window, `None` past it.
+    fn status_of(
+        &self,
+        snapshot: &BrokerSnapshot
+    ) -> Option<CacheStatus> {
+        // A snapshot from the future, after a clock change, counts as just fetched
+        let age: Duration = (Utc::now() - snapshot.fetched_at).to_std().unwrap_or(Duration::ZERO);
+
+        if age < self.ttl {
+            return Some(CacheStatus::Fresh);
+        }
+        if age < self.ttl + self.stale_while_revalidate {
+            return Some(CacheStatus::Stale);
+        }
+
+        None
+    }
+
+    fn lock_for(
+        &self,
+        key: &str
+    ) -> Arc<AsyncMutex<()>> {
+        let mut locks = self.locks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        locks.entry(key.to_string()).or_default().clone()
+    }
+
+    /// Refreshes the snapshot of the broker in the background, unless a refresh is already running.
+    fn spawn_refresh<F, Fut>(
+        self: &Arc<Self>,
+        key: &str,
+        fetch: F
+    )
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<BrokerSnapshot, Box<dyn StdError + Send + Sync + 'static>>> + Send + 'static
+    {
+        {
+            let mut refreshing = self.refreshing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if !refreshing.insert(key.to_string()) {
+                return;
+            }
+        }
+
+        let cache: Arc<SnapshotCache> = Arc::clone(self);
+        let key: String = key.to_string();
+        tokio::spawn(async move {
+            let lock: Arc<AsyncMutex<()>> = cache.lock_for(&key);
+            let guard = lock.lock().await;
+
+            let fresh: bool = cache.get(&key).is_some_and(|snapshot| cache.status_of(&snapshot) == Some(CacheStatus::Fresh));
+            if !fresh {
+                match fetch().await {
+                    Ok(snapshot) => {
+                        cache.insert(&key, snapshot);
+                        info!("Refreshed the snapshot of {} in the background", key);
+                    },
+                    Err(error) => warn!("Failed to refresh the snapshot of {}, keeping the stale one: {}", key, error)
+                }
+            }
+
+            drop(guard);
+            let mut refreshing = cache.refreshing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            refreshing.remove(&key);
+        });
+    }
+}
@@ -0,0 +1,224 @@
+//! # Snapshot cache backends
+//!
+//! Where the snapshot cache keeps its entries. `MemoryStore` lives as long as the process,
+//! `DiskStore` writes one JSON file per broker, so snapshots and their fetch time survive restarts.
+//!
+//! `DiskStore` writes every snapshot to a temporary file and renames it over the previous one.
+//! A rename within one directory is atomic, so other processes sharing the directory
+//! read either the previous snapshot or the new one, never a partial file.
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt::Debug;
+use std::fs::{
+    self,
+    File
+};
+use std::io::{
+    BufReader,
+    BufWriter,
+    ErrorKind,
+    Write
+};
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::sync::Mutex;
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering
+};
+
+use crate::model::BrokerSnapshot;
+use crate::utils::archive::sanitize_broker;
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The extension of every snapshot written by `DiskStore`.
+const SNAPSHOT_EXTENSION: &str = ".json";
+
+/// Tells apart the temporary files of concurrent writers within one process.
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+
+/// The `CacheStore` trait is implemented by every backend of the snapshot cache.
+pub trait CacheStore: Debug + Send + Sync {
+    /// # Returns the snapshot stored under the key, `None` when there is none.
+    fn load(
+        &self,
+        key: &str
+    ) -> Result<Option<BrokerSnapshot>, Box<dyn StdError + Send + Sync + 'static>>;
+
+    /// # Stores the snapshot under the key, replacing the previous one.
+    fn save(
+        &self,
+        key: &str,
+        snapshot: &BrokerSnapshot
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>>;
+
+    /// # Removes the snapshot stored under the key, if any.
+    fn remove(
+        &self,
+        key: &str
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>>;
+}
+
+
+/// The `MemoryStore` struct keeps the snapshots in the memory of the process.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, BrokerSnapshot>>
+}
+
+impl MemoryStore {
+    /// # Creates an empty `MemoryStore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn load(
+        &self,
+        key: &str
+    ) -> Result<Option<BrokerSnapshot>, Box<dyn StdError + Send + Sync + 'static>> {
+        let entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(entries.get(key).cloned())
+    }
+
+    fn save(
+        &self,
+        key: &str,
+        snapshot: &BrokerSnapshot
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        entries.insert(key.to_string(), snapshot.clone());
+        Ok(())
+    }
+
+    fn remove(
+        &self,
+        key: &str
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        entries.remove(key);
+        Ok(())
+    }
+}
+
+
+/// The `DiskStore` struct keeps the snapshots as `<dir>/<broker>.json` files.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::caching::store::{ CacheStore, DiskStore };
+/// use spread_tracker::model::BrokerSnapshot;
+///
+/// let dir = std::env::temp_dir().join(format!("spread_cache_doc_{}", std::process::id()));
+/// let snapshot = BrokerSnapshot {
+///     broker: "fxpro".to_string(),
+///     url: "https://www.myfxbook.com/forex-broker-quotes/fxpro/5168".to_string(),
+///     quotes: serde_json::json!([{ "symbol": "EURUSD", "ask": 1.0712, "bid": 1.0714, "spread": 0.2 }]),
+///     fetched_at: chrono::Utc::now()
+/// };
+///
+/// DiskStore::new(&dir).save("fxpro", &snapshot).unwrap();
+///
+/// // Another store on the same directory, like the next run of a CLI, reads the snapshot back.
+/// let loaded = DiskStore::new(&dir).load("fxpro").unwrap();
+/// assert_eq!(loaded, Some(snapshot));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct DiskStore {
+    dir: PathBuf
+}
+
+impl DiskStore {
+    /// # Creates a `DiskStore` in the given directory, it is created on the first write.
+    pub fn new(
+        dir: impl AsRef<Path>
+    ) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf()
+        }
+    }
+
+    /// Returns the directory of the store.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path_for(
+        &self,
+        key: &str
+    ) -> PathBuf {
+        self.dir.join(format!("{}{}", sanitize_broker(key), SNAPSHOT_EXTENSION))
+    }
+}
+
+impl CacheStore for DiskStore {
+    fn load(
+        &self,
+        key: &str
+    ) -> Result<Option<BrokerSnapshot>, Box<dyn StdError + Send + Sync + 'static>> {
+        let file: File = match File::open(self.path_for(key)) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(Box::new(error))
+        };
+
+        let snapshot: BrokerSnapshot = serde_json::from_reader(BufReader::new(file))?;
+        Ok(Some(snapshot))
+    }
+
+    fn save(
+        &self,
+        key: &str,
+        snapshot: &BrokerSnapshot
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        fs::create_dir_all(&self.dir)?;
+
+        // The temporary file is unique per process and per write, so concurrent writers never share one
+        let path: PathBuf = self.path_for(key);
+        let temp_path: PathBuf = self.dir.join(format!(
+            ".{}.{}.{}.tmp",
+            sanitize_broker(key),
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let written: Result<(), Box<dyn StdError + Send + Sync + 'static>> = (|| {
+            let mut writer: BufWriter<File> = BufWriter::new(File::create(&temp_path)?);
+            serde_json::to_writer(&mut writer, snapshot)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            fs::rename(&temp_path, &path)?;
+            Ok(())
+        })();
+
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+
+        written
+    }
+
+    fn remove(
+        &self,
+        key: &str
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        match fs::remove_file(self.path_for(key)) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(Box::new(error))
+        }
+    }
+}
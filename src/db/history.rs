@@ -0,0 +1,325 @@
+//! # Historical queries
+//!
+//! Reads the stored quotes back, from whichever backend `open_storage` opened.
+//!
+//! - `symbol_spreads`: the quotes of a symbol across every broker between two times, grouped by broker.
+//! - `snapshot_at`: the latest quote of every broker / symbol pair as of a time.
+//! - `series`: the spread of a symbol bucketed at any interval, min, max, mean, last and count per bucket.
+//!
+//! Series also read the rollups of `db::retention`, so they reach past the raw retention.
+//! They use the coarsest resolution the interval is a multiple of, a series is as fine as the history that was kept.
+
+use chrono::{
+    DateTime,
+    Duration,
+    DurationRound,
+    Utc
+};
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::sync::Arc;
+
+use crate::config::StorageConfig;
+use crate::db::storage::{
+    Db,
+    QuoteFilter,
+    open_storage
+};
+use crate::model::{
+    Quote,
+    Resolution,
+    SpreadAggregate
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// Struct that holds the spread of a broker / symbol pair over a bucket of a series.
+///
+/// `bucket` is the start of the bucket, buckets are aligned on the Unix epoch in UTC.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SeriesPoint {
+    pub bucket: DateTime<Utc>,
+    pub broker: String,
+    pub symbol: String,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub last: f64,
+    pub count: u64
+}
+
+
+/// The `History` struct runs the historical queries on a `Db`.
+///
+/// ### Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use chrono::{ Duration, TimeZone, Utc };
+/// use spread_tracker::db::history::History;
+/// use spread_tracker::db::memory::MemoryDb;
+/// use spread_tracker::db::storage::Db;
+/// use spread_tracker::model::Quote;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let db = Arc::new(MemoryDb::new());
+/// let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+/// let quote = |broker: &str, minutes: i64, spread: f64| Quote::sample(broker, "EURUSD", start + Duration::minutes(minutes), spread);
+/// db.save_quotes(&[quote("fxpro", 0, 0.2), quote("fxpro", 10, 0.4), quote("vantage", 5, 0.1)]).await.unwrap();
+///
+/// let history = History::new(db);
+///
+/// let spreads = history.symbol_spreads("EURUSD", start, start + Duration::hours(1)).await.unwrap();
+/// assert_eq!(spreads["fxpro"].len(), 2);
+///
+/// let snapshot = history.snapshot_at(start + Duration::minutes(5), None).await.unwrap();
+/// assert_eq!(snapshot.iter().map(|quote| quote.spread).collect::<Vec<_>>(), vec![0.2, 0.1]);
+///
+/// let series = history.series("EURUSD", Some("fxpro"), start, start + Duration::hours(1), Duration::minutes(15)).await.unwrap();
+/// assert!((series[0].mean - 0.3).abs() < 1e-9);
+/// assert_eq!((series[0].last, series[0].count), (0.4, 2));
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct History {
+    db: Arc<dyn Db>
+}
+
+impl History {
+    /// # Creates a `History` over the database.
+    pub fn new(
+        db: Arc<dyn Db>
+    ) -> Self {
+        Self {
+            db
+        }
+    }
+
+    /// # Opens the storage backend of the `Storage` section, see `open_storage`.
+    ///
+    /// ### Errors
+    /// The errors of `open_storage`.
+    pub async fn open(
+        config: &StorageConfig
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        Ok(Self::new(open_storage(config).await?))
+    }
+
+    /// Returns the database the queries run on.
+    pub fn db(&self) -> &Arc<dyn Db> {
+        &self.db
+    }
+
+    /// # Returns the quotes of the symbol from every broker, from `from` inclusive to `to` exclusive, keyed by broker.
+    ///
+    /// ### Errors
+    /// An error will be returned if the query failed.
+    pub async fn symbol_spreads(
+        &self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>
+    ) -> Result<BTreeMap<String, Vec<Quote>>, Box<dyn StdError + Send + Sync + 'static>> {
+        let quotes: Vec<Quote> = self.db.query_quotes(&QuoteFilter::new().symbol(symbol).from(from).to(to)).await?;
+
+        let mut brokers: BTreeMap<String, Vec<Quote>> = BTreeMap::new();
+        for quote in quotes {
+            brokers.entry(quote.broker.clone()).or_default().push(quote);
+        }
+
+        Ok(brokers)
+    }
+
+    /// # Returns the latest quote of every broker / symbol pair at or before `at`, optionally of a single broker.
+    ///
+    /// ### Errors
+    /// An error will be returned if the query failed.
+    pub async fn snapshot_at(
+        &self,
+        at: DateTime<Utc>,
+        broker: Option<&str>
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        // `to` is exclusive, a quote fetched at `at` belongs to the snapshot
+        let mut filter: QuoteFilter = QuoteFilter::new().to(at + Duration::microseconds(1));
+        if let Some(broker) = broker {
+            filter = filter.broker(broker);
+        }
+
+        self.db.latest_quotes(&filter).await
+    }
+
+    /// # Returns the spread of the symbol in buckets of `interval`, from `from` inclusive to `to` exclusive,
+    /// ordered by bucket, then broker.
+    ///
+    /// The raw quotes are bucketed as they are. Older history comes from the aggregates of the coarsest resolution
+    /// `interval` is a multiple of, an interval under a minute only reads raw quotes.
+    ///
+    /// ### Errors
+    /// An error will be returned if the interval is not positive, or a query failed.
+    pub async fn series(
+        &self,
+        symbol: &str,
+        broker: Option<&str>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        interval: Duration
+    ) -> Result<Vec<SeriesPoint>, Box<dyn StdError + Send + Sync + 'static>> {
+        if interval <= Duration::zero() {
+            return Err(format!("The interval must be positive, got {}", interval).into());
+        }
+
+        let mut filter: QuoteFilter = QuoteFilter::new().symbol(symbol).from(from).to(to);
+        if let Some(broker) = broker {
+            filter = filter.broker(broker);
+        }
+
+        let mut buckets: BTreeMap<(DateTime<Utc>, String), Bucket> = BTreeMap::new();
+        let mut add = |ts: DateTime<Utc>, piece: Bucket| -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+            let bucket: DateTime<Utc> = ts.duration_trunc(interval)?;
+            match buckets.get_mut(&(bucket, piece.broker.clone())) {
+                Some(current) => current.merge(piece),
+                None => {
+                    buckets.insert((bucket, piece.broker.clone()), piece);
+                }
+            }
+            Ok(())
+        };
+
+        // The rolled up history and the raw quotes never overlap, the rollup deletes the quotes it read
+        let resolution: Option<Resolution> = Resolution::ALL.into_iter()
+            .rev()
+            .find(|resolution| interval.num_microseconds().zip(resolution.duration().num_microseconds())
+                .is_some_and(|(interval, resolution)| interval % resolution == 0));
+        if let Some(resolution) = resolution {
+            for aggregate in self.db.query_aggregates(resolution, &filter).await? {
+                add(aggregate.bucket, Bucket::from_aggregate(&aggregate))?;
+            }
+        }
+        for quote in self.db.query_quotes(&filter).await? {
+            add(quote.ts, Bucket::from_quote(&quote))?;
+        }
+
+        Ok(
+            buckets.into_iter()
+                .map(|((bucket, _), piece)| piece.into_point(bucket, symbol))
+                .collect()
+        )
+    }
+}
+
+
+/// # Parses an interval like `30s`, `15m`, `4h`, `1d` or `1w`.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::Duration;
+/// use spread_tracker::db::history::parse_interval;
+///
+/// assert_eq!(parse_interval("15m").unwrap(), Duration::minutes(15));
+/// assert!(parse_interval("15").is_err());
+/// ```
+///
+/// ### Errors
+/// An error will be returned if the amount is not a positive number or the unit is unknown.
+pub fn parse_interval(
+    interval: &str
+) -> Result<Duration, String> {
+    let interval: &str = interval.trim();
+    let split: usize = interval.find(|c: char| !c.is_ascii_digit()).unwrap_or(interval.len());
+    let (amount, unit): (&str, &str) = interval.split_at(split);
+
+    let amount: i64 = amount.parse::<i64>().ok()
+        .filter(|amount| *amount > 0)
+        .ok_or_else(|| format!("{:?} does not start with a positive number", interval))?;
+
+    match unit {
+        "s" => Ok(Duration::seconds(amount)),
+        "m" => Ok(Duration::minutes(amount)),
+        "h" => Ok(Duration::hours(amount)),
+        "d" => Ok(Duration::days(amount)),
+        "w" => Ok(Duration::weeks(amount)),
+        _ => Err(format!("{:?} has no unit, use s, m, h, d or w", interval))
+    }
+}
+
+
+/// The running statistics of a bucket, the sum stands in for the mean until the bucket is complete.
+#[derive(Debug)]
+struct Bucket {
+    broker: String,
+    min: f64,
+    max: f64,
+    sum: f64,
+    last: f64,
+    last_ts: DateTime<Utc>,
+    count: u64
+}
+
+impl Bucket {
+    fn from_quote(
+        quote: &Quote
+    ) -> Self {
+        Self {
+            broker: quote.broker.clone(),
+            min: quote.spread,
+            max: quote.spread,
+            sum: quote.spread,
+            last: quote.spread,
+            last_ts: quote.ts,
+            count: 1
+        }
+    }
+
+    fn from_aggregate(
+        aggregate: &SpreadAggregate
+    ) -> Self {
+        Self {
+            broker: aggregate.broker.clone(),
+            min: aggregate.min,
+            max: aggregate.max,
+            sum: aggregate.mean * aggregate.count as f64,
+            last: aggregate.last,
+            last_ts: aggregate.bucket,
+            count: aggregate.count
+        }
+    }
+
+    fn merge(
+        &mut self,
+        other: Bucket
+    ) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+        if other.last_ts >= self.last_ts {
+            self.last = other.last;
+            self.last_ts = other.last_ts;
+        }
+    }
+
+    fn into_point(
+        self,
+        bucket: DateTime<Utc>,
+        symbol: &str
+    ) -> SeriesPoint {
+        SeriesPoint {
+            bucket,
+            broker: self.broker,
+            symbol: symbol.to_string(),
+            min: self.min,
+            max: self.max,
+            mean: self.sum / self.count.max(1) as f64,
+            last: self.last,
+            count: self.count
+        }
+    }
+}
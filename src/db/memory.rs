@@ -0,0 +1,176 @@
+//! # In-memory storage
+//!
+//! A `Db` that keeps every quote and aggregate in the memory of the process, for tests and short-lived jobs.
+
+use async_trait::async_trait;
+use chrono::{
+    DateTime,
+    Utc
+};
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::sync::Mutex;
+
+use crate::db::storage::{
+    Db,
+    QuoteFilter,
+    latest_per_pair
+};
+use crate::model::{
+    Quote,
+    Resolution,
+    SpreadAggregate
+};
+
+
+/// The key of a quote, ordered the way `query_quotes` returns them.
+type QuoteKey = (DateTime<Utc>, String, String);
+
+/// The key of an aggregate, ordered the way `query_aggregates` returns them.
+type AggregateKey = (Resolution, DateTime<Utc>, String, String);
+
+
+/// The `MemoryDb` struct stores quotes in memory.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, Utc };
+/// use spread_tracker::db::memory::MemoryDb;
+/// use spread_tracker::db::storage::{ Db, QuoteFilter };
+/// use spread_tracker::model::Quote;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let db = MemoryDb::new();
+/// let now = Utc::now();
+/// let quote = |minutes: i64, spread: f64| Quote::sample("fxpro", "EURUSD", now - Duration::minutes(minutes), spread);
+///
+/// db.save_quotes(&[quote(10, 0.3), quote(5, 0.2), quote(0, 0.1)]).await.unwrap();
+/// // Saving a quote again replaces it, it is not duplicated.
+/// db.save_quotes(&[quote(0, 0.1)]).await.unwrap();
+///
+/// let recent = db.query_quotes(&QuoteFilter::new().symbol("EURUSD").from(now - Duration::minutes(6))).await.unwrap();
+/// assert_eq!(recent.len(), 2);
+///
+/// // The latest quote as it was six minutes ago.
+/// let latest = db.latest_quotes(&QuoteFilter::new().to(now - Duration::minutes(6))).await.unwrap();
+/// assert_eq!(latest[0].spread, 0.3);
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryDb {
+    quotes: Mutex<BTreeMap<QuoteKey, Quote>>,
+    aggregates: Mutex<BTreeMap<AggregateKey, SpreadAggregate>>
+}
+
+impl MemoryDb {
+    /// # Creates an empty `MemoryDb`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of quotes stored.
+    pub fn len(&self) -> usize {
+        self.quotes.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len()
+    }
+
+    /// Returns `true` when no quote is stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl Db for MemoryDb {
+    async fn save_quotes(
+        &self,
+        quotes: &[Quote]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut stored = self.quotes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for quote in quotes {
+            stored.insert((quote.ts, quote.broker.clone(), quote.symbol.clone()), quote.clone());
+        }
+
+        Ok(quotes.len())
+    }
+
+    async fn query_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        let stored = self.quotes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        Ok(
+            stored.values()
+                .filter(|quote| filter.matches(quote))
+                .take(filter.limit.unwrap_or(usize::MAX))
+                .cloned()
+                .collect()
+        )
+    }
+
+    async fn latest_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        let matching: Vec<Quote> = {
+            let stored = self.quotes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            stored.values().filter(|quote| filter.matches(quote)).cloned().collect()
+        };
+
+        Ok(latest_per_pair(matching))
+    }
+
+    async fn delete_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut stored = self.quotes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let before: usize = stored.len();
+        stored.retain(|_, quote| !filter.matches(quote));
+
+        Ok(before - stored.len())
+    }
+
+    async fn save_aggregates(
+        &self,
+        aggregates: &[SpreadAggregate]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut stored = self.aggregates.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for aggregate in aggregates {
+            let key: AggregateKey = (aggregate.resolution, aggregate.bucket, aggregate.broker.clone(), aggregate.symbol.clone());
+            stored.insert(key, aggregate.clone());
+        }
+
+        Ok(aggregates.len())
+    }
+
+    async fn query_aggregates(
+        &self,
+        resolution: Resolution,
+        filter: &QuoteFilter
+    ) -> Result<Vec<SpreadAggregate>, Box<dyn StdError + Send + Sync + 'static>> {
+        let stored = self.aggregates.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        Ok(
+            stored.values()
+                .filter(|aggregate| aggregate.resolution == resolution && filter.matches_aggregate(aggregate))
+                .take(filter.limit.unwrap_or(usize::MAX))
+                .cloned()
+                .collect()
+        )
+    }
+
+    async fn delete_aggregates(
+        &self,
+        resolution: Resolution,
+        filter: &QuoteFilter
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut stored = self.aggregates.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let before: usize = stored.len();
+        stored.retain(|_, aggregate| aggregate.resolution != resolution || !filter.matches_aggregate(aggregate));
+
+        Ok(before - stored.len())
+    }
+}
@@ -2,8 +2,41 @@
 //!
 //! This module contains the implementation of the Supabase SDK (Which i also maintain) so if you want to use it, you can use this module as a reference.
 //!
+//! Every backend implements the `Db` trait of `db::storage`, so the store can be swapped without touching the tracker code.
+//!
+//! ### Modules
+//! - `storage`: The `Db` trait and the `QuoteFilter` used to query quotes.
+//! - `memory`: An in-memory `Db`, for tests.
+//! - `sqlite`: A `Db` in a single SQLite file, with versioned migrations.
+//! - `supabase`: A `Db` backed by a Supabase / PostgREST table.
+//! - `postgres`: A `Db` in a PostgreSQL table, a TimescaleDB hypertable when available.
+//! - `history`: Historical queries over the stored quotes, spreads across brokers, snapshots as of a time and series.
+//! - `retention`: Rolls old quotes up into per-minute, hourly and daily aggregates, and purges what is past its retention.
+//!
 //! ### Example
 //!
+//! ```no_run
+//! use spread_tracker::SpreadTracker;
+//! use spread_tracker::config::{ SpreadBrokerUrl, SupabaseConfig };
+//! use spread_tracker::db::storage::Db;
+//! use spread_tracker::db::supabase::SupabaseStore;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let store = SupabaseStore::from_config(&SupabaseConfig::load().unwrap()).unwrap();
+//!
+//! let tracker = SpreadTracker::new(SpreadBrokerUrl::new()).unwrap();
+//! let snapshot = tracker.snapshot(spread_tracker::config::Brokers::FxPro).await.unwrap();
+//!
+//! store.save_snapshot(&snapshot).await.unwrap();
+//! # }
+//! ```
 
+pub mod storage;
+pub mod memory;
+pub mod sqlite;
 pub mod supabase;
-pub mod utils;
\ No newline at end of file
+pub mod postgres;
+pub mod retention;
+pub mod history;
+pub mod utils;
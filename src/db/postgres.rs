@@ -0,0 +1,602 @@
+//! # PostgreSQL / TimescaleDB storage
+//!
+//! A `Db` in a PostgreSQL table, for spread history that outgrows a single SQLite file.
+//!
+//! - With `Timescale` and the `timescaledb` extension available, the table is a hypertable chunked on `ts`.
+//! - Otherwise the table is natively partitioned by month on `ts`, the partition of a month is created
+//!   before the first quote of that month is written. A default partition catches everything else.
+//! - Quotes are written with a binary `COPY` into a staging table, then upserted into the table in one statement,
+//!   so a batch costs a single round trip of rows whatever its size.
+//! - The rollups of `db::retention` live in the plain `<table>_aggregates` table, keyed by `(resolution, broker, symbol, bucket)`.
+//!
+//! The columns are plain `timestamptz` / `text` / `double precision`, which is what TimescaleDB continuous aggregates
+//! expect. Hourly spreads per pair, for example:
+//!
+//! ```sql
+//! create materialized view spread_quotes_hourly
+//! with (timescaledb.continuous) as
+//!     select time_bucket('1 hour', ts) as bucket, broker, symbol,
+//!            avg(spread) as avg_spread, min(spread) as min_spread, max(spread) as max_spread, count(*) as samples
+//!     from spread_quotes
+//!     group by bucket, broker, symbol;
+//! ```
+
+use async_trait::async_trait;
+use chrono::{
+    DateTime,
+    Datelike,
+    NaiveDate,
+    Utc
+};
+use std::collections::HashSet;
+use std::error::Error as StdError;
+use std::sync::Mutex;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{
+    ToSql,
+    Type
+};
+use tokio_postgres::{
+    Client,
+    NoTls,
+    Row
+};
+
+use crate::config::PostgresConfig;
+use crate::db::storage::{
+    Db,
+    QuoteFilter,
+    dedupe_quotes
+};
+use crate::errors::ErrorsSpread;
+use crate::model::{
+    Quote,
+    Resolution,
+    SpreadAggregate
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The `Partitioning` enum tells how the quotes table of a `PostgresDb` is split over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partitioning {
+    /// A TimescaleDB hypertable, chunked by TimescaleDB itself.
+    Hypertable,
+    /// Native PostgreSQL partitions, one per month.
+    Monthly,
+    /// A plain table, created before this crate managed it.
+    None
+}
+
+
+/// The `PostgresDb` struct stores quotes in a PostgreSQL table.
+///
+/// ### Example
+///
+/// The example only runs against a server when `SPREAD_TRACKER_POSTGRES_URL` is set.
+///
+/// ```
+/// use spread_tracker::db::postgres::PostgresDb;
+/// use spread_tracker::db::storage::{ Db, QuoteFilter };
+/// use spread_tracker::model::{ BrokerSnapshot, Quote };
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let Ok(url) = std::env::var("SPREAD_TRACKER_POSTGRES_URL") else { return };
+/// let table = format!("spread_quotes_doc_{}", std::process::id());
+/// let db = PostgresDb::connect(&url, &table, true).await.unwrap();
+///
+/// let snapshot = BrokerSnapshot {
+///     broker: "fxpro".to_string(),
+///     url: "https://www.myfxbook.com/forex-broker-quotes/fxpro/5168".to_string(),
+///     quotes: serde_json::json!([
+///         { "symbol": "EURUSD", "ask": 1.0712, "bid": 1.0714, "spread": 0.2 },
+///         { "symbol": "GBPUSD", "ask": 1.2501, "bid": 1.2504, "spread": 0.3 }
+///     ]),
+///     fetched_at: chrono::Utc::now()
+/// };
+///
+/// // Saving the same snapshot twice keeps one row per quote.
+/// db.save_snapshot(&snapshot).await.unwrap();
+/// db.save_snapshot(&snapshot).await.unwrap();
+///
+/// let latest = db.latest_quotes(&QuoteFilter::new().broker("fxpro")).await.unwrap();
+/// assert_eq!(latest.len(), 2);
+/// assert_eq!(latest[0], Quote::from_snapshot(&snapshot)[0]);
+/// # db.drop_table().await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PostgresDb {
+    client: tokio::sync::Mutex<Client>,
+    table: String,
+    partitioning: Partitioning,
+    partitions: Mutex<HashSet<String>>
+}
+
+impl PostgresDb {
+    /// # Connects to the database and creates the quotes table when needed.
+    ///
+    /// With `timescale`, the table becomes a hypertable when the `timescaledb` extension is available,
+    /// otherwise it is partitioned by month.
+    ///
+    /// ### Errors
+    /// An error will be returned if the table name is not a plain lowercase identifier,
+    /// the connection failed or the table could not be created.
+    pub async fn connect(
+        url: &str,
+        table: &str,
+        timescale: bool
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        if !is_identifier(table) {
+            return Err(format!("{:?} is not a valid table name, use lowercase letters, digits and underscores", table).into());
+        }
+
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                error!("The PostgreSQL connection failed: {}", err);
+            }
+        });
+
+        let partitioning: Partitioning = create_schema(&client, table, timescale).await?;
+        info!("Storing quotes in PostgreSQL table {} ({:?})", table, partitioning);
+
+        Ok(Self {
+            client: tokio::sync::Mutex::new(client),
+            table: table.to_string(),
+            partitioning,
+            partitions: Mutex::new(HashSet::new())
+        })
+    }
+
+    /// # Connects with the `Postgres` section of the configuration.
+    ///
+    /// An empty `Url` is read from the `DATABASE_URL` environment variable.
+    ///
+    /// ### Errors
+    /// `missing_database_credentials` will be returned if the URL is missing from both,
+    /// otherwise the errors of `connect`.
+    pub async fn from_config(
+        config: &PostgresConfig
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let url: String = match config.url.is_empty() {
+            true => std::env::var("DATABASE_URL").ok().filter(|value| !value.is_empty()),
+            false => Some(config.url.clone())
+        }.ok_or(ErrorsSpread::MissingDatabaseCredentials)?;
+
+        Self::connect(&url, &config.table, config.timescale).await
+    }
+
+    /// Returns the table the quotes are written to.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns how the table is split over time.
+    pub fn partitioning(&self) -> Partitioning {
+        self.partitioning
+    }
+
+    /// Returns the table the aggregates are written to.
+    pub fn aggregates_table(&self) -> String {
+        format!("{}_aggregates", self.table)
+    }
+
+    /// # Drops the quotes table and its partitions, for tests.
+    pub async fn drop_table(&self) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        let client = self.client.lock().await;
+        client.batch_execute(&format!("DROP TABLE IF EXISTS {}, {} CASCADE", self.table, self.aggregates_table())).await?;
+        self.partitions.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
+
+        Ok(())
+    }
+
+    /// Creates the monthly partitions the quotes fall in, the ones not created by this process yet.
+    async fn ensure_partitions(
+        &self,
+        client: &Client,
+        quotes: &[&Quote]
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        if self.partitioning != Partitioning::Monthly {
+            return Ok(());
+        }
+
+        let months: HashSet<NaiveDate> = quotes.iter()
+            .filter_map(|quote| NaiveDate::from_ymd_opt(quote.ts.year(), quote.ts.month(), 1))
+            .collect();
+
+        for month in months {
+            let partition: String = format!("{}_y{}m{:02}", self.table, month.year(), month.month());
+            let known: bool = self.partitions.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).contains(&partition);
+            if known {
+                continue;
+            }
+
+            let next: NaiveDate = month.checked_add_months(chrono::Months::new(1)).ok_or("The quote is too far in the future")?;
+            client.batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {} PARTITION OF {} FOR VALUES FROM ('{} 00:00:00+00') TO ('{} 00:00:00+00')",
+                partition, self.table, month, next
+            )).await?;
+
+            self.partitions.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(partition);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Db for PostgresDb {
+    /// # Upserts the quotes with a binary `COPY` into a staging table, and returns how many rows were written.
+    ///
+    /// A quote repeated within the call is written once, the last one wins.
+    /// The call is a single transaction, either every quote is written or none is.
+    async fn save_quotes(
+        &self,
+        quotes: &[Quote]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let rows: Vec<&Quote> = dedupe_quotes(quotes);
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        let mut client = self.client.lock().await;
+        self.ensure_partitions(&client, &rows).await?;
+
+        let staging: String = format!("{}_staging", self.table);
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(&format!(
+            "CREATE TEMPORARY TABLE IF NOT EXISTS {} (LIKE {}) ON COMMIT DELETE ROWS",
+            staging, self.table
+        )).await?;
+
+        let sink = transaction.copy_in(&format!(
+            "COPY {} (ts, broker, symbol, ask, bid, spread) FROM STDIN BINARY",
+            staging
+        )).await?;
+        let writer = BinaryCopyInWriter::new(
+            sink,
+            &[Type::TIMESTAMPTZ, Type::TEXT, Type::TEXT, Type::FLOAT8, Type::FLOAT8, Type::FLOAT8]
+        );
+        let mut writer = std::pin::pin!(writer);
+        for quote in &rows {
+            writer.as_mut().write(&[&quote.ts, &quote.broker, &quote.symbol, &quote.ask, &quote.bid, &quote.spread]).await?;
+        }
+        writer.finish().await?;
+
+        let written: u64 = transaction.execute(&format!(
+            "INSERT INTO {} (ts, broker, symbol, ask, bid, spread)
+             SELECT ts, broker, symbol, ask, bid, spread FROM {}
+             ON CONFLICT (broker, symbol, ts) DO UPDATE SET ask = excluded.ask, bid = excluded.bid, spread = excluded.spread",
+            self.table, staging
+        ), &[]).await?;
+        transaction.commit().await?;
+        info!("Upserted {} quote(s) into {}", written, self.table);
+
+        Ok(written as usize)
+    }
+
+    async fn query_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        let (conditions, mut values): (String, Vec<Box<dyn ToSql + Sync + Send>>) = where_clause(filter, "ts", None);
+        let mut sql: String = format!(
+            "SELECT ts, broker, symbol, ask, bid, spread FROM {}{} ORDER BY ts, broker, symbol",
+            self.table, conditions
+        );
+        if let Some(limit) = filter.limit {
+            values.push(Box::new(limit as i64));
+            sql.push_str(&format!(" LIMIT ${}", values.len()));
+        }
+
+        let client = self.client.lock().await;
+        let rows: Vec<Row> = client.query(&sql, &parameters(&values)).await?;
+
+        rows.iter().map(quote_from_row).collect()
+    }
+
+    async fn latest_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        // DISTINCT ON walks the primary key backwards, one row per pair
+        let (conditions, values): (String, Vec<Box<dyn ToSql + Sync + Send>>) = where_clause(filter, "ts", None);
+        let sql: String = format!(
+            "SELECT DISTINCT ON (broker, symbol) ts, broker, symbol, ask, bid, spread FROM {}{}
+             ORDER BY broker, symbol, ts DESC",
+            self.table, conditions
+        );
+
+        let client = self.client.lock().await;
+        let rows: Vec<Row> = client.query(&sql, &parameters(&values)).await?;
+
+        rows.iter().map(quote_from_row).collect()
+    }
+
+    async fn delete_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let (conditions, values): (String, Vec<Box<dyn ToSql + Sync + Send>>) = where_clause(filter, "ts", None);
+
+        let client = self.client.lock().await;
+        let deleted: u64 = client.execute(&format!("DELETE FROM {}{}", self.table, conditions), &parameters(&values)).await?;
+
+        Ok(deleted as usize)
+    }
+
+    /// # Upserts the aggregates in a single statement, one array parameter per column.
+    async fn save_aggregates(
+        &self,
+        aggregates: &[SpreadAggregate]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        if aggregates.is_empty() {
+            return Ok(0);
+        }
+
+        let resolutions: Vec<&str> = aggregates.iter().map(|aggregate| aggregate.resolution.as_str()).collect();
+        let buckets: Vec<DateTime<Utc>> = aggregates.iter().map(|aggregate| aggregate.bucket).collect();
+        let brokers: Vec<&str> = aggregates.iter().map(|aggregate| aggregate.broker.as_str()).collect();
+        let symbols: Vec<&str> = aggregates.iter().map(|aggregate| aggregate.symbol.as_str()).collect();
+        let mins: Vec<f64> = aggregates.iter().map(|aggregate| aggregate.min).collect();
+        let maxes: Vec<f64> = aggregates.iter().map(|aggregate| aggregate.max).collect();
+        let means: Vec<f64> = aggregates.iter().map(|aggregate| aggregate.mean).collect();
+        let lasts: Vec<f64> = aggregates.iter().map(|aggregate| aggregate.last).collect();
+        let counts: Vec<i64> = aggregates.iter().map(|aggregate| aggregate.count as i64).collect();
+
+        let client = self.client.lock().await;
+        let written: u64 = client.execute(
+            &format!(
+                "INSERT INTO {} (resolution, bucket, broker, symbol, min, max, mean, last, count)
+                 SELECT * FROM UNNEST($1::text[], $2::timestamptz[], $3::text[], $4::text[],
+                                      $5::float8[], $6::float8[], $7::float8[], $8::float8[], $9::int8[])
+                 ON CONFLICT (resolution, broker, symbol, bucket) DO UPDATE SET
+                    min = excluded.min, max = excluded.max, mean = excluded.mean, last = excluded.last, count = excluded.count",
+                self.aggregates_table()
+            ),
+            &[&resolutions, &buckets, &brokers, &symbols, &mins, &maxes, &means, &lasts, &counts]
+        ).await?;
+
+        Ok(written as usize)
+    }
+
+    async fn query_aggregates(
+        &self,
+        resolution: Resolution,
+        filter: &QuoteFilter
+    ) -> Result<Vec<SpreadAggregate>, Box<dyn StdError + Send + Sync + 'static>> {
+        let (conditions, mut values): (String, Vec<Box<dyn ToSql + Sync + Send>>) = where_clause(filter, "bucket", Some(resolution));
+        let mut sql: String = format!(
+            "SELECT resolution, bucket, broker, symbol, min, max, mean, last, count FROM {}{} ORDER BY bucket, broker, symbol",
+            self.aggregates_table(), conditions
+        );
+        if let Some(limit) = filter.limit {
+            values.push(Box::new(limit as i64));
+            sql.push_str(&format!(" LIMIT ${}", values.len()));
+        }
+
+        let client = self.client.lock().await;
+        let rows: Vec<Row> = client.query(&sql, &parameters(&values)).await?;
+
+        rows.iter().map(aggregate_from_row).collect()
+    }
+
+    async fn delete_aggregates(
+        &self,
+        resolution: Resolution,
+        filter: &QuoteFilter
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let (conditions, values): (String, Vec<Box<dyn ToSql + Sync + Send>>) = where_clause(filter, "bucket", Some(resolution));
+
+        let client = self.client.lock().await;
+        let deleted: u64 = client.execute(&format!("DELETE FROM {}{}", self.aggregates_table(), conditions), &parameters(&values)).await?;
+
+        Ok(deleted as usize)
+    }
+}
+
+
+/// Creates the quotes table, as a hypertable or partitioned by month, and the aggregates table,
+/// and returns how the quotes table ended up partitioned.
+///
+/// An existing table is kept as it is, its partitioning is read back from the catalog.
+async fn create_schema(
+    client: &Client,
+    table: &str,
+    timescale: bool
+) -> Result<Partitioning, Box<dyn StdError + Send + Sync + 'static>> {
+    let columns: &str = "ts      timestamptz      NOT NULL,
+        broker  text             NOT NULL,
+        symbol  text             NOT NULL,
+        ask     double precision NOT NULL,
+        bid     double precision NOT NULL,
+        spread  double precision NOT NULL,
+        PRIMARY KEY (broker, symbol, ts)";
+
+    create_aggregates_table(client, table).await?;
+
+    if timescale && timescale_available(client).await {
+        client.batch_execute(&format!("CREATE TABLE IF NOT EXISTS {} ({})", table, columns)).await?;
+        let hypertable = client.execute(
+            "SELECT create_hypertable($1::text::regclass, 'ts', if_not_exists => TRUE, create_default_indexes => FALSE)",
+            &[&table]
+        ).await;
+        match hypertable {
+            Ok(_) => {
+                client.batch_execute(&format!("CREATE INDEX IF NOT EXISTS {0}_ts_idx ON {0} (ts DESC)", table)).await?;
+                return Ok(Partitioning::Hypertable);
+            },
+            Err(err) => warn!("Could not turn {} into a hypertable, keeping it as it is: {}", table, err)
+        }
+    } else {
+        client.batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {0} ({1}) PARTITION BY RANGE (ts);
+             CREATE TABLE IF NOT EXISTS {0}_default PARTITION OF {0} DEFAULT;",
+            table, columns
+        )).await?;
+    }
+    client.batch_execute(&format!("CREATE INDEX IF NOT EXISTS {0}_ts_idx ON {0} (ts DESC)", table)).await?;
+
+    let kind: i8 = client.query_one(
+        "SELECT c.relkind FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+         WHERE c.relname = $1 AND n.nspname = current_schema()",
+        &[&table]
+    ).await?.get(0);
+
+    match kind as u8 {
+        b'p' => Ok(Partitioning::Monthly),
+        _ => Ok(Partitioning::None)
+    }
+}
+
+
+/// Creates the table of the aggregates, a plain table since it stays small.
+async fn create_aggregates_table(
+    client: &Client,
+    table: &str
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    client.batch_execute(&format!(
+        "CREATE TABLE IF NOT EXISTS {0}_aggregates (
+            resolution  text             NOT NULL,
+            bucket      timestamptz      NOT NULL,
+            broker      text             NOT NULL,
+            symbol      text             NOT NULL,
+            min         double precision NOT NULL,
+            max         double precision NOT NULL,
+            mean        double precision NOT NULL,
+            last        double precision NOT NULL,
+            count       bigint           NOT NULL,
+            PRIMARY KEY (resolution, broker, symbol, bucket)
+        );
+        CREATE INDEX IF NOT EXISTS {0}_aggregates_bucket_idx ON {0}_aggregates (resolution, bucket);",
+        table
+    )).await?;
+
+    Ok(())
+}
+
+
+/// Installs the `timescaledb` extension when the server ships it, and returns `true` when it is usable.
+async fn timescale_available(
+    client: &Client
+) -> bool {
+    let shipped: bool = match client.query_opt("SELECT 1 FROM pg_available_extensions WHERE name = 'timescaledb'", &[]).await {
+        Ok(row) => row.is_some(),
+        Err(err) => {
+            warn!("Could not list the available extensions: {}", err);
+            false
+        }
+    };
+    if !shipped {
+        info!("TimescaleDB is not available, partitioning quotes by month");
+        return false;
+    }
+
+    match client.batch_execute("CREATE EXTENSION IF NOT EXISTS timescaledb").await {
+        Ok(()) => true,
+        Err(err) => {
+            warn!("Could not enable TimescaleDB, partitioning quotes by month: {}", err);
+            false
+        }
+    }
+}
+
+
+/// Returns `true` for a plain lowercase identifier, the only table names interpolated into the SQL.
+/// It is short enough for the names derived from it to stay within the 63 bytes PostgreSQL keeps.
+fn is_identifier(
+    name: &str
+) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|first| first.is_ascii_lowercase() || first == '_')
+        && chars.all(|char| char.is_ascii_lowercase() || char.is_ascii_digit() || char == '_')
+        && name.len() <= 40
+}
+
+
+/// Builds the `WHERE` clause of the filter on the time column, and of the resolution of aggregates, and the values bound to it.
+fn where_clause(
+    filter: &QuoteFilter,
+    time_column: &str,
+    resolution: Option<Resolution>
+) -> (String, Vec<Box<dyn ToSql + Sync + Send>>) {
+    let mut conditions: Vec<String> = Vec::new();
+    let mut values: Vec<Box<dyn ToSql + Sync + Send>> = Vec::new();
+
+    if let Some(resolution) = resolution {
+        values.push(Box::new(resolution.as_str()));
+        conditions.push(format!("resolution = ${}", values.len()));
+    }
+
+    if let Some(broker) = &filter.broker {
+        values.push(Box::new(broker.clone()));
+        conditions.push(format!("broker = ${}", values.len()));
+    }
+    if let Some(symbol) = &filter.symbol {
+        values.push(Box::new(symbol.clone()));
+        conditions.push(format!("symbol = ${}", values.len()));
+    }
+    if let Some(from) = filter.from {
+        values.push(Box::new(from));
+        conditions.push(format!("{} >= ${}", time_column, values.len()));
+    }
+    if let Some(to) = filter.to {
+        values.push(Box::new(to));
+        conditions.push(format!("{} < ${}", time_column, values.len()));
+    }
+
+    match conditions.is_empty() {
+        true => (String::new(), values),
+        false => (format!(" WHERE {}", conditions.join(" AND ")), values)
+    }
+}
+
+
+fn parameters(
+    values: &[Box<dyn ToSql + Sync + Send>]
+) -> Vec<&(dyn ToSql + Sync)> {
+    values.iter().map(|value| value.as_ref() as &(dyn ToSql + Sync)).collect()
+}
+
+
+fn quote_from_row(
+    row: &Row
+) -> Result<Quote, Box<dyn StdError + Send + Sync + 'static>> {
+    let ts: DateTime<Utc> = row.try_get(0)?;
+
+    Ok(Quote {
+        ts,
+        broker: row.try_get(1)?,
+        symbol: row.try_get(2)?,
+        ask: row.try_get(3)?,
+        bid: row.try_get(4)?,
+        spread: row.try_get(5)?
+    })
+}
+
+
+fn aggregate_from_row(
+    row: &Row
+) -> Result<SpreadAggregate, Box<dyn StdError + Send + Sync + 'static>> {
+    let name: &str = row.try_get(0)?;
+    let count: i64 = row.try_get(8)?;
+
+    Ok(SpreadAggregate {
+        resolution: Resolution::from_name(name).ok_or_else(|| format!("Unknown resolution {:?}", name))?,
+        bucket: row.try_get(1)?,
+        broker: row.try_get(2)?,
+        symbol: row.try_get(3)?,
+        min: row.try_get(4)?,
+        max: row.try_get(5)?,
+        mean: row.try_get(6)?,
+        last: row.try_get(7)?,
+        count: count.max(0) as u64
+    })
+}
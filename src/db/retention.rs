@@ -0,0 +1,249 @@
+//! # Retention and downsampling
+//!
+//! Polling every broker every minute adds millions of rows a month, `RetentionPolicy` keeps the history bounded.
+//!
+//! - Raw quotes are kept for a number of days. Older quotes are rolled up into per-minute, hourly and daily
+//!   `SpreadAggregate`s (min, max, mean, last and count of the spread) per broker and symbol, then deleted.
+//! - The aggregates of each resolution are kept for their own number of days, or forever.
+//!
+//! The rollup only goes through the `Db` trait, so it behaves the same on every backend.
+//! It works a UTC day and a broker at a time: a day is only rolled up once it is entirely past the raw retention,
+//! so every aggregate it writes covers a complete bucket. Writing the aggregates of a day replaces them,
+//! so a run interrupted between writing them and deleting the quotes is picked up again by the next run.
+
+use chrono::{
+    DateTime,
+    Duration,
+    Utc
+};
+use serde_derive::Serialize;
+use std::collections::BTreeSet;
+use std::error::Error as StdError;
+
+use crate::config::RetentionConfig;
+use crate::db::storage::{
+    Db,
+    QuoteFilter
+};
+use crate::model::{
+    Quote,
+    Resolution,
+    SpreadAggregate
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// Struct that reports what a retention run did.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RetentionReport {
+    /// The raw quotes rolled up into aggregates.
+    pub quotes_rolled_up: usize,
+    /// The raw quotes deleted after their rollup.
+    pub quotes_deleted: usize,
+    /// The aggregates written, every resolution together.
+    pub aggregates_written: usize,
+    /// The aggregates deleted past the retention of their resolution.
+    pub aggregates_deleted: usize
+}
+
+
+/// The `RetentionPolicy` struct rolls up and purges the stored quotes of a `Db`.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, TimeZone, Utc };
+/// use spread_tracker::db::memory::MemoryDb;
+/// use spread_tracker::db::retention::RetentionPolicy;
+/// use spread_tracker::db::storage::{ Db, QuoteFilter };
+/// use spread_tracker::model::{ Quote, Resolution };
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let db = MemoryDb::new();
+/// let now = Utc.with_ymd_and_hms(2024, 5, 11, 12, 0, 0).unwrap();
+///
+/// // A quote every 30 minutes over the last ten days.
+/// let quotes: Vec<Quote> = (0..10 * 48)
+///     .map(|step| Quote::sample("fxpro", "EURUSD", now - Duration::minutes(30 * step), 0.2))
+///     .collect();
+/// db.save_quotes(&quotes).await.unwrap();
+///
+/// // Raw quotes for three days, minutes for five, hours and days forever.
+/// let policy = RetentionPolicy::new()
+///     .keep_raw(Some(Duration::days(3)))
+///     .keep_aggregates(Resolution::Minute, Some(Duration::days(5)))
+///     .keep_aggregates(Resolution::Hour, None);
+/// let report = policy.apply(&db, now).await.unwrap();
+///
+/// // Everything before midnight three days ago is rolled up, the raw quotes after it are kept.
+/// let cutoff = Utc.with_ymd_and_hms(2024, 5, 8, 0, 0, 0).unwrap();
+/// assert_eq!(report.quotes_deleted, report.quotes_rolled_up);
+/// assert!(db.query_quotes(&QuoteFilter::new()).await.unwrap().iter().all(|quote| quote.ts >= cutoff));
+///
+/// let hours = db.query_aggregates(Resolution::Hour, &QuoteFilter::new()).await.unwrap();
+/// assert_eq!(hours.iter().map(|hour| hour.count as usize).sum::<usize>(), report.quotes_rolled_up);
+/// assert!(db.query_aggregates(Resolution::Minute, &QuoteFilter::new()).await.unwrap()
+///     .iter().all(|minute| minute.bucket >= now - Duration::days(5)));
+///
+/// // Running it again has nothing left to roll up.
+/// assert_eq!(policy.apply(&db, now).await.unwrap().quotes_rolled_up, 0);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    raw: Option<Duration>,
+    minute: Option<Duration>,
+    hour: Option<Duration>,
+    day: Option<Duration>
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self::from_config(&RetentionConfig::default())
+    }
+}
+
+impl RetentionPolicy {
+    /// # Creates a `RetentionPolicy` with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Creates a `RetentionPolicy` from the `Retention` section of the configuration.
+    pub fn from_config(
+        config: &RetentionConfig
+    ) -> Self {
+        let days = |days: u64| match days {
+            0 => None,
+            days => Some(Duration::days(days as i64))
+        };
+
+        Self {
+            raw: days(config.raw_days),
+            minute: days(config.minute_days),
+            hour: days(config.hour_days),
+            day: days(config.day_days)
+        }
+    }
+
+    /// Sets how long raw quotes are kept before their rollup, `None` keeps them forever and rolls nothing up.
+    pub fn keep_raw(mut self, keep: Option<Duration>) -> Self {
+        self.raw = keep;
+        self
+    }
+
+    /// Sets how long the aggregates of the resolution are kept, `None` keeps them forever.
+    pub fn keep_aggregates(mut self, resolution: Resolution, keep: Option<Duration>) -> Self {
+        match resolution {
+            Resolution::Minute => self.minute = keep,
+            Resolution::Hour => self.hour = keep,
+            Resolution::Day => self.day = keep
+        }
+        self
+    }
+
+    /// Returns how long raw quotes are kept.
+    pub fn raw(&self) -> Option<Duration> {
+        self.raw
+    }
+
+    /// Returns how long the aggregates of the resolution are kept.
+    pub fn aggregates(&self, resolution: Resolution) -> Option<Duration> {
+        match resolution {
+            Resolution::Minute => self.minute,
+            Resolution::Hour => self.hour,
+            Resolution::Day => self.day
+        }
+    }
+
+    /// # Rolls up and purges the stored quotes as of now.
+    ///
+    /// ### Errors
+    /// The errors of `apply`.
+    pub async fn run(
+        &self,
+        db: &dyn Db
+    ) -> Result<RetentionReport, Box<dyn StdError + Send + Sync + 'static>> {
+        self.apply(db, Utc::now()).await
+    }
+
+    /// # Rolls up the raw quotes past their retention, then purges the aggregates past theirs, as of `now`.
+    ///
+    /// ### Errors
+    /// An error will be returned if the database failed, what was done before the failure is kept.
+    pub async fn apply(
+        &self,
+        db: &dyn Db,
+        now: DateTime<Utc>
+    ) -> Result<RetentionReport, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut report: RetentionReport = RetentionReport::default();
+
+        if let Some(raw) = self.raw {
+            let cutoff: DateTime<Utc> = Resolution::Day.truncate(now - raw);
+            let mut previous_day: Option<DateTime<Utc>> = None;
+
+            // The oldest quote left tells the next day to roll up
+            while let Some(oldest) = db.query_quotes(&QuoteFilter::new().to(cutoff).limit(1)).await?.first() {
+                let day: DateTime<Utc> = Resolution::Day.truncate(oldest.ts);
+                if previous_day.is_some_and(|previous| day <= previous) {
+                    error!("The quotes of {} were rolled up but not deleted, stopping the retention", day.date_naive());
+                    return Err(format!("The quotes of {} could not be deleted", day.date_naive()).into());
+                }
+                previous_day = Some(day);
+
+                self.roll_up_day(db, day, now, &mut report).await?;
+            }
+        }
+
+        for resolution in Resolution::ALL {
+            if let Some(keep) = self.aggregates(resolution) {
+                let filter: QuoteFilter = QuoteFilter::new().to(resolution.truncate(now - keep));
+                report.aggregates_deleted += db.delete_aggregates(resolution, &filter).await?;
+            }
+        }
+        info!("Retention: {:?}", report);
+
+        Ok(report)
+    }
+
+    /// Rolls up the quotes of a day, a broker at a time, then deletes them.
+    async fn roll_up_day(
+        &self,
+        db: &dyn Db,
+        day: DateTime<Utc>,
+        now: DateTime<Utc>,
+        report: &mut RetentionReport
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        let day_end: DateTime<Utc> = day + Resolution::Day.duration();
+
+        // The latest quote of every pair that day, which gives the brokers to go through
+        let brokers: BTreeSet<String> = db.latest_quotes(&QuoteFilter::new().from(day).to(day_end)).await?
+            .into_iter()
+            .map(|quote| quote.broker)
+            .collect();
+
+        for broker in brokers {
+            let filter: QuoteFilter = QuoteFilter::new().broker(&broker).from(day).to(day_end);
+            let quotes: Vec<Quote> = db.query_quotes(&filter).await?;
+
+            // Aggregates already past their own retention are not worth writing
+            let aggregates: Vec<SpreadAggregate> = Resolution::ALL.into_iter()
+                .filter(|resolution| self.aggregates(*resolution).is_none_or(|keep| day_end > now - keep))
+                .flat_map(|resolution| SpreadAggregate::from_quotes(resolution, &quotes))
+                .collect();
+
+            report.aggregates_written += db.save_aggregates(&aggregates).await?;
+            report.quotes_rolled_up += quotes.len();
+            report.quotes_deleted += db.delete_quotes(&filter).await?;
+        }
+        info!("Rolled up the quotes of {}", day.date_naive());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,490 @@
+//! # SQLite storage
+//!
+//! A `Db` in a single SQLite file, for single-box deployments that do not want to run Supabase.
+//!
+//! - The schema is versioned with `PRAGMA user_version`, pending migrations are applied when the database is opened.
+//! - The database runs in WAL mode, so a poller writing quotes and a reader querying them do not block each other.
+//! - `(broker, symbol, ts)` is the primary key, which serves both "latest per broker / symbol" and the time range
+//!   of a single pair. A separate index on `ts` serves time ranges across every pair.
+//! - The rollups of `db::retention` live in the `aggregates` table, keyed by `(resolution, broker, symbol, bucket)`.
+//!
+//! Timestamps and buckets are stored as microseconds since the Unix epoch, so they sort and compare as integers.
+
+use async_trait::async_trait;
+use chrono::{
+    DateTime,
+    Utc
+};
+use rusqlite::{
+    Connection,
+    OptionalExtension,
+    Row,
+    ToSql,
+    params
+};
+use std::error::Error as StdError;
+use std::path::Path;
+use std::sync::{
+    Arc,
+    Mutex
+};
+use std::time::Duration;
+
+use crate::db::storage::{
+    Db,
+    QuoteFilter
+};
+use crate::model::{
+    Quote,
+    Resolution,
+    SpreadAggregate
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The schema migrations, in order. Migration `n` brings the database to `user_version` `n + 1`.
+///
+/// Applied migrations must never be edited, a schema change is a new migration appended to the list.
+const MIGRATIONS: &[&str] = &[
+    // 1: the quotes table, keyed by broker, symbol and time
+    "CREATE TABLE quotes (
+        ts      INTEGER NOT NULL,
+        broker  TEXT    NOT NULL,
+        symbol  TEXT    NOT NULL,
+        ask     REAL    NOT NULL,
+        bid     REAL    NOT NULL,
+        spread  REAL    NOT NULL,
+        PRIMARY KEY (broker, symbol, ts)
+    ) WITHOUT ROWID;",
+    // 2: time ranges across every broker and symbol
+    "CREATE INDEX quotes_ts_idx ON quotes (ts);",
+    // 3: the per-minute, hourly and daily rollups of the retention
+    "CREATE TABLE aggregates (
+        resolution  TEXT    NOT NULL,
+        bucket      INTEGER NOT NULL,
+        broker      TEXT    NOT NULL,
+        symbol      TEXT    NOT NULL,
+        min         REAL    NOT NULL,
+        max         REAL    NOT NULL,
+        mean        REAL    NOT NULL,
+        last        REAL    NOT NULL,
+        count       INTEGER NOT NULL,
+        PRIMARY KEY (resolution, broker, symbol, bucket)
+    ) WITHOUT ROWID;
+    CREATE INDEX aggregates_bucket_idx ON aggregates (resolution, bucket);"
+];
+
+/// How long a writer waits for the lock of another connection before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+/// The `SqliteDb` struct stores quotes in an SQLite database.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::db::sqlite::SqliteDb;
+/// use spread_tracker::db::storage::{ Db, QuoteFilter };
+/// use spread_tracker::model::{ BrokerSnapshot, Quote };
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let path = std::env::temp_dir().join(format!("spread_sqlite_doc_{}.db", std::process::id()));
+/// let db = SqliteDb::open(&path).unwrap();
+/// assert_eq!(db.schema_version().unwrap(), 3);
+///
+/// let snapshot = BrokerSnapshot {
+///     broker: "fxpro".to_string(),
+///     url: "https://www.myfxbook.com/forex-broker-quotes/fxpro/5168".to_string(),
+///     quotes: serde_json::json!([
+///         { "symbol": "EURUSD", "ask": 1.0712, "bid": 1.0714, "spread": 0.2 },
+///         { "symbol": "GBPUSD", "ask": 1.2501, "bid": 1.2504, "spread": 0.3 }
+///     ]),
+///     fetched_at: chrono::Utc::now()
+/// };
+///
+/// // Saving the same snapshot twice keeps one row per quote.
+/// db.save_snapshot(&snapshot).await.unwrap();
+/// db.save_snapshot(&snapshot).await.unwrap();
+///
+/// let latest = db.latest_quotes(&QuoteFilter::new().broker("fxpro")).await.unwrap();
+/// assert_eq!(latest.len(), 2);
+/// assert_eq!(latest[0], Quote::from_snapshot(&snapshot)[0]);
+///
+/// // A second connection, like a reader next to the poller, sees the same quotes.
+/// let reader = SqliteDb::open(&path).unwrap();
+/// assert_eq!(reader.query_quotes(&QuoteFilter::new()).await.unwrap().len(), 2);
+/// # drop((db, reader));
+/// # for suffix in ["", "-wal", "-shm"] { let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix)); }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SqliteDb {
+    connection: Arc<Mutex<Connection>>
+}
+
+impl SqliteDb {
+    /// # Opens the database file, creating it when needed, and applies the pending migrations.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be opened or a migration failed.
+    pub fn open(
+        path: impl AsRef<Path>
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let connection: Connection = Connection::open(path.as_ref())?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+
+        // WAL lets readers run while the poller writes, NORMAL is durable enough in WAL mode
+        let journal_mode: String = connection.query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            warn!("{} does not support WAL, running in {} mode", path.as_ref().display(), journal_mode);
+        }
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+
+        Self::from_connection(connection)
+    }
+
+    /// # Opens a private in-memory database, for tests.
+    ///
+    /// ### Errors
+    /// An error will be returned if a migration failed.
+    pub fn open_in_memory() -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(
+        mut connection: Connection
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        migrate(&mut connection)?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection))
+        })
+    }
+
+    /// # Returns the schema version of the database, the number of migrations applied.
+    pub fn schema_version(&self) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let connection = self.connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(user_version(&connection)?)
+    }
+
+    /// Runs a blocking closure on the connection, off the async runtime.
+    async fn with_connection<T, F>(
+        &self,
+        task: F
+    ) -> Result<T, Box<dyn StdError + Send + Sync + 'static>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, rusqlite::Error> + Send + 'static
+    {
+        let connection: Arc<Mutex<Connection>> = self.connection.clone();
+
+        let result: Result<T, rusqlite::Error> = tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            task(&mut connection)
+        }).await?;
+
+        Ok(result?)
+    }
+}
+
+#[async_trait]
+impl Db for SqliteDb {
+    async fn save_quotes(
+        &self,
+        quotes: &[Quote]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let quotes: Vec<Quote> = quotes.to_vec();
+
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut statement = transaction.prepare_cached(
+                    "INSERT INTO quotes (ts, broker, symbol, ask, bid, spread) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                     ON CONFLICT (broker, symbol, ts) DO UPDATE SET ask = excluded.ask, bid = excluded.bid, spread = excluded.spread"
+                )?;
+                for quote in &quotes {
+                    statement.execute(params![
+                        quote.ts.timestamp_micros(),
+                        quote.broker,
+                        quote.symbol,
+                        quote.ask,
+                        quote.bid,
+                        quote.spread
+                    ])?;
+                }
+            }
+            transaction.commit()?;
+
+            Ok(quotes.len())
+        }).await
+    }
+
+    async fn query_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        let filter: QuoteFilter = filter.clone();
+
+        self.with_connection(move |connection| {
+            let (conditions, mut values): (String, Vec<Box<dyn ToSql + Send>>) = where_clause(&filter, "ts", None);
+            let mut sql: String = format!(
+                "SELECT ts, broker, symbol, ask, bid, spread FROM quotes{} ORDER BY ts, broker, symbol",
+                conditions
+            );
+            if let Some(limit) = filter.limit {
+                sql.push_str(" LIMIT ?");
+                values.push(Box::new(limit as i64));
+            }
+
+            let mut statement = connection.prepare(&sql)?;
+            let quotes = statement.query_map(
+                rusqlite::params_from_iter(values.iter().map(|value| value.as_ref() as &dyn ToSql)),
+                quote_from_row
+            )?;
+
+            quotes.collect()
+        }).await
+    }
+
+    async fn latest_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        let filter: QuoteFilter = filter.clone();
+
+        self.with_connection(move |connection| {
+            // The latest time of every pair comes straight from the primary key
+            let (conditions, values): (String, Vec<Box<dyn ToSql + Send>>) = where_clause(&filter, "ts", None);
+            let sql: String = format!(
+                "SELECT q.ts, q.broker, q.symbol, q.ask, q.bid, q.spread
+                 FROM quotes q
+                 JOIN (SELECT broker, symbol, MAX(ts) AS ts FROM quotes{} GROUP BY broker, symbol) l
+                   ON q.broker = l.broker AND q.symbol = l.symbol AND q.ts = l.ts
+                 ORDER BY q.broker, q.symbol",
+                conditions
+            );
+
+            let mut statement = connection.prepare(&sql)?;
+            let quotes = statement.query_map(
+                rusqlite::params_from_iter(values.iter().map(|value| value.as_ref() as &dyn ToSql)),
+                quote_from_row
+            )?;
+
+            quotes.collect()
+        }).await
+    }
+
+    async fn delete_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let filter: QuoteFilter = filter.clone();
+
+        self.with_connection(move |connection| {
+            let (conditions, values): (String, Vec<Box<dyn ToSql + Send>>) = where_clause(&filter, "ts", None);
+            connection.execute(
+                &format!("DELETE FROM quotes{}", conditions),
+                rusqlite::params_from_iter(values.iter().map(|value| value.as_ref() as &dyn ToSql))
+            )
+        }).await
+    }
+
+    async fn save_aggregates(
+        &self,
+        aggregates: &[SpreadAggregate]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let aggregates: Vec<SpreadAggregate> = aggregates.to_vec();
+
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut statement = transaction.prepare_cached(
+                    "INSERT INTO aggregates (resolution, bucket, broker, symbol, min, max, mean, last, count)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                     ON CONFLICT (resolution, broker, symbol, bucket) DO UPDATE SET
+                        min = excluded.min, max = excluded.max, mean = excluded.mean, last = excluded.last, count = excluded.count"
+                )?;
+                for aggregate in &aggregates {
+                    statement.execute(params![
+                        aggregate.resolution.as_str(),
+                        aggregate.bucket.timestamp_micros(),
+                        aggregate.broker,
+                        aggregate.symbol,
+                        aggregate.min,
+                        aggregate.max,
+                        aggregate.mean,
+                        aggregate.last,
+                        aggregate.count as i64
+                    ])?;
+                }
+            }
+            transaction.commit()?;
+
+            Ok(aggregates.len())
+        }).await
+    }
+
+    async fn query_aggregates(
+        &self,
+        resolution: Resolution,
+        filter: &QuoteFilter
+    ) -> Result<Vec<SpreadAggregate>, Box<dyn StdError + Send + Sync + 'static>> {
+        let filter: QuoteFilter = filter.clone();
+
+        self.with_connection(move |connection| {
+            let (conditions, mut values): (String, Vec<Box<dyn ToSql + Send>>) = where_clause(&filter, "bucket", Some(resolution));
+            let mut sql: String = format!(
+                "SELECT resolution, bucket, broker, symbol, min, max, mean, last, count FROM aggregates{} ORDER BY bucket, broker, symbol",
+                conditions
+            );
+            if let Some(limit) = filter.limit {
+                sql.push_str(" LIMIT ?");
+                values.push(Box::new(limit as i64));
+            }
+
+            let mut statement = connection.prepare(&sql)?;
+            let aggregates = statement.query_map(
+                rusqlite::params_from_iter(values.iter().map(|value| value.as_ref() as &dyn ToSql)),
+                aggregate_from_row
+            )?;
+
+            aggregates.collect()
+        }).await
+    }
+
+    async fn delete_aggregates(
+        &self,
+        resolution: Resolution,
+        filter: &QuoteFilter
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let filter: QuoteFilter = filter.clone();
+
+        self.with_connection(move |connection| {
+            let (conditions, values): (String, Vec<Box<dyn ToSql + Send>>) = where_clause(&filter, "bucket", Some(resolution));
+            connection.execute(
+                &format!("DELETE FROM aggregates{}", conditions),
+                rusqlite::params_from_iter(values.iter().map(|value| value.as_ref() as &dyn ToSql))
+            )
+        }).await
+    }
+}
+
+
+/// Applies the migrations the database has not seen yet, each one in its own transaction.
+fn migrate(
+    connection: &mut Connection
+) -> Result<(), rusqlite::Error> {
+    let applied: usize = user_version(connection)?;
+    if applied > MIGRATIONS.len() {
+        warn!("The database is at schema version {}, newer than this build ({})", applied, MIGRATIONS.len());
+        return Ok(());
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", (index + 1) as i64)?;
+        transaction.commit()?;
+        info!("Applied SQLite migration {}", index + 1);
+    }
+
+    Ok(())
+}
+
+
+fn user_version(
+    connection: &Connection
+) -> Result<usize, rusqlite::Error> {
+    let version: Option<i64> = connection.query_row("PRAGMA user_version", [], |row| row.get(0)).optional()?;
+    Ok(version.unwrap_or(0).max(0) as usize)
+}
+
+
+/// Builds the `WHERE` clause of the filter on the time column, and of the resolution of aggregates, and the values bound to it.
+fn where_clause(
+    filter: &QuoteFilter,
+    time_column: &str,
+    resolution: Option<Resolution>
+) -> (String, Vec<Box<dyn ToSql + Send>>) {
+    let mut conditions: Vec<String> = Vec::new();
+    let mut values: Vec<Box<dyn ToSql + Send>> = Vec::new();
+
+    if let Some(resolution) = resolution {
+        conditions.push("resolution = ?".to_string());
+        values.push(Box::new(resolution.as_str()));
+    }
+
+    if let Some(broker) = &filter.broker {
+        conditions.push("broker = ?".to_string());
+        values.push(Box::new(broker.clone()));
+    }
+    if let Some(symbol) = &filter.symbol {
+        conditions.push("symbol = ?".to_string());
+        values.push(Box::new(symbol.clone()));
+    }
+    if let Some(from) = filter.from {
+        conditions.push(format!("{} >= ?", time_column));
+        values.push(Box::new(from.timestamp_micros()));
+    }
+    if let Some(to) = filter.to {
+        conditions.push(format!("{} < ?", time_column));
+        values.push(Box::new(to.timestamp_micros()));
+    }
+
+    match conditions.is_empty() {
+        true => (String::new(), values),
+        false => (format!(" WHERE {}", conditions.join(" AND ")), values)
+    }
+}
+
+
+fn quote_from_row(
+    row: &Row
+) -> Result<Quote, rusqlite::Error> {
+    let micros: i64 = row.get(0)?;
+    let ts: DateTime<Utc> = DateTime::from_timestamp_micros(micros).ok_or_else(|| {
+        rusqlite::Error::IntegralValueOutOfRange(0, micros)
+    })?;
+
+    Ok(Quote {
+        ts,
+        broker: row.get(1)?,
+        symbol: row.get(2)?,
+        ask: row.get(3)?,
+        bid: row.get(4)?,
+        spread: row.get(5)?
+    })
+}
+
+
+fn aggregate_from_row(
+    row: &Row
+) -> Result<SpreadAggregate, rusqlite::Error> {
+    let name: String = row.get(0)?;
+    let resolution: Resolution = Resolution::from_name(&name).ok_or_else(|| {
+        rusqlite::Error::InvalidColumnType(0, name.clone(), rusqlite::types::Type::Text)
+    })?;
+    let micros: i64 = row.get(1)?;
+    let bucket: DateTime<Utc> = DateTime::from_timestamp_micros(micros).ok_or_else(|| {
+        rusqlite::Error::IntegralValueOutOfRange(1, micros)
+    })?;
+    let count: i64 = row.get(8)?;
+
+    Ok(SpreadAggregate {
+        resolution,
+        bucket,
+        broker: row.get(2)?,
+        symbol: row.get(3)?,
+        min: row.get(4)?,
+        max: row.get(5)?,
+        mean: row.get(6)?,
+        last: row.get(7)?,
+        count: count.max(0) as u64
+    })
+}
@@ -0,0 +1,294 @@
+//! # Storage trait
+//!
+//! The `Db` trait is what the rest of the crate, and the services built on it, store quotes through.
+//! Every backend of the `db` module implements it, so a store can be swapped without touching the tracker code.
+//!
+//! - `save_snapshot` / `save_quotes` upsert quotes keyed by `(broker, symbol, ts)`, saving the same quote twice keeps one row.
+//! - `query_quotes` returns the quotes matching a `QuoteFilter`, oldest first.
+//! - `latest_quotes` returns the most recent quote of every broker / symbol pair matching a `QuoteFilter`.
+//! - `delete_quotes` deletes the quotes matching a `QuoteFilter`, for the retention of `db::retention`.
+//! - `save_aggregates` / `query_aggregates` / `delete_aggregates` do the same for the per-minute, hourly and daily
+//!   rollups of the quotes, keyed by `(resolution, broker, symbol, bucket)`.
+//!
+//! `open_storage` opens the backend selected by the `Storage` section of the configuration.
+
+use async_trait::async_trait;
+use chrono::{
+    DateTime,
+    Utc
+};
+use std::error::Error as StdError;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::config::{
+    PostgresConfig,
+    StorageBackend,
+    StorageConfig,
+    SupabaseConfig
+};
+use crate::db::memory::MemoryDb;
+use crate::db::postgres::PostgresDb;
+use crate::db::sqlite::SqliteDb;
+use crate::db::supabase::SupabaseStore;
+use crate::model::{
+    BrokerSnapshot,
+    Quote,
+    Resolution,
+    SpreadAggregate
+};
+
+
+/// The `Db` trait is implemented by every storage backend.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::db::memory::MemoryDb;
+/// use spread_tracker::db::storage::{ Db, QuoteFilter };
+/// use spread_tracker::model::BrokerSnapshot;
+///
+/// async fn store_and_read(db: &dyn Db, snapshot: &BrokerSnapshot) -> usize {
+///     db.save_snapshot(snapshot).await.unwrap();
+///     db.query_quotes(&QuoteFilter::new().broker(&snapshot.broker)).await.unwrap().len()
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let snapshot = BrokerSnapshot {
+///     broker: "fxpro".to_string(),
+///     url: "https://www.myfxbook.com/forex-broker-quotes/fxpro/5168".to_string(),
+///     quotes: serde_json::json!([{ "symbol": "EURUSD", "ask": 1.0712, "bid": 1.0714, "spread": 0.2 }]),
+///     fetched_at: chrono::Utc::now()
+/// };
+///
+/// assert_eq!(store_and_read(&MemoryDb::new(), &snapshot).await, 1);
+/// # }
+/// ```
+#[async_trait]
+pub trait Db: Debug + Send + Sync {
+    /// # Upserts the quotes of a snapshot, and returns how many rows were written.
+    async fn save_snapshot(
+        &self,
+        snapshot: &BrokerSnapshot
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        self.save_quotes(&Quote::from_snapshot(snapshot)).await
+    }
+
+    /// # Upserts the quotes, keyed by `(broker, symbol, ts)`, and returns how many rows were written.
+    async fn save_quotes(
+        &self,
+        quotes: &[Quote]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>>;
+
+    /// # Returns the quotes matching the filter, ordered by time, then broker, then symbol.
+    async fn query_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>>;
+
+    /// # Returns the most recent quote of every broker / symbol pair matching the filter, ordered by broker, then symbol.
+    ///
+    /// The time range of the filter applies before picking the most recent quote,
+    /// so a filter with `to` returns the quotes as they were at that time. The limit of the filter is ignored.
+    async fn latest_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>>;
+
+    /// # Deletes the quotes matching the filter, and returns how many were deleted. The limit of the filter is ignored.
+    async fn delete_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>>;
+
+    /// # Upserts the aggregates, keyed by `(resolution, broker, symbol, bucket)`, and returns how many rows were written.
+    async fn save_aggregates(
+        &self,
+        aggregates: &[SpreadAggregate]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>>;
+
+    /// # Returns the aggregates of the resolution matching the filter, ordered by bucket, then broker, then symbol.
+    ///
+    /// The time range of the filter applies to the start of the buckets.
+    async fn query_aggregates(
+        &self,
+        resolution: Resolution,
+        filter: &QuoteFilter
+    ) -> Result<Vec<SpreadAggregate>, Box<dyn StdError + Send + Sync + 'static>>;
+
+    /// # Deletes the aggregates of the resolution matching the filter, and returns how many were deleted.
+    async fn delete_aggregates(
+        &self,
+        resolution: Resolution,
+        filter: &QuoteFilter
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>>;
+}
+
+
+/// The `QuoteFilter` struct selects quotes by broker, symbol and time range.
+///
+/// `from` is inclusive and `to` is exclusive, an unset field matches everything.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, Utc };
+/// use spread_tracker::db::storage::QuoteFilter;
+///
+/// let filter = QuoteFilter::new()
+///     .broker("fxpro")
+///     .symbol("EURUSD")
+///     .from(Utc::now() - Duration::hours(1))
+///     .limit(100);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuoteFilter {
+    pub broker: Option<String>,
+    pub symbol: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<usize>
+}
+
+impl QuoteFilter {
+    /// # Creates a filter that matches every quote.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only matches the quotes of the broker.
+    pub fn broker(mut self, broker: &str) -> Self {
+        self.broker = Some(broker.to_string());
+        self
+    }
+
+    /// Only matches the quotes of the symbol.
+    pub fn symbol(mut self, symbol: &str) -> Self {
+        self.symbol = Some(symbol.to_string());
+        self
+    }
+
+    /// Only matches the quotes fetched at or after the time.
+    pub fn from(mut self, from: DateTime<Utc>) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    /// Only matches the quotes fetched before the time.
+    pub fn to(mut self, to: DateTime<Utc>) -> Self {
+        self.to = Some(to);
+        self
+    }
+
+    /// Returns at most the given number of quotes.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// # Returns `true` when the quote matches the broker, symbol and time range of the filter.
+    pub fn matches(
+        &self,
+        quote: &Quote
+    ) -> bool {
+        self.broker.as_ref().is_none_or(|broker| &quote.broker == broker)
+            && self.symbol.as_ref().is_none_or(|symbol| &quote.symbol == symbol)
+            && self.from.is_none_or(|from| quote.ts >= from)
+            && self.to.is_none_or(|to| quote.ts < to)
+    }
+
+    /// # Returns `true` when the aggregate matches the broker, symbol and time range of the filter, on its bucket.
+    pub fn matches_aggregate(
+        &self,
+        aggregate: &SpreadAggregate
+    ) -> bool {
+        self.broker.as_ref().is_none_or(|broker| &aggregate.broker == broker)
+            && self.symbol.as_ref().is_none_or(|symbol| &aggregate.symbol == symbol)
+            && self.from.is_none_or(|from| aggregate.bucket >= from)
+            && self.to.is_none_or(|to| aggregate.bucket < to)
+    }
+}
+
+
+/// # Keeps the most recent quote of every broker / symbol pair, ordered by broker, then symbol.
+///
+/// Backends that cannot pick the latest quotes in the database use it on the quotes of their query.
+pub fn latest_per_pair(
+    quotes: Vec<Quote>
+) -> Vec<Quote> {
+    let mut latest: std::collections::BTreeMap<(String, String), Quote> = std::collections::BTreeMap::new();
+
+    for quote in quotes {
+        let key: (String, String) = (quote.broker.clone(), quote.symbol.clone());
+        match latest.get(&key) {
+            Some(current) if current.ts >= quote.ts => {},
+            _ => {
+                latest.insert(key, quote);
+            }
+        }
+    }
+
+    latest.into_values().collect()
+}
+
+
+/// # Keeps one quote per `(broker, symbol, ts)`, the last one wins, in the order they were first seen.
+///
+/// Postgres rejects an upsert that touches the same row twice, backends on it dedupe their batches first.
+pub fn dedupe_quotes(
+    quotes: &[Quote]
+) -> Vec<&Quote> {
+    let mut positions: std::collections::HashMap<(&str, &str, i64), usize> = std::collections::HashMap::new();
+    let mut unique: Vec<&Quote> = Vec::new();
+
+    for quote in quotes {
+        let key: (&str, &str, i64) = (&quote.broker, &quote.symbol, quote.ts.timestamp_micros());
+        match positions.get(&key) {
+            Some(position) => unique[*position] = quote,
+            None => {
+                positions.insert(key, unique.len());
+                unique.push(quote);
+            }
+        }
+    }
+
+    unique
+}
+
+
+/// # Opens the storage backend selected by the `Storage` section of the configuration.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::config::{ StorageBackend, StorageConfig };
+/// use spread_tracker::db::storage::{ open_storage, QuoteFilter };
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let config = StorageConfig { backend: StorageBackend::Memory, ..StorageConfig::default() };
+/// let db = open_storage(&config).await.unwrap();
+///
+/// assert!(db.query_quotes(&QuoteFilter::new()).await.unwrap().is_empty());
+/// # }
+/// ```
+///
+/// ### Errors
+/// An error will be returned if the backend could not be opened,
+/// `missing_database_credentials` if the credentials of a remote backend are missing.
+pub async fn open_storage(
+    config: &StorageConfig
+) -> Result<Arc<dyn Db>, Box<dyn StdError + Send + Sync + 'static>> {
+    let db: Arc<dyn Db> = match config.backend {
+        StorageBackend::Memory => Arc::new(MemoryDb::new()),
+        StorageBackend::Sqlite => {
+            let path: String = config.path.clone();
+            Arc::new(tokio::task::spawn_blocking(move || SqliteDb::open(path)).await??)
+        },
+        StorageBackend::Supabase => Arc::new(SupabaseStore::from_config(&SupabaseConfig::load()?)?),
+        StorageBackend::Postgres => Arc::new(PostgresDb::from_config(&PostgresConfig::load()?).await?)
+    };
+
+    Ok(db)
+}
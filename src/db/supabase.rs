@@ -1 +1,586 @@
-//! # Supabase Client
\ No newline at end of file
+//! # Supabase Client
+//!
+//! Stores the quotes of every broker snapshot in a Supabase table, through its PostgREST API.
+//! Rows are written in batches with idempotent upserts keyed by `(broker, symbol, ts)`,
+//! so saving the same snapshot twice leaves a single row per quote.
+//!
+//! The `supabase_rs` crate inserts a single row at a time and has no `on_conflict` support,
+//! so the requests are sent through the `HttpTransport` of the tracker instead.
+//! The same transport makes the store testable against a local PostgREST server, or without any server at all.
+//!
+//! ### Table schema
+//!
+//! ```sql
+//! create table if not exists spread_quotes (
+//!     ts      timestamptz      not null,
+//!     broker  text             not null,
+//!     symbol  text             not null,
+//!     ask     double precision not null,
+//!     bid     double precision not null,
+//!     spread  double precision not null,
+//!     primary key (broker, symbol, ts)
+//! );
+//!
+//! create index if not exists spread_quotes_ts_idx on spread_quotes (ts);
+//! ```
+//!
+//! The primary key is the conflict target of the upserts, it must match `(broker, symbol, ts)`.
+//!
+//! `latest_quotes` reads the `<table>_latest` view when the filter has no time range, and calls the
+//! `<table>_latest_between` function when it has one. PostgREST cannot pick the most recent row per pair by itself,
+//! and a filter on the view would apply after `distinct on`, dropping the pairs quoted after the range:
+//!
+//! ```sql
+//! create or replace view spread_quotes_latest as
+//!     select distinct on (broker, symbol) *
+//!     from spread_quotes
+//!     order by broker, symbol, ts desc;
+//!
+//! create or replace function spread_quotes_latest_between(from_ts timestamptz default null, to_ts timestamptz default null)
+//! returns setof spread_quotes
+//! language sql stable
+//! as $$
+//!     select distinct on (broker, symbol) *
+//!     from spread_quotes
+//!     where (from_ts is null or ts >= from_ts) and (to_ts is null or ts < to_ts)
+//!     order by broker, symbol, ts desc;
+//! $$;
+//! ```
+//!
+//! The rollups of `db::retention` are written to `<table>_aggregates`:
+//!
+//! ```sql
+//! create table if not exists spread_quotes_aggregates (
+//!     resolution  text             not null,
+//!     bucket      timestamptz      not null,
+//!     broker      text             not null,
+//!     symbol      text             not null,
+//!     min         double precision not null,
+//!     max         double precision not null,
+//!     mean        double precision not null,
+//!     last        double precision not null,
+//!     count       bigint           not null,
+//!     primary key (resolution, broker, symbol, bucket)
+//! );
+//! ```
+
+use async_trait::async_trait;
+use reqwest::Url;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::error::Error as StdError;
+use std::sync::Arc;
+
+use crate::config::SupabaseConfig;
+use crate::db::storage::{
+    Db,
+    QuoteFilter,
+    dedupe_quotes
+};
+use crate::errors::{
+    ErrorsSpread,
+    HttpStatusError
+};
+use crate::model::{
+    BrokerSnapshot,
+    Quote,
+    Resolution,
+    SpreadAggregate
+};
+use crate::utils::request_builder::shared_client;
+use crate::utils::transport::{
+    HttpRequest,
+    HttpResponse,
+    HttpTransport,
+    ReqwestTransport
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The columns that identify a quote, the conflict target of the upserts.
+pub const CONFLICT_COLUMNS: &str = "broker,symbol,ts";
+
+/// The columns that identify an aggregate, the conflict target of its upserts.
+pub const AGGREGATE_CONFLICT_COLUMNS: &str = "resolution,broker,symbol,bucket";
+
+/// The columns read back into a `Quote`.
+const QUOTE_COLUMNS: &str = "ts,broker,symbol,ask,bid,spread";
+
+/// The columns read back into a `SpreadAggregate`.
+const AGGREGATE_COLUMNS: &str = "resolution,bucket,broker,symbol,min,max,mean,last,count";
+
+
+/// The `SupabaseStore` struct writes quotes to a Supabase / PostgREST table.
+///
+/// ### Example
+///
+/// ```
+/// use std::sync::{ Arc, Mutex };
+/// use async_trait::async_trait;
+/// use spread_tracker::db::storage::Db;
+/// use spread_tracker::db::supabase::SupabaseStore;
+/// use spread_tracker::model::BrokerSnapshot;
+/// use spread_tracker::utils::transport::{ HttpRequest, HttpResponse, HttpTransport };
+///
+/// // A stand-in for PostgREST that accepts every request and keeps it.
+/// #[derive(Debug, Default)]
+/// struct FakePostgrest {
+///     requests: Mutex<Vec<HttpRequest>>
+/// }
+///
+/// #[async_trait]
+/// impl HttpTransport for FakePostgrest {
+///     async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error + Send + Sync>> {
+///         self.requests.lock().unwrap().push(request);
+///         Ok(HttpResponse::new(201, ""))
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let postgrest = Arc::new(FakePostgrest::default());
+/// let store = SupabaseStore::with_transport("http://localhost:3000", "anon-key", postgrest.clone())
+///     .rest_path("")
+///     .batch_size(2);
+///
+/// let snapshot = BrokerSnapshot {
+///     broker: "fxpro".to_string(),
+///     url: "https://www.myfxbook.com/forex-broker-quotes/fxpro/5168".to_string(),
+///     quotes: serde_json::json!([
+///         { "symbol": "EURUSD", "ask": 1.0712, "bid": 1.0714, "spread": 0.2 },
+///         { "symbol": "GBPUSD", "ask": 1.2501, "bid": 1.2504, "spread": 0.3 },
+///         { "symbol": "USDJPY", "ask": 155.12, "bid": 155.15, "spread": 0.3 }
+///     ]),
+///     fetched_at: chrono::Utc::now()
+/// };
+///
+/// assert_eq!(store.save_snapshot(&snapshot).await.unwrap(), 3);
+///
+/// // Three quotes in batches of two make two upserts.
+/// let requests = postgrest.requests.lock().unwrap();
+/// assert_eq!(requests.len(), 2);
+/// assert_eq!(requests[0].url, "http://localhost:3000/spread_quotes?on_conflict=broker,symbol,ts");
+/// assert!(requests[0].headers.contains(&("Prefer".to_string(), "resolution=merge-duplicates,return=minimal".to_string())));
+/// # }
+/// ```
+///
+/// Reads are paged, PostgREST cuts every response at its `max-rows`:
+///
+/// ```
+/// use std::sync::Arc;
+/// use spread_tracker::db::storage::{ Db, QuoteFilter };
+/// use spread_tracker::db::supabase::SupabaseStore;
+/// use spread_tracker::utils::transport::{ Cassette, HttpRequest, HttpResponse, ReplayTransport };
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let page = |offset: usize| format!(
+///     "http://localhost:3000/spread_quotes?broker=eq.fxpro&select=ts%2Cbroker%2Csymbol%2Cask%2Cbid%2Cspread\
+///      &order=ts.asc%2Cbroker.asc%2Csymbol.asc&limit=2&offset={}",
+///     offset
+/// );
+/// let row = |minute: u32| format!(
+///     r#"{{"ts":"2024-05-01T12:0{}:00Z","broker":"fxpro","symbol":"EURUSD","ask":1.0712,"bid":1.0714,"spread":0.2}}"#,
+///     minute
+/// );
+///
+/// // Five rows, two per page: the short third page ends the read.
+/// let mut cassette = Cassette::new();
+/// cassette.record(HttpRequest::get(&page(0)), HttpResponse::new(200, &format!("[{},{}]", row(0), row(1))));
+/// cassette.record(HttpRequest::get(&page(2)), HttpResponse::new(200, &format!("[{},{}]", row(2), row(3))));
+/// cassette.record(HttpRequest::get(&page(4)), HttpResponse::new(200, &format!("[{}]", row(4))));
+///
+/// let store = SupabaseStore::with_transport("http://localhost:3000", "anon-key", Arc::new(ReplayTransport::new(cassette)))
+///     .rest_path("")
+///     .page_size(2);
+///
+/// let quotes = store.query_quotes(&QuoteFilter::new().broker("fxpro")).await.unwrap();
+/// assert_eq!(quotes.len(), 5);
+/// assert_eq!(quotes[4].ts.to_rfc3339(), "2024-05-01T12:04:00+00:00");
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SupabaseStore {
+    url: String,
+    key: String,
+    rest_path: String,
+    table: String,
+    batch_size: usize,
+    page_size: usize,
+    transport: Arc<dyn HttpTransport>
+}
+
+impl SupabaseStore {
+    /// # Creates a `SupabaseStore` for the project URL and API key, using the process-wide HTTP client.
+    ///
+    /// ### Errors
+    /// `failed_to_read_yaml` will be returned if the `Http` section is not in the correct format.
+    pub fn new(
+        url: &str,
+        key: &str
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        Ok(Self::with_transport(url, key, Arc::new(ReqwestTransport::new(shared_client()?))))
+    }
+
+    /// # Creates a `SupabaseStore` that sends every request through the given transport.
+    pub fn with_transport(
+        url: &str,
+        key: &str,
+        transport: Arc<dyn HttpTransport>
+    ) -> Self {
+        let defaults: SupabaseConfig = SupabaseConfig::default();
+
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            key: key.to_string(),
+            rest_path: defaults.rest_path,
+            table: defaults.table,
+            batch_size: defaults.batch_size,
+            page_size: defaults.page_size,
+            transport
+        }
+    }
+
+    /// # Creates a `SupabaseStore` from the `Supabase` section of the configuration.
+    ///
+    /// An empty `Url` or `Key` is read from the `SUPABASE_URL` / `SUPABASE_KEY` environment variables.
+    ///
+    /// ### Errors
+    /// `missing_database_credentials` will be returned if the URL or the key is missing from both.
+    pub fn from_config(
+        config: &SupabaseConfig
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let url: String = credential(&config.url, "SUPABASE_URL").ok_or(ErrorsSpread::MissingDatabaseCredentials)?;
+        let key: String = credential(&config.key, "SUPABASE_KEY").ok_or(ErrorsSpread::MissingDatabaseCredentials)?;
+
+        Ok(
+            Self::new(&url, &key)?
+                .rest_path(&config.rest_path)
+                .table(&config.table)
+                .batch_size(config.batch_size)
+                .page_size(config.page_size)
+        )
+    }
+
+    /// Sets the path of the REST API, `/rest/v1` for Supabase and `""` for a plain PostgREST server.
+    pub fn rest_path(mut self, rest_path: &str) -> Self {
+        self.rest_path = rest_path.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Sets the table the quotes are written to.
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = table.to_string();
+        self
+    }
+
+    /// Sets the maximum number of rows per request, `0` is treated as `1`.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Sets the maximum number of rows read per request, `0` is treated as `1`.
+    ///
+    /// It must not be above the `max-rows` of the server, a page cut short by the server ends the read.
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Returns the URL of the table.
+    pub fn table_url(&self) -> String {
+        format!("{}{}/{}", self.url, self.rest_path, self.table)
+    }
+
+    /// Adds the `apikey` and `Authorization` headers Supabase expects on every request.
+    fn authorize(
+        &self,
+        request: HttpRequest
+    ) -> HttpRequest {
+        request
+            .header("apikey", &self.key)
+            .header("Authorization", &format!("Bearer {}", self.key))
+    }
+
+    /// Returns the table of the aggregates.
+    pub fn aggregates_table(&self) -> String {
+        format!("{}_aggregates", self.table)
+    }
+
+    /// # Builds the URL of a table or view, with the filter on the time column, and on the resolution of aggregates.
+    fn filter_url(
+        &self,
+        relation: &str,
+        filter: &QuoteFilter,
+        time_column: &str,
+        resolution: Option<Resolution>
+    ) -> Result<Url, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut url: Url = Url::parse(&format!("{}{}/{}", self.url, self.rest_path, relation))?;
+        {
+            let mut query = url.query_pairs_mut();
+            if let Some(resolution) = resolution {
+                query.append_pair("resolution", &format!("eq.{}", resolution.as_str()));
+            }
+            if let Some(broker) = &filter.broker {
+                query.append_pair("broker", &format!("eq.{}", broker));
+            }
+            if let Some(symbol) = &filter.symbol {
+                query.append_pair("symbol", &format!("eq.{}", symbol));
+            }
+            if let Some(from) = filter.from {
+                query.append_pair(time_column, &format!("gte.{}", from.to_rfc3339()));
+            }
+            if let Some(to) = filter.to {
+                query.append_pair(time_column, &format!("lt.{}", to.to_rfc3339()));
+            }
+        }
+
+        Ok(url)
+    }
+
+    /// # Reads the rows of the filtered URL, in the given order, a page at a time.
+    ///
+    /// PostgREST caps every response at its `max-rows`, so the rows are read with `limit` and `offset`
+    /// until a page comes back short. The order must be a total one for the pages not to overlap.
+    async fn select<T: DeserializeOwned>(
+        &self,
+        mut url: Url,
+        columns: &str,
+        order: &str,
+        limit: Option<usize>
+    ) -> Result<Vec<T>, Box<dyn StdError + Send + Sync + 'static>> {
+        url.query_pairs_mut()
+            .append_pair("select", columns)
+            .append_pair("order", order);
+
+        let mut rows: Vec<T> = Vec::new();
+        loop {
+            let wanted: usize = limit.map_or(self.page_size, |limit| self.page_size.min(limit - rows.len()));
+            let mut page_url: Url = url.clone();
+            page_url.query_pairs_mut()
+                .append_pair("limit", &wanted.to_string())
+                .append_pair("offset", &rows.len().to_string());
+
+            let request: HttpRequest = self.authorize(HttpRequest::get(page_url.as_str()))
+                .header("Accept", "application/json");
+            let response: HttpResponse = self.transport.send(request).await?;
+            if !response.is_success() {
+                error!("Supabase rejected a query on {} with status {}: {}", url.path(), response.status, response.body);
+                return Err(Box::new(HttpStatusError { status: response.status, retry_after: None }));
+            }
+
+            let page: Vec<T> = serde_json::from_str(&response.body)?;
+            let last: bool = page.len() < wanted;
+            rows.extend(page);
+            if last || limit.is_some_and(|limit| rows.len() >= limit) {
+                break;
+            }
+        }
+
+        Ok(rows)
+    }
+
+    /// # Deletes the rows of the filtered URL, and returns how many were deleted.
+    ///
+    /// The count comes from the `Content-Range` header PostgREST sends with `Prefer: count=exact`.
+    async fn delete(
+        &self,
+        url: Url
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let request: HttpRequest = self.authorize(HttpRequest::with_body("DELETE", url.as_str(), String::new()))
+            .header("Prefer", "count=exact,return=minimal");
+        let response: HttpResponse = self.transport.send(request).await?;
+        if !response.is_success() {
+            error!("Supabase rejected a delete on {} with status {}: {}", url.path(), response.status, response.body);
+            return Err(Box::new(HttpStatusError { status: response.status, retry_after: None }));
+        }
+
+        // `Content-Range: */42`, the total after the slash
+        let deleted: usize = response.header("content-range")
+            .and_then(|range| range.rsplit('/').next())
+            .and_then(|total| total.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        Ok(deleted)
+    }
+
+    /// # Upserts the rows in batches on the conflict columns, and returns how many rows were written.
+    async fn upsert<T: Serialize>(
+        &self,
+        table: &str,
+        conflict_columns: &str,
+        rows: &[T]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let url: String = format!("{}{}/{}?on_conflict={}", self.url, self.rest_path, table, conflict_columns);
+        let mut written: usize = 0;
+
+        for batch in rows.chunks(self.batch_size) {
+            let request: HttpRequest = self.authorize(HttpRequest::with_body("POST", &url, serde_json::to_string(batch)?))
+                .header("Content-Type", "application/json")
+                .header("Prefer", "resolution=merge-duplicates,return=minimal");
+
+            let response: HttpResponse = self.transport.send(request).await?;
+            if !response.is_success() {
+                error!("Supabase rejected a batch of {} row(s) with status {}: {}", batch.len(), response.status, response.body);
+                return Err(Box::new(HttpStatusError { status: response.status, retry_after: None }));
+            }
+
+            written += batch.len();
+        }
+        info!("Upserted {} row(s) into {}", written, table);
+
+        Ok(written)
+    }
+}
+
+
+#[async_trait]
+impl Db for SupabaseStore {
+    /// # Upserts the quotes in batches, and returns how many rows were written.
+    ///
+    /// A quote repeated within the call is written once, the last one wins,
+    /// since Postgres rejects an upsert that touches the same row twice.
+    /// If a batch is rejected, the batches written before it are kept.
+    async fn save_quotes(
+        &self,
+        quotes: &[Quote]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let rows: Vec<&Quote> = dedupe_quotes(quotes);
+        self.upsert(&self.table, CONFLICT_COLUMNS, &rows).await
+    }
+
+    async fn query_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        let url: Url = self.filter_url(&self.table, filter, "ts", None)?;
+        self.select(url, QUOTE_COLUMNS, "ts.asc,broker.asc,symbol.asc", filter.limit).await
+    }
+
+    /// # Returns the latest quote of every pair matching the filter.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use chrono::{ TimeZone, Utc };
+    /// use spread_tracker::db::storage::{ Db, QuoteFilter };
+    /// use spread_tracker::db::supabase::SupabaseStore;
+    /// use spread_tracker::utils::transport::{ Cassette, HttpRequest, HttpResponse, ReplayTransport };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let page = |offset: usize| format!(
+    ///     "http://localhost:3000/rpc/spread_quotes_latest_between?to_ts=2024-05-01T12%3A06%3A00%2B00%3A00\
+    ///      &select=ts%2Cbroker%2Csymbol%2Cask%2Cbid%2Cspread&order=broker.asc%2Csymbol.asc&limit=2&offset={}",
+    ///     offset
+    /// );
+    /// let row = |broker: &str, symbol: &str| format!(
+    ///     r#"{{"ts":"2024-05-01T12:05:00Z","broker":"{}","symbol":"{}","ask":1.0712,"bid":1.0714,"spread":0.2}}"#,
+    ///     broker,
+    ///     symbol
+    /// );
+    ///
+    /// // The function returns one row per pair, three pairs come in two pages.
+    /// let mut cassette = Cassette::new();
+    /// cassette.record(
+    ///     HttpRequest::get(&page(0)),
+    ///     HttpResponse::new(200, &format!("[{},{}]", row("fxpro", "EURUSD"), row("fxpro", "GBPUSD")))
+    /// );
+    /// cassette.record(HttpRequest::get(&page(2)), HttpResponse::new(200, &format!("[{}]", row("pepperstone", "EURUSD"))));
+    ///
+    /// let store = SupabaseStore::with_transport("http://localhost:3000", "anon-key", Arc::new(ReplayTransport::new(cassette)))
+    ///     .rest_path("")
+    ///     .page_size(2);
+    ///
+    /// let at = Utc.with_ymd_and_hms(2024, 5, 1, 12, 6, 0).unwrap();
+    /// let quotes = store.latest_quotes(&QuoteFilter::new().to(at)).await.unwrap();
+    /// assert_eq!(quotes.len(), 3);
+    /// assert_eq!(quotes[2].broker, "pepperstone");
+    /// # }
+    /// ```
+    async fn latest_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        // Without a time range the view already holds the latest quote of every pair
+        if filter.from.is_none() && filter.to.is_none() {
+            let url: Url = self.filter_url(&format!("{}_latest", self.table), filter, "ts", None)?;
+            return self.select(url, QUOTE_COLUMNS, "broker.asc,symbol.asc", None).await;
+        }
+
+        // With one, the function picks the latest quote of every pair within it, the broker and symbol filter its rows
+        let pairs: QuoteFilter = QuoteFilter {
+            from: None,
+            to: None,
+            ..filter.clone()
+        };
+        let mut url: Url = self.filter_url(&format!("rpc/{}_latest_between", self.table), &pairs, "ts", None)?;
+        {
+            let mut query = url.query_pairs_mut();
+            if let Some(from) = filter.from {
+                query.append_pair("from_ts", &from.to_rfc3339());
+            }
+            if let Some(to) = filter.to {
+                query.append_pair("to_ts", &to.to_rfc3339());
+            }
+        }
+        self.select(url, QUOTE_COLUMNS, "broker.asc,symbol.asc", None).await
+    }
+
+    async fn delete_quotes(
+        &self,
+        filter: &QuoteFilter
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        // PostgREST refuses a DELETE without a filter, an empty filter matches every row explicitly
+        let mut url: Url = self.filter_url(&self.table, filter, "ts", None)?;
+        if url.query().is_none_or(|query| query.is_empty()) {
+            url.query_pairs_mut().append_pair("ts", "not.is.null");
+        }
+
+        self.delete(url).await
+    }
+
+    async fn save_aggregates(
+        &self,
+        aggregates: &[SpreadAggregate]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        self.upsert(&self.aggregates_table(), AGGREGATE_CONFLICT_COLUMNS, aggregates).await
+    }
+
+    async fn query_aggregates(
+        &self,
+        resolution: Resolution,
+        filter: &QuoteFilter
+    ) -> Result<Vec<SpreadAggregate>, Box<dyn StdError + Send + Sync + 'static>> {
+        let url: Url = self.filter_url(&self.aggregates_table(), filter, "bucket", Some(resolution))?;
+        self.select(url, AGGREGATE_COLUMNS, "bucket.asc,broker.asc,symbol.asc", filter.limit).await
+    }
+
+    async fn delete_aggregates(
+        &self,
+        resolution: Resolution,
+        filter: &QuoteFilter
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let url: Url = self.filter_url(&self.aggregates_table(), filter, "bucket", Some(resolution))?;
+        self.delete(url).await
+    }
+}
+
+
+/// Returns the configured value, or the environment variable when the value is empty.
+fn credential(
+    configured: &str,
+    variable: &str
+) -> Option<String> {
+    if !configured.is_empty() {
+        return Some(configured.to_string());
+    }
+
+    std::env::var(variable).ok().filter(|value| !value.is_empty())
+}
@@ -16,6 +16,14 @@
 // couldn't retrieve spread data from the broker URL
 // failed to bind the json object to the key
 
+// the broker answered with an anti-bot challenge page
+// the broker answered with a captcha page
+// the broker answered with a login wall
+// the broker answered with an error page
+// the broker is backed off after answering with a block page
+
+// the database url or key is missing from the configuration and the environment
+
 #![allow(clippy::new_ret_no_self)]
 
 use std::fmt::{
@@ -25,11 +33,12 @@ use std::fmt::{
     Result
 };
 use std::error::Error as StdError; // we import as StdError to avoid conflicts with our Error enum
+use std::time::Duration;
 
 
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorsSpread {
     UrlInvalid,
     UrlNotReachable,
@@ -47,6 +56,12 @@ pub enum ErrorsSpread {
     FailedToParseSpread,
     CouldNotRetrieveSpreadData,
     FailedToBindJsonObjectToKey,
+    AntiBotChallenge,
+    CaptchaRequired,
+    LoginRequired,
+    ErrorPage,
+    BrokerBackedOff,
+    MissingDatabaseCredentials,
 }
 
 
@@ -72,7 +87,13 @@ impl Display for ErrorsSpread {
             ErrorsSpread::FailedToParseBidPrice => write!(f, "failed to bind the json object to the key"),
             ErrorsSpread::FailedToParseSpread => write!(f, "failed to bind the json object to the key"),
             ErrorsSpread::CouldNotRetrieveSpreadData => write!(f, "failed to bind the json object to the key"),
-            ErrorsSpread::FailedToBindJsonObjectToKey => write!(f, "failed to bind the json object to the key")
+            ErrorsSpread::FailedToBindJsonObjectToKey => write!(f, "failed to bind the json object to the key"),
+            ErrorsSpread::AntiBotChallenge => write!(f, "the broker answered with an anti-bot challenge page"),
+            ErrorsSpread::CaptchaRequired => write!(f, "the broker answered with a captcha page"),
+            ErrorsSpread::LoginRequired => write!(f, "the broker answered with a login wall"),
+            ErrorsSpread::ErrorPage => write!(f, "the broker answered with an error page"),
+            ErrorsSpread::BrokerBackedOff => write!(f, "the broker is backed off after answering with a block page"),
+            ErrorsSpread::MissingDatabaseCredentials => write!(f, "the database url or key is missing from the configuration and the environment")
         }
     }
 }
@@ -98,8 +119,60 @@ impl ErrorsSpread {
             ErrorsSpread::FailedToParseBidPrice => "failed to bind the json object to the key",
             ErrorsSpread::FailedToParseSpread => "failed to bind the json object to the key",
             ErrorsSpread::CouldNotRetrieveSpreadData => "failed to bind the json object to the key",
-            ErrorsSpread::FailedToBindJsonObjectToKey => "failed to bind the json object to the key"
+            ErrorsSpread::FailedToBindJsonObjectToKey => "failed to bind the json object to the key",
+            ErrorsSpread::AntiBotChallenge => "the broker answered with an anti-bot challenge page",
+            ErrorsSpread::CaptchaRequired => "the broker answered with a captcha page",
+            ErrorsSpread::LoginRequired => "the broker answered with a login wall",
+            ErrorsSpread::ErrorPage => "the broker answered with an error page",
+            ErrorsSpread::BrokerBackedOff => "the broker is backed off after answering with a block page",
+            ErrorsSpread::MissingDatabaseCredentials => "the database url or key is missing from the configuration and the environment"
         }
     }
 
-}
\ No newline at end of file
+}
+
+
+impl ErrorsSpread {
+    /// Returns `true` for the kinds reported when a broker answered with a block page instead of quotes.
+    pub fn is_block_page(
+        &self
+    ) -> bool {
+        matches!(
+            self,
+            ErrorsSpread::AntiBotChallenge | ErrorsSpread::CaptchaRequired | ErrorsSpread::LoginRequired | ErrorsSpread::ErrorPage
+        )
+    }
+}
+
+
+impl StdError for ErrorsSpread {}
+
+
+/// # `HttpStatusError` is returned when a broker page answers with a non-success status code.
+///
+/// It keeps the `Retry-After` delay sent by the server, so the retry policy can honor it.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::errors::HttpStatusError;
+///
+/// let error = HttpStatusError { status: 503, retry_after: None };
+/// assert_eq!(error.to_string(), "broker page answered with status 503");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpStatusError {
+    pub status: u16,
+    pub retry_after: Option<Duration>,
+}
+
+impl Display for HttpStatusError {
+    fn fmt(
+        &self,
+        f: &mut Formatter
+    ) -> Result {
+        write!(f, "broker page answered with status {}", self.status)
+    }
+}
+
+impl StdError for HttpStatusError {}
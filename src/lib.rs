@@ -43,13 +43,16 @@
 //! </div>
 //!
 //!
-//! ```rust
-//! use spread_tracker::config::SpreadBrokerUrl;
-//! use spread_tracker::model::{
-//!     Symbol,
-//!     SymbolSpread
+//! ```no_run
+//! use spread_tracker::SpreadTracker;
+//! use spread_tracker::config::{
+//!     SpreadBrokerUrl,
+//!     Brokers
 //! };
+//! use serde_json::Value;
 //!
+//! # #[tokio::main]
+//! # async fn main() {
 //! let config: SpreadBrokerUrl = SpreadBrokerUrl::new();
 //!
 //! // In this example, we are tracking the spread of the symbols from the Vantage and EightCap brokers.
@@ -65,9 +68,12 @@
 //! ).await.unwrap();
 //!
 //! println!("Spread: {:#?}", spread);
+//! # }
+//! ```
 //!
 //! Result:
 //!
+//! ```json
 //! {
 //!  "spread": {
 //!    "eightcap": [
@@ -94,9 +100,12 @@
 //!
 //! * `spread` is the key for the spread data.
 //! * `broker` is the key for the broker name, which will differ based on the broker.
+//! * `fetch` is only returned by `SpreadTracker::fetch_spread`, it is the key for how each broker page was fetched:
+//!   number of attempts, last status code, last error, whether the quotes came from the cache, and when they were downloaded.
+//!   `get_spread` returns the `spread` key alone.
 //!
 //! - Notes:
-//! A Vector of objects is sometimes referred to as a list of objects. It is a collection of objects that are stored in no particular order.
+//!   A Vector of objects is sometimes referred to as a list of objects. It is a collection of objects that are stored in no particular order.
 //!
 //! ### Configuration
 //! The `config.yaml` is used to store the broker URLs for the spread tracking, and the `model.rs` is used to store the data model for the spread tracking. The `config.yaml` file should be in the following format:
@@ -111,12 +120,19 @@
 //!
 //!
 //! ### Caching
-//! wip
-//! You can use the `db` module to store the spread data in a database.
-//! Currently, the library relies on `supabase_rs` to store the data in a Supabase database.
+//! The parsed quotes of every broker are kept in a snapshot cache, shared by every tracker built with `SpreadTracker::new`.
+//! Calling `get_spread` again within the TTL does not send a request, and past the TTL the last quotes are served
+//! right away while a refresh runs in the background. Concurrent calls for the same broker share a single fetch.
+//! The TTL and the stale-while-revalidate window are read from the optional `Cache` section of `spread_config.yaml`.
 //!
-//! If you want to use a different database, you can implement the `Db` trait for your database.
-//! Or simply call the `store_spread` method in the `model.rs` file to store the spread data in your database via a manual file like `.json`
+//!
+//! ### HTTP client
+//! Every broker page is downloaded through one shared `reqwest::Client`, built by `utils::request_builder::RequestBuilder`.
+//! Timeouts, user agent, default headers, compression, cookies, redirects and proxy are read from the optional `Http` section of `spread_config.yaml`.
+//! Use `SpreadTracker::with_client` to hand the tracker a client of your own.
+//!
+//! Refreshing a page that was fetched before sends its `ETag` / `Last-Modified` back as `If-None-Match` / `If-Modified-Since`.
+//! A `304 Not Modified` reuses the quotes parsed last time, and is counted in `SpreadTracker::fetch_stats`.
 //!
 //!
 //! ### Asynchronous vs Synchronous API
@@ -126,12 +142,20 @@
 //!
 //!
 //! ### Database
-//! wip
 //! You can use the `db` module to store the spread data in a database.
-//! Currently, the library relies on `supabase_rs` to store the data in a Supabase database.
+//! `db::supabase::SupabaseStore` upserts the quotes of every snapshot into a Supabase table, in batches,
+//! with the credentials of the optional `Supabase` section of `spread_config.yaml`. The table schema is documented in `db::supabase`.
 //!
-//! If you want to use a different database, you can implement the `Db` trait for your database.
+//! `db::retention::RetentionPolicy` rolls quotes older than the `Retention` section allows into per-minute, hourly and daily
+//! aggregates, and purges what is past its retention, on every backend.
+//!
+//! `db::history::History` reads it back: the spreads of a symbol across brokers, the snapshot as of a time,
+//! and series at any interval, over the raw quotes and the rolled up history together.
+//!
+//! If you want to use a different database, you can implement the `db::storage::Db` trait for your database,
+//! `db::memory::MemoryDb` is an in-memory implementation for tests.
 //! Or simply call the `store_spread` method in the `model.rs` file to store the spread data in your database via a manual file like `.json`
+//! Without a database, `utils::save_to_json::NdjsonWriter` appends the quotes to rotating NDJSON files, see the `Ndjson` section.
 //!
 //! ### Errors
 //!
@@ -155,11 +179,19 @@
 //! * `failed_to_parse_spread` will be returned if the spread could not be parsed from the string.
 //! * `could_not_retrieve_spread_data` will be returned if the spread data could not be retrieved from the broker URL.
 //! * `failed_to_bind_json_object_to_key` will be returned if the JSON object could not be bound to the key.
+//! * `anti_bot_challenge` will be returned if the broker answered with an anti-bot challenge page.
+//! * `captcha_required` will be returned if the broker answered with a captcha page.
+//! * `login_required` will be returned if the broker answered with a login wall.
+//! * `error_page` will be returned if the broker answered with an error page.
+//! * `broker_backed_off` will be returned if the broker is skipped after answering with a block page.
+//! * `missing_database_credentials` will be returned if the database URL or key is missing from the configuration and the environment.
 //!
 //!
 //!
 //! ### Modules
 //! - `db`: This module is used to store the spread data in a database.
+//! - `alerts`: This module is used to alert on the spreads, with rules from the configuration delivered through log, webhook or command channels.
+//! - `analytics`: This module is used to compare the spreads, like ranking the brokers of a symbol or their statistics over time.
 //! - `caching`: This module is used to cache the spread data to minimize the number of requests to the broker.
 //! - `utils`: This module is used to save the spread data to a `.json` file.
 //! - `config`: This module is used to load the configuration from the `config.yaml` file.
@@ -177,6 +209,8 @@
 #![allow(rustdoc::invalid_html_tags)]
 
 // import the necessary modules into the hierarchy
+pub mod alerts;
+pub mod analytics;
 pub mod caching;
 pub mod db;
 pub mod utils;
@@ -189,13 +223,24 @@ use core::error;
 use std::fs::File;
 use std::io::Write;
 use std::error::Error as StdError;
-use reqwest::get;
+use std::sync::{ Arc, Mutex };
+use reqwest::Client;
 use serde_json::{ Value, Map };
 
 // import the necessary modules into the hierarchy
 use crate::utils::regex_finder::find_symbol_spread;
-use crate::model::{ Symbol, SymbolSpread };
-use crate::config::{ SpreadBrokerUrl, Brokers };
+use crate::utils::request_builder::shared_client;
+use crate::utils::retry::{ RetryPolicy, ErrorClass, classify_error, parse_retry_after };
+use crate::utils::rate_limiter::RateLimiter;
+use crate::utils::block_detection::{ BlockBackoff, BlockKind, detect_block };
+use crate::utils::archive::{ ArchivedResponse, ResponseArchive };
+use crate::utils::transport::{ HttpRequest, HttpResponse, HttpTransport, ReqwestTransport };
+use crate::utils::conditional::{ ValidatedSnapshot, ValidatorStore, Validators };
+use crate::utils::fetch_stats::FetchStats;
+use crate::caching::snapshot_cache::{ CacheLookup, CacheStatus, SnapshotCache };
+use crate::model::{ Symbol, SymbolSpread, BrokerFetch, BrokerSnapshot };
+use crate::config::{ SpreadBrokerUrl, Brokers, RetryConfig, ArchiveConfig, CacheConfig };
+use crate::errors::{ ErrorsSpread, HttpStatusError };
 use crate::utils::format::{ wrap_json_under_key, vec_to_json, extract_broker_name };
 
 use tracing::{ info, warn, error };
@@ -203,33 +248,277 @@ use tracing::{ info, warn, error };
 /// ### The `SpreadTracker` struct is used to track the spread of various symbols in the forex market.
 ///
 /// This struct is used to track the spread of various symbols in the forex market.
+/// It owns the HTTP transport used for every broker page, by default a shared `reqwest` client so the connection pool is reused between requests,
+/// and the retry policy applied when a broker page fails to download.
+/// Any `HttpTransport` can be plugged in, like the record / replay transports of `utils::transport` for offline tests.
+/// Every request goes through the per-host rate limiter, which is shared process-wide unless `with_rate_limiter` is used.
+/// A broker that answers with a challenge, captcha, login wall or error page is skipped for a while, see `utils::block_detection`.
+/// Raw responses are only written to disk when an archive is configured, see `utils::archive`.
+/// Parsed quotes are served from the snapshot cache when one is set, see `caching`.
+/// A page that was fetched before is requested conditionally, a `304 Not Modified` reuses the previous quotes, see `utils::conditional`.
+///
+/// ### Example
 ///
+/// ```no_run
+/// use spread_tracker::SpreadTracker;
+/// use spread_tracker::config::{ SpreadBrokerUrl, Brokers };
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let tracker: SpreadTracker = SpreadTracker::new(SpreadBrokerUrl::new()).unwrap();
+///
+/// let spread = tracker.fetch_spread(vec![Brokers::Vantage]).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
 pub struct SpreadTracker {
     spread_broker_url: SpreadBrokerUrl,
-    spread: SymbolSpread,
+    transport: Arc<dyn HttpTransport>,
+    retry: RetryPolicy,
+    rate_limiter: Arc<RateLimiter>,
+    block_backoff: Arc<BlockBackoff>,
+    archive: Option<Arc<ResponseArchive>>,
+    cache: Option<Arc<SnapshotCache>>,
+    validators: Arc<ValidatorStore>,
+    stats: Arc<FetchStats>,
 }
 
 impl SpreadTracker {
-    /// The `get_spreads` function is used to get the spread of various symbols from the broker URL.
+    /// # Creates a `SpreadTracker` that uses the process-wide HTTP client.
     ///
-    /// This function is used to get the spread of various symbols from the broker URL.
+    /// The client is built once from the `Http` section of the `spread_config.yaml` file, see `utils::request_builder`.
+    /// The retry policy is read from the `Retry` section, see `utils::retry`,
+    /// and the optional response archive from the `Archive` section, see `utils::archive`.
+    /// Unless the `Cache` section disables it, the tracker shares the process-wide snapshot cache, see `caching`.
+    ///
+    /// ### Errors
+    /// `failed_to_read_yaml` will be returned if the `Http`, `Retry`, `Archive` or `Cache` section is not in the correct format.
+    ///
+    pub fn new(
+        config: SpreadBrokerUrl
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let retry: RetryPolicy = RetryPolicy::from_config(&RetryConfig::load()?);
+        let mut tracker: SpreadTracker = SpreadTracker::with_client(config, shared_client()?).with_retry_policy(retry);
+
+        if let Some(archive) = ResponseArchive::from_config(&ArchiveConfig::load()?) {
+            tracker = tracker.with_archive(archive);
+        }
+
+        if CacheConfig::load()?.enabled {
+            tracker = tracker.with_cache(SnapshotCache::global());
+        }
+
+        Ok(tracker)
+    }
+
+    /// # Creates a `SpreadTracker` that uses the given HTTP client.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use spread_tracker::SpreadTracker;
+    /// use spread_tracker::config::SpreadBrokerUrl;
+    /// use spread_tracker::utils::request_builder::RequestBuilder;
+    ///
+    /// let client = RequestBuilder::new().user_agent("my-desk/1.0").build().unwrap();
+    /// let tracker = SpreadTracker::with_client(SpreadBrokerUrl::new(), client);
+    /// ```
+    pub fn with_client(
+        config: SpreadBrokerUrl,
+        client: Client
+    ) -> Self {
+        SpreadTracker::with_transport(config, Arc::new(ReqwestTransport::new(client)))
+    }
+
+    /// # Creates a `SpreadTracker` that sends every request through the given transport.
+    ///
+    /// See `utils::transport::ReplayTransport` for a tracker that runs against recorded pages, without the internet.
+    pub fn with_transport(
+        config: SpreadBrokerUrl,
+        transport: Arc<dyn HttpTransport>
+    ) -> Self {
+        SpreadTracker {
+            spread_broker_url: config,
+            transport,
+            retry: RetryPolicy::default(),
+            rate_limiter: RateLimiter::global(),
+            block_backoff: BlockBackoff::global(),
+            archive: None,
+            cache: None,
+            validators: ValidatorStore::global(),
+            stats: FetchStats::global(),
+        }
+    }
+
+    /// # Replaces the retry policy applied to every broker fetch.
     ///
     /// ### Example
     ///
     /// ```
     /// use spread_tracker::SpreadTracker;
     /// use spread_tracker::config::SpreadBrokerUrl;
+    /// use spread_tracker::utils::retry::RetryPolicy;
+    ///
+    /// let tracker = SpreadTracker::new(SpreadBrokerUrl::new())
+    ///     .unwrap()
+    ///     .with_retry_policy(RetryPolicy::new().max_attempts(5));
+    /// ```
+    pub fn with_retry_policy(
+        mut self,
+        retry: RetryPolicy
+    ) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// # Replaces the rate limiter applied to every request of the tracker.
+    ///
+    /// By default the tracker shares the process-wide limiter, see `RateLimiter::global`.
+    /// A tracker with its own limiter does not count against the shared budget.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use spread_tracker::SpreadTracker;
+    /// use spread_tracker::config::SpreadBrokerUrl;
+    /// use spread_tracker::utils::rate_limiter::RateLimiter;
+    ///
+    /// let tracker = SpreadTracker::new(SpreadBrokerUrl::new())
+    ///     .unwrap()
+    ///     .with_rate_limiter(Arc::new(RateLimiter::new(0.2, 1)));
+    /// ```
+    pub fn with_rate_limiter(
+        mut self,
+        rate_limiter: Arc<RateLimiter>
+    ) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// # Replaces the backoff applied to brokers that answered with a block page.
+    ///
+    /// By default the tracker shares the process-wide backoff, see `BlockBackoff::global`.
+    pub fn with_block_backoff(
+        mut self,
+        block_backoff: Arc<BlockBackoff>
+    ) -> Self {
+        self.block_backoff = block_backoff;
+        self
+    }
+
+    /// # Archives every raw broker response, see `utils::archive`.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use spread_tracker::SpreadTracker;
+    /// use spread_tracker::config::SpreadBrokerUrl;
+    /// use spread_tracker::utils::archive::ResponseArchive;
+    ///
+    /// let tracker = SpreadTracker::new(SpreadBrokerUrl::new())
+    ///     .unwrap()
+    ///     .with_archive(ResponseArchive::new("archive").max_entries_per_broker(Some(50)));
+    /// ```
+    pub fn with_archive(
+        mut self,
+        archive: ResponseArchive
+    ) -> Self {
+        self.archive = Some(Arc::new(archive));
+        self
+    }
+
+    /// # Serves the parsed quotes of every broker from the given cache, see `caching`.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use spread_tracker::SpreadTracker;
+    /// use spread_tracker::caching::snapshot_cache::SnapshotCache;
+    /// use spread_tracker::config::SpreadBrokerUrl;
+    /// use spread_tracker::utils::request_builder::RequestBuilder;
+    ///
+    /// let client = RequestBuilder::new().build().unwrap();
+    /// let tracker = SpreadTracker::with_client(SpreadBrokerUrl::new(), client)
+    ///     .with_cache(Arc::new(SnapshotCache::new(Duration::from_secs(30))));
+    /// ```
+    pub fn with_cache(
+        mut self,
+        cache: Arc<SnapshotCache>
+    ) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// # Replaces the store of the `ETag` / `Last-Modified` validators used for conditional requests.
+    ///
+    /// By default the tracker shares the process-wide store, see `ValidatorStore::global`.
+    pub fn with_validator_store(
+        mut self,
+        validators: Arc<ValidatorStore>
+    ) -> Self {
+        self.validators = validators;
+        self
+    }
+
+    /// # Replaces the statistics the tracker counts its requests in.
+    ///
+    /// By default the tracker shares the process-wide statistics, see `FetchStats::global`.
+    pub fn with_fetch_stats(
+        mut self,
+        stats: Arc<FetchStats>
+    ) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Returns the statistics the tracker counts its requests in.
+    pub fn fetch_stats(&self) -> &FetchStats {
+        self.stats.as_ref()
+    }
+
+    /// Returns the snapshot cache of the tracker, if any.
+    pub fn cache(&self) -> Option<&Arc<SnapshotCache>> {
+        self.cache.as_ref()
+    }
+
+    /// Returns the response archive of the tracker, if any.
+    pub fn archive(&self) -> Option<&ResponseArchive> {
+        self.archive.as_deref()
+    }
+
+    /// Returns the HTTP transport owned by the tracker.
+    pub fn transport(&self) -> &dyn HttpTransport {
+        self.transport.as_ref()
+    }
+
+    /// The `get_spreads` function is used to get the spread of various symbols from the broker URL.
     ///
-    /// let result = SpreadTracker::get_spreads(SpreadBrokerUrl::new());
+    /// This function is used to get the spread of various symbols from the broker URL.
+    /// It is a shorthand for `SpreadTracker::new(config)?.fetch_spread(brokers)`, without the `fetch` key:
+    /// the result only holds the `spread` key. Use `fetch_spread` to know how every broker was fetched.
+    ///
+    /// ### Example
+    ///
+    /// ```no_run
+    /// use spread_tracker::SpreadTracker;
+    /// use spread_tracker::config::{ SpreadBrokerUrl, Brokers };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let result = SpreadTracker::get_spread(SpreadBrokerUrl::new(), vec![Brokers::Vantage]).await;
     ///
     /// println!("Result: {:#?}", result);
-    /// Output:
-    /// {
-    ///    "ask": Number(0.90451),
-    ///    "bid": Number(1.37551),
-    ///    "spread": Number(0.471),
-    ///    "symbol": String("USDCHF"),
-    /// });
+    /// // Output:
+    /// // {
+    /// //    "ask": Number(0.90451),
+    /// //    "bid": Number(1.37551),
+    /// //    "spread": Number(0.471),
+    /// //    "symbol": String("USDCHF"),
+    /// // }
+    /// # }
     /// ```
     ///
     /// ### Errors
@@ -242,80 +531,375 @@ impl SpreadTracker {
     pub async fn get_spread(
         config: SpreadBrokerUrl,
         brokers: Vec<Brokers>
+    ) -> Result<Value, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut spread: Value = SpreadTracker::new(config)?.fetch_spread(brokers).await?;
+        if let Some(object) = spread.as_object_mut() {
+            object.remove("fetch");
+        }
+
+        Ok(spread)
+    }
+
+    /// # Gets the spread of various symbols from the given brokers, using the transport owned by the tracker.
+    ///
+    /// Every broker page is fetched with the retry policy of the tracker, unless the snapshot cache can answer.
+    /// How each broker was fetched is reported under the `fetch` key, next to the `spread` key.
+    /// `cache` is `fresh` or `stale` when the quotes were served from the cache, `miss` when they were fetched for this call,
+    /// and `null` without a cache. `fetched_at` is when the quotes were downloaded:
+    /// ```json
+    /// {
+    ///   "spread": { "vantage": [ ... ] },
+    ///   "fetch": {
+    ///     "vantage": {
+    ///       "broker": "vantage", "url": "https://...", "attempts": 2, "status": 200, "error": null,
+    ///       "cache": "miss", "fetched_at": "2024-05-02T09:30:00Z"
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    ///
+    /// ### Errors
+    /// `internal_error` will be returned if there is an internal error.
+    ///
+    pub async fn fetch_spread(
+        &self,
+        brokers: Vec<Brokers>
     ) -> Result<Value, Box<dyn StdError + Send + Sync + 'static>> {
         let mut all_broker_spreads: Map<String, Value> = serde_json::Map::new();
+        let mut all_broker_fetches: Map<String, Value> = serde_json::Map::new();
 
         for broker in brokers {
-            let url: String = config.get_url(broker.clone());
+            let url: String = self.spread_broker_url.get_url(broker.clone());
             let url: &str = url.as_str();
             info!("URL: {}", url);
 
             let name: String = extract_broker_name(url).unwrap_or_else(|_| broker.to_string());
 
-            let spread_tracker: Result<
-                String,
-                Box<dyn StdError + Send + Sync>
-            > = SpreadTracker::download_html_body(url).await;
-            info!("Spread Tracker: {:#?}", spread_tracker);
-
-            if let Ok(body) = spread_tracker {
-                if let Ok(results) = SpreadTracker::regex_find_symbol_spread(&body).await {
-                    if let Ok(json_output) = vec_to_json(results) {
-                        info!("JSON Output: {:#?}", json_output);
-                        // Wrap the JSON output under the broker's name
-                        all_broker_spreads.insert(name, json_output);
-                    }
-                }
+            let (snapshot, fetch): (
+                Result<BrokerSnapshot, Box<dyn StdError + Send + Sync>>,
+                BrokerFetch
+            ) = self.fetch_snapshot(&name, url).await;
+
+            all_broker_fetches.insert(name.clone(), serde_json::to_value(&fetch)?);
+
+            if let Ok(snapshot) = snapshot {
+                info!("JSON Output: {:#?}", snapshot.quotes);
+                // Wrap the JSON output under the broker's name
+                all_broker_spreads.insert(name, snapshot.quotes);
             }
         }
 
-        // Wrap all broker spreads under the key "spread"
-        let wrapped_all_broker_spreads: Value = serde_json::json!({ "spread": all_broker_spreads });
+        // Wrap all broker spreads under the key "spread", and how they were fetched under the key "fetch"
+        let wrapped_all_broker_spreads: Value = serde_json::json!({
+            "spread": all_broker_spreads,
+            "fetch": all_broker_fetches
+        });
 
         Ok(wrapped_all_broker_spreads)
     }
 
+    /// # Gets the parsed quotes of a single broker, as a snapshot that can be stored, see `db`.
+    ///
+    /// The snapshot is served from the cache of the tracker when it can answer.
+    ///
+    /// ### Errors
+    /// The error of the fetch will be returned if the page could not be fetched or parsed.
+    pub async fn snapshot(
+        &self,
+        broker: Brokers
+    ) -> Result<BrokerSnapshot, Box<dyn StdError + Send + Sync + 'static>> {
+        let url: String = self.spread_broker_url.get_url(broker.clone());
+        let name: String = extract_broker_name(&url).unwrap_or_else(|_| broker.to_string());
+
+        self.fetch_snapshot(&name, &url).await.0
+    }
+
     /// The `download_html_body` function is used to download the HTML body from the URL.
-    /// This function is used to download the HTML body from the URL.
+    /// This function is used to download the HTML body from the URL, through the process-wide HTTP client.
     ///
     /// ### Example
     ///
-    /// ```
+    /// ```no_run
     /// use spread_tracker::SpreadTracker;
     ///
-    /// let url = "https://www.vantagefx.com/trading-info/forex-market-hours/";
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let url = "https://www.myfxbook.com/forex-broker-quotes/vantage/6052";
     ///
-    /// let body = SpreadTracker::download_html_body(url);
+    /// let body = SpreadTracker::download_html_body(url).await.unwrap();
     ///
-    /// assert_eq!(body, "EURUSD 0.0001 1.1234 1.1233");
+    /// assert!(body.contains("Spread"));
+    /// # }
     /// ```
     pub async fn download_html_body(
         url: &str
     ) -> Result<String, Box<dyn StdError + Send + Sync + 'static>> {
-        // Download the HTML body
-        let response = get(url).await;
-        if response.is_err() {
-            return Err(Box::new(response.err().unwrap()));
+        let response: HttpResponse = SpreadTracker::download_with(
+            &ReqwestTransport::new(shared_client()?),
+            &RateLimiter::global(),
+            None,
+            HttpRequest::get(url)
+        ).await?;
+
+        Ok(response.body)
+    }
+
+    /// # Downloads the HTML body from the URL, through the transport and retry policy owned by the tracker.
+    ///
+    /// ### Errors
+    /// The error of the last attempt will be returned if every attempt failed, or if the failure is permanent.
+    pub async fn download(
+        &self,
+        url: &str
+    ) -> Result<String, Box<dyn StdError + Send + Sync + 'static>> {
+        let name: String = extract_broker_name(url).unwrap_or_else(|_| url.to_string());
+
+        self.fetch_page(&name, url, None).await.0.map(|response| response.body)
+    }
+
+    /// # Returns the parsed quotes of a broker, from the snapshot cache when the tracker has one.
+    ///
+    /// On a cache miss the page is fetched once, even when several callers ask for the same broker at the same time.
+    async fn fetch_snapshot(
+        &self,
+        name: &str,
+        url: &str
+    ) -> (Result<BrokerSnapshot, Box<dyn StdError + Send + Sync + 'static>>, BrokerFetch) {
+        let cache: Arc<SnapshotCache> = match &self.cache {
+            Some(cache) => cache.clone(),
+            None => return self.fetch_and_parse(name, url).await
+        };
+
+        // The fetch runs inside the cache, its record is handed back through the slot
+        let slot: Arc<Mutex<Option<BrokerFetch>>> = Arc::new(Mutex::new(None));
+        let fetch = {
+            let tracker: SpreadTracker = self.clone();
+            let slot: Arc<Mutex<Option<BrokerFetch>>> = slot.clone();
+            let (name, url): (String, String) = (name.to_string(), url.to_string());
+            move || async move {
+                let (snapshot, fetch) = tracker.fetch_and_parse(&name, &url).await;
+                *slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(fetch);
+                snapshot
+            }
+        };
+
+        let lookup: Result<CacheLookup, Box<dyn StdError + Send + Sync + 'static>> = cache.get_or_fetch(name, fetch).await;
+
+        // A background refresh may fill the slot too, only a miss reports the fetch made for this call
+        let fetched: Option<BrokerFetch> = slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        match lookup {
+            Ok(lookup) => {
+                let mut fetch: BrokerFetch = match (lookup.status, fetched) {
+                    (CacheStatus::Miss, Some(fetch)) => fetch,
+                    _ => BrokerFetch::new(name, url)
+                };
+                fetch.cache = Some(lookup.status);
+                fetch.fetched_at = Some(lookup.snapshot.fetched_at);
+                (Ok(lookup.snapshot), fetch)
+            },
+            Err(error) => {
+                let mut fetch: BrokerFetch = fetched.unwrap_or_else(|| BrokerFetch::new(name, url));
+                fetch.cache = Some(CacheStatus::Miss);
+                (Err(error), fetch)
+            }
         }
-        let text = response.unwrap().text().await;
-        if text.is_err() {
-            return Err(Box::new(text.err().unwrap()));
+    }
+
+    /// # Fetches a broker page and parses its quotes into a snapshot.
+    ///
+    /// A page fetched before is requested with its `ETag` / `Last-Modified`,
+    /// and a `304 Not Modified` reuses the previous snapshot without parsing anything.
+    ///
+    /// ### Errors
+    /// `failed_to_parse_symbol_spread` will be returned if the quotes could not be parsed.
+    async fn fetch_and_parse(
+        &self,
+        name: &str,
+        url: &str
+    ) -> (Result<BrokerSnapshot, Box<dyn StdError + Send + Sync + 'static>>, BrokerFetch) {
+        let previous: Option<ValidatedSnapshot> = self.validators.get(url);
+
+        let (response, mut fetch): (
+            Result<HttpResponse, Box<dyn StdError + Send + Sync>>,
+            BrokerFetch
+        ) = self.fetch_page(name, url, previous.as_ref().map(|previous| &previous.validators)).await;
+
+        let response: HttpResponse = match response {
+            Ok(response) => response,
+            Err(error) => return (Err(error), fetch)
+        };
+
+        let fetched_at: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
+
+        if response.status == 304 {
+            // Only a request that carried validators can be answered with a 304
+            let previous: ValidatedSnapshot = match previous {
+                Some(previous) => previous,
+                None => {
+                    let error: HttpStatusError = HttpStatusError { status: 304, retry_after: None };
+                    fetch.error = Some(error.to_string());
+                    return (Err(Box::new(error)), fetch);
+                }
+            };
+            info!("{} was not modified, reusing the previous snapshot", name);
+
+            let snapshot: BrokerSnapshot = BrokerSnapshot {
+                fetched_at,
+                ..previous.snapshot
+            };
+            self.validators.insert(url, previous.validators, snapshot.clone());
+            fetch.fetched_at = Some(fetched_at);
+            return (Ok(snapshot), fetch);
         }
-        let body: String = text.unwrap();
 
-        let file_result: Result<File, std::io::Error> = File::create("body.txt");
-        if file_result.is_err() {
-            error!("Failed to create file: {:#?}", file_result.as_ref().err().unwrap());
-            return Err(Box::new(file_result.err().unwrap()));
+        info!("Spread Tracker: {:#?}", response.status);
+        let quotes: Option<Value> = match SpreadTracker::regex_find_symbol_spread(&response.body).await {
+            Ok(results) => vec_to_json(results).ok(),
+            Err(_) => None
+        };
+
+        match quotes {
+            Some(quotes) => {
+                fetch.fetched_at = Some(fetched_at);
+                let snapshot: BrokerSnapshot = BrokerSnapshot {
+                    broker: name.to_string(),
+                    url: url.to_string(),
+                    quotes,
+                    fetched_at
+                };
+
+                match Validators::from_response(&response) {
+                    Some(validators) => self.validators.insert(url, validators, snapshot.clone()),
+                    None => self.validators.remove(url)
+                }
+
+                (Ok(snapshot), fetch)
+            },
+            None => {
+                let error: ErrorsSpread = ErrorsSpread::FailedToParseSymbolSpread;
+                fetch.error = Some(error.to_string());
+                (Err(Box::new(error)), fetch)
+            }
         }
-        let mut file: File = file_result.unwrap();
-        let write_result = write!(file, "{}", body);
-        if write_result.is_err() {
-            error!("Failed to write to file: {:#?}", write_result.as_ref().err().unwrap());
-            return Err(Box::new(write_result.err().unwrap()));
+    }
+
+    /// # Fetches a broker page with the retry policy, and records how many attempts it took.
+    ///
+    /// Retryable failures are retried after the backoff of the policy, or after the `Retry-After` of a 429 / 503.
+    /// Permanent failures, like a 404, are returned right away.
+    /// Block pages are permanent failures that also back the broker off, a backed off broker is not requested at all.
+    /// With validators, the request is conditional and a `304 Not Modified` is returned as a successful response.
+    async fn fetch_page(
+        &self,
+        name: &str,
+        url: &str,
+        validators: Option<&Validators>
+    ) -> (Result<HttpResponse, Box<dyn StdError + Send + Sync + 'static>>, BrokerFetch) {
+        let mut fetch: BrokerFetch = BrokerFetch::new(name, url);
+
+        if let Some(remaining) = self.block_backoff.remaining(url) {
+            let error: ErrorsSpread = ErrorsSpread::BrokerBackedOff;
+            info!("Skipping {}, backed off for another {:?}", name, remaining);
+            fetch.error = Some(format!("{} for another {}s", error, remaining.as_secs()));
+            return (Err(Box::new(error)), fetch);
         }
 
-        Ok(body)
+        loop {
+            fetch.attempts += 1;
+
+            let request: HttpRequest = match validators {
+                Some(validators) => validators.apply(HttpRequest::get(url)),
+                None => HttpRequest::get(url)
+            };
+
+            let error: Box<dyn StdError + Send + Sync + 'static> = match SpreadTracker::download_with(self.transport.as_ref(), &self.rate_limiter, self.archive.clone(), request).await {
+                Ok(response) => {
+                    match response.status {
+                        304 => self.stats.record_not_modified(),
+                        _ => self.stats.record_downloaded(response.body.len())
+                    }
+                    self.block_backoff.record_success(url);
+                    fetch.status = Some(response.status);
+                    fetch.error = None;
+                    return (Ok(response), fetch);
+                },
+                Err(error) => error
+            };
+            self.stats.record_failed();
+
+            fetch.status = error.downcast_ref::<HttpStatusError>().map(|status_error| status_error.status);
+            fetch.error = Some(error.to_string());
+
+            if error.downcast_ref::<ErrorsSpread>().is_some_and(|kind| kind.is_block_page()) {
+                self.block_backoff.record_block(url);
+                return (Err(error), fetch);
+            }
+
+            let class: ErrorClass = classify_error(error.as_ref());
+            if class == ErrorClass::Permanent || fetch.attempts >= self.retry.attempts() {
+                warn!("Giving up on {} after {} attempt(s): {}", name, fetch.attempts, error);
+                return (Err(error), fetch);
+            }
+
+            let delay: std::time::Duration = self.retry.delay_for(fetch.attempts, error.as_ref());
+            warn!("Attempt {} for {} failed: {}, retrying in {:?}", fetch.attempts, name, error, delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn download_with(
+        transport: &dyn HttpTransport,
+        rate_limiter: &RateLimiter,
+        archive: Option<Arc<ResponseArchive>>,
+        request: HttpRequest
+    ) -> Result<HttpResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        let url: String = request.url.clone();
+        let url: &str = url.as_str();
+
+        // Wait for a slot in the request budget of the host
+        rate_limiter.acquire_url(url).await;
+
+        // Download the HTML body
+        let response: HttpResponse = transport.send(request).await?;
+
+        let status: u16 = response.status;
+        let success: bool = response.is_success();
+        let retry_after: Option<std::time::Duration> = response.header("retry-after").and_then(parse_retry_after);
+
+        // Archive the raw response when asked to, a failing archive never fails the fetch
+        if let Some(archive) = archive {
+            let response: ArchivedResponse = ArchivedResponse {
+                broker: extract_broker_name(url).unwrap_or_else(|_| url.to_string()),
+                url: url.to_string(),
+                status,
+                headers: response.headers.clone(),
+                fetched_at: chrono::Utc::now(),
+                body: response.body.clone()
+            };
+            let stored = tokio::task::spawn_blocking(move || archive.store(&response).map_err(|error| error.to_string())).await;
+            match stored {
+                Ok(Ok(_)) => {},
+                Ok(Err(error)) => error!("Failed to archive the response of {}: {}", url, error),
+                Err(error) => error!("Failed to archive the response of {}: {}", url, error)
+            }
+        }
+
+        // The page did not change since the validators of the request, there is nothing to parse
+        if status == 304 {
+            return Ok(response);
+        }
+
+        // Recognize block pages before parsing, a plain 4xx / 5xx stays a status error so it can be retried
+        match (detect_block(status, &response.body), success) {
+            (None, true) => Ok(response),
+            (None, false) | (Some(BlockKind::ErrorPage), false) => {
+                Err(Box::new(HttpStatusError { status, retry_after }))
+            },
+            (Some(kind), _) => {
+                warn!("{} answered with a block page: {:?}", url, kind);
+                Err(Box::new(kind.to_error()))
+            }
+        }
     }
 
     /// The `regex_find_symbol_spread` function is used to find the symbol spread in the HTML body.
@@ -326,12 +910,14 @@ impl SpreadTracker {
     /// ```
     /// use spread_tracker::SpreadTracker;
     ///
-    /// let body = "<span></span> 1.1234 1.1233 0.0001Spread";
-    ///
-    /// let spread = SpreadTracker::regex_find_symbol_spread(& body);
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let body = "<span></span>\nAUDCAD\n0.90269\n1.40269\n<a id=\"5168_8Spread\"\n0.5";
     ///
-    /// assert_eq!(spread, vec!["1.1234 1.1233 0.0001Spread"]);
+    /// let spread = SpreadTracker::regex_find_symbol_spread(& body).await.unwrap();
     ///
+    /// assert_eq!(spread, vec!["AUDCAD 0.90269 1.40269 0.5"]);
+    /// # }
     /// ```
     ///
     /// ### Errors
@@ -1,26 +1,711 @@
 #![allow(unused_imports)]
 #![allow(clippy::single_component_path_imports)]
 
-pub mod utils;
-pub mod config;
-pub mod model;
-
 use spread_tracker::SpreadTracker;
-use spread_tracker::model::SymbolSpread;
-use spread_tracker::config::{ SpreadBrokerUrl, Brokers };
+use spread_tracker::alerts::channels::Notifier;
+use spread_tracker::alerts::rules::{ Alert, AlertEngine };
+use spread_tracker::analytics::anomaly::{ SpikeDetector, SpikeEvent };
+use spread_tracker::analytics::commission::{ AllInCost, CostModel };
+use spread_tracker::analytics::ranking::{ RankBy, Ranker, SymbolRanking };
+use spread_tracker::analytics::sessions::{ RolloverDetector, RolloverWidening, SessionCalendar, SessionStats };
+use spread_tracker::analytics::stats::{ SpreadStats, stored_stats };
+use spread_tracker::model::{ BrokerSnapshot, Quote, SymbolSpread };
+use spread_tracker::config::{ SpreadBrokerUrl, Brokers, NdjsonConfig, NdjsonRecord, RetentionConfig, StatsConfig, StorageBackend, StorageConfig, CommissionConfig, RolloverConfig, SpikeConfig, AlertConfig };
+use spread_tracker::db::history::{ History, SeriesPoint, parse_interval };
+use spread_tracker::db::retention::{ RetentionPolicy, RetentionReport };
+use spread_tracker::db::storage::{ open_storage, Db, QuoteFilter };
+use spread_tracker::utils::format::extract_broker_name;
+use spread_tracker::utils::parquet_export::ParquetExporter;
+use spread_tracker::utils::save_to_json::NdjsonWriter;
 
-use serde_json::Value;
+use chrono::{ DateTime, Duration, SubsecRound, Utc };
+use clap::{ Parser, Subcommand };
+use serde_json::{ Map, Value };
+use std::error::Error as StdError;
 use std::fs::File;
 use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use tracing_subscriber::EnvFilter;
 use tracing::{ info, warn, error };
 
+/// Spread tracker for forex pairs, without a command it fetches the spreads of every broker and logs them.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Exports quotes to Parquet files, partitioned by date and broker.
+    Export {
+        /// The directory the files are written under.
+        #[arg(long, default_value = "export")]
+        out: PathBuf,
+        /// Only exports the quotes of the broker.
+        #[arg(long)]
+        broker: Option<String>,
+        /// Only exports the quotes of the symbol.
+        #[arg(long)]
+        symbol: Option<String>,
+        /// Only exports the quotes fetched at or after the time, in RFC 3339.
+        #[arg(long)]
+        from: Option<DateTime<Utc>>,
+        /// Only exports the quotes fetched before the time, in RFC 3339.
+        #[arg(long)]
+        to: Option<DateTime<Utc>>,
+        /// Fetches every broker now and exports the fresh snapshots, instead of the stored quotes.
+        #[arg(long)]
+        live: bool
+    },
+    /// Rolls the stored quotes up and purges them, following the `Retention` section of the configuration.
+    Retention,
+    /// Ranks the brokers of a symbol from the cheapest, by spread in pips plus commission, or by spread alone.
+    Rank {
+        #[arg(long)]
+        symbol: String,
+        /// Ranks the latest stored quotes instead of fetching every broker now.
+        #[arg(long)]
+        stored: bool,
+        /// The age in seconds after which a quote is stale, 0 never flags a quote.
+        #[arg(long, default_value_t = 300)]
+        max_age_secs: u64,
+        /// The round-turn commission of a broker in pips, like `ic-markets=0.7`, repeatable.
+        /// It overrides the schedule of the broker in the `Commissions` section.
+        #[arg(long = "commission", value_parser = parse_commission)]
+        commissions: Vec<(String, f64)>,
+        /// The monthly volume of the account in lots, which picks the tier of tiered commissions,
+        /// instead of their `MonthlyVolumeLots`.
+        #[arg(long)]
+        monthly_volume: Option<f64>,
+        /// What to rank by, `all-in` for the spread plus the commission, or `spread`.
+        #[arg(long, default_value = "all-in", value_parser = parse_rank_by)]
+        by: RankBy,
+        /// Prints the ranking as JSON instead of a table.
+        #[arg(long)]
+        json: bool
+    },
+    /// Prints the all-in cost of a round-turn trade at every broker, spread and commission, in the account currency, cheapest first.
+    Cost {
+        #[arg(long)]
+        symbol: String,
+        /// The size of the trade, in standard lots.
+        #[arg(long, default_value_t = 1.0, value_parser = parse_lots)]
+        lots: f64,
+        /// The currency of the account.
+        #[arg(long, default_value = "USD")]
+        account: String,
+        /// The monthly volume of the account in lots, which picks the tier of tiered commissions,
+        /// instead of their `MonthlyVolumeLots`.
+        #[arg(long)]
+        monthly_volume: Option<f64>,
+        /// Uses the latest stored quotes instead of fetching every broker now.
+        #[arg(long)]
+        stored: bool,
+        /// Prints the costs as JSON instead of a table.
+        #[arg(long)]
+        json: bool
+    },
+    /// Prints the spread statistics of every broker and symbol over the last windows, in pips, as JSON.
+    Stats {
+        /// Only the quotes of the broker.
+        #[arg(long)]
+        broker: Option<String>,
+        /// Only the quotes of the symbol.
+        #[arg(long)]
+        symbol: Option<String>,
+        /// A window like 1h, 1d or 1w, repeatable. The `Stats` section gives them by default.
+        #[arg(long = "window")]
+        windows: Vec<String>
+    },
+    /// Prints the spread statistics of the stored quotes by FX session, and the widening around every rollover, as JSON.
+    Sessions {
+        /// Only the quotes of the broker.
+        #[arg(long)]
+        broker: Option<String>,
+        /// Only the quotes of the symbol.
+        #[arg(long)]
+        symbol: Option<String>,
+        /// The window before now, like 1d or 1w.
+        #[arg(long, default_value = "1w")]
+        window: String,
+        /// Lists only the rollovers the spread widened around.
+        #[arg(long)]
+        widened: bool
+    },
+    /// Replays the stored quotes through the spike detection of the `Spikes` section, and prints a JSON line per spike.
+    Spikes {
+        /// Only the quotes of the broker.
+        #[arg(long)]
+        broker: Option<String>,
+        /// Only the quotes of the symbol.
+        #[arg(long)]
+        symbol: Option<String>,
+        /// The window before now to replay, like 1d or 1w.
+        #[arg(long, default_value = "1w")]
+        window: String
+    },
+    /// Queries the stored quotes and prints the result as JSON.
+    Query {
+        #[command(subcommand)]
+        query: Query
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Query {
+    /// Prints the quotes of a symbol from every broker between two times, keyed by broker.
+    Spreads {
+        #[arg(long)]
+        symbol: String,
+        /// The first time, inclusive, in RFC 3339.
+        #[arg(long)]
+        from: DateTime<Utc>,
+        /// The last time, exclusive, in RFC 3339.
+        #[arg(long)]
+        to: DateTime<Utc>
+    },
+    /// Prints the latest quote of every broker and symbol as of a time.
+    Snapshot {
+        /// The time of the snapshot in RFC 3339, now by default.
+        #[arg(long)]
+        at: Option<DateTime<Utc>>,
+        /// Only prints the quotes of the broker.
+        #[arg(long)]
+        broker: Option<String>
+    },
+    /// Prints the spread of a symbol aggregated at an interval: min, max, mean, last and count per bucket.
+    Series {
+        #[arg(long)]
+        symbol: String,
+        /// Only aggregates the quotes of the broker.
+        #[arg(long)]
+        broker: Option<String>,
+        /// The first time, inclusive, in RFC 3339.
+        #[arg(long)]
+        from: DateTime<Utc>,
+        /// The last time, exclusive, in RFC 3339.
+        #[arg(long)]
+        to: DateTime<Utc>,
+        /// The bucket size, like 30s, 15m, 4h, 1d or 1w.
+        #[arg(long, default_value = "1h", value_parser = parse_interval)]
+        interval: Duration
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    let cli: Cli = Cli::parse();
+
+    match cli.command {
+        None => track_spreads().await,
+        Some(Command::Export { out, broker, symbol, from, to, live }) => {
+            init_tracing();
+
+            let filter: QuoteFilter = QuoteFilter { broker, symbol, from, to, limit: None };
+            if let Err(err) = export(out, &filter, live).await {
+                error!("The export failed: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Retention) => {
+            init_tracing();
+
+            if let Err(err) = retention().await {
+                error!("The retention failed: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Rank { symbol, stored, max_age_secs, commissions, monthly_volume, by, json }) => {
+            init_tracing_to_stderr();
+
+            let commission_config: CommissionConfig = CommissionConfig::load().unwrap_or_else(|err| {
+                error!("Failed to load the commissions: {}", err);
+                std::process::exit(1);
+            });
+            let mut model: CostModel = CostModel::from_config(&commission_config);
+            if let Some(lots) = monthly_volume {
+                model = model.monthly_volume(lots);
+            }
+            let mut ranker: Ranker = Ranker::new()
+                .max_age(Some(Duration::seconds(max_age_secs as i64)).filter(|_| max_age_secs > 0))
+                .rank_by(by)
+                .cost_model(model);
+            for (broker, pips) in &commissions {
+                ranker = ranker.commission(broker, *pips);
+            }
+
+            if let Err(err) = rank(&symbol, ranker, stored, json).await {
+                error!("The ranking failed: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Cost { symbol, lots, account, monthly_volume, stored, json }) => {
+            init_tracing_to_stderr();
+
+            if let Err(err) = cost(&symbol, lots, &account, monthly_volume, stored, json).await {
+                error!("The trade cost failed: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Stats { broker, symbol, windows }) => {
+            init_tracing_to_stderr();
+
+            let filter: QuoteFilter = QuoteFilter { broker, symbol, from: None, to: None, limit: None };
+            if let Err(err) = stats(&filter, windows).await {
+                error!("The statistics failed: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Sessions { broker, symbol, window, widened }) => {
+            init_tracing_to_stderr();
+
+            let filter: QuoteFilter = QuoteFilter { broker, symbol, from: None, to: None, limit: None };
+            if let Err(err) = sessions(&filter, &window, widened).await {
+                error!("The session statistics failed: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Spikes { broker, symbol, window }) => {
+            init_tracing_to_stderr();
+
+            let filter: QuoteFilter = QuoteFilter { broker, symbol, from: None, to: None, limit: None };
+            if let Err(err) = spikes(&filter, &window).await {
+                error!("The spike replay failed: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Query { query: command }) => {
+            // The result goes to stdout, the logs go to stderr
+            init_tracing_to_stderr();
+
+            if let Err(err) = query(command).await {
+                error!("The query failed: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+async fn track_spreads() {
+    init_tracing();
+
+    // Every broker is fetched once per poll, every consumer shares the snapshots
+    let snapshots: Vec<BrokerSnapshot> = match fetch_snapshots().await {
+        Ok(snapshots) => snapshots,
+        Err(err) => {
+            error!("Failed to fetch the broker snapshots: {}", err);
+            return;
+        }
+    };
+
+    // The shape of `SpreadTracker::get_spread`, the quotes of every broker under the `spread` key
+    let spread: Map<String, Value> = snapshots.iter()
+        .map(|snapshot| (snapshot.broker.clone(), snapshot.quotes.clone()))
+        .collect();
+    info!("Spread yield: {:#?}", serde_json::json!({ "spread": spread }));
+
+    // The storage is opened once per poll, the quotes are saved to it and the spike detection warms up from it
+    let db: Option<Arc<dyn Db>> = match open_configured_storage().await {
+        Ok(db) => Some(db),
+        Err(err) => {
+            error!("Failed to open the storage: {}", err);
+            None
+        }
+    };
+    if let Some(db) = &db {
+        if let Err(err) = save_snapshots(db.as_ref(), &snapshots).await {
+            error!("Failed to save the snapshots to the storage: {}", err);
+        }
+    }
+
+    if let Err(err) = save_history(&snapshots) {
+        error!("Failed to save the NDJSON history: {}", err);
+    }
+
+    if let Err(err) = detect_spikes(db.as_deref(), &snapshots).await {
+        error!("Failed to detect the spread spikes: {}", err);
+    }
+
+    if let Err(err) = evaluate_alerts(&snapshots).await {
+        error!("Failed to evaluate the alert rules: {}", err);
+    }
+}
+
+/// Opens the storage of the `Storage` section.
+async fn open_configured_storage() -> Result<Arc<dyn Db>, Box<dyn StdError + Send + Sync + 'static>> {
+    open_storage(&StorageConfig::load()?).await
+}
+
+/// Loads the `Storage` section for a command reading the quotes saved by the previous runs.
+///
+/// The `Memory` backend starts empty in every process, so it is refused instead of answering with no quotes.
+fn persisted_storage_config() -> Result<StorageConfig, Box<dyn StdError + Send + Sync + 'static>> {
+    let config: StorageConfig = StorageConfig::load()?;
+    if config.backend == StorageBackend::Memory {
+        return Err("The Memory storage does not outlive a run, set the Backend of the Storage section to Sqlite, Supabase or Postgres".into());
+    }
+
+    Ok(config)
+}
+
+/// Saves the snapshot of every broker to the storage, the quotes of a snapshot saved twice are kept once.
+async fn save_snapshots(
+    db: &dyn Db,
+    snapshots: &[BrokerSnapshot]
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let mut saved: usize = 0;
+    for snapshot in snapshots {
+        saved += db.save_snapshot(snapshot).await?;
+    }
+    info!("Saved {} quote(s) of {} broker(s) to the storage", saved, snapshots.len());
+
+    Ok(())
+}
+
+/// Appends the snapshots of every broker to the NDJSON history, when the `Ndjson` section enables it.
+fn save_history(
+    snapshots: &[BrokerSnapshot]
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let config: NdjsonConfig = NdjsonConfig::load()?;
+    let writer: NdjsonWriter = match NdjsonWriter::from_config(&config) {
+        Some(writer) => writer,
+        None => return Ok(())
+    };
+
+    let written: usize = match config.record {
+        NdjsonRecord::Quote => writer.write_quotes(&snapshots.iter().flat_map(Quote::from_snapshot).collect::<Vec<Quote>>())?,
+        NdjsonRecord::Snapshot => writer.write_snapshots(snapshots)?
+    };
+    writer.close()?;
+    info!("Appended {} record(s) to the NDJSON history in {}", written, writer.dir().display());
+
+    Ok(())
+}
+
+/// Compares the fresh quotes with the stored ones, when the `Spikes` section enables it, and appends the spikes to its `Dir`.
+async fn detect_spikes(
+    db: Option<&dyn Db>,
+    snapshots: &[BrokerSnapshot]
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let config: SpikeConfig = SpikeConfig::load()?;
+    if !config.enabled {
+        return Ok(());
+    }
+    let mut detector: SpikeDetector = SpikeDetector::from_config(&config)?;
+
+    // The quotes of the previous polls are read back from the storage, without it every run starts the warm-up over
+    let quotes: Vec<Quote> = snapshots.iter().flat_map(Quote::from_snapshot).collect();
+    let spikes: Vec<SpikeEvent> = match db {
+        Some(db) => detector.push_poll(db, &quotes).await?,
+        None => detector.push_all(&quotes)
+    };
+    if let Some(dir) = &config.dir {
+        let writer: NdjsonWriter = NdjsonWriter::new(dir).prefix("spikes");
+        writer.write_records(&spikes, |spike| spike.ts)?;
+        writer.close()?;
+    }
+    info!("Found {} spread spike(s) in {} quote(s)", spikes.len(), quotes.len());
+
+    Ok(())
+}
+
+/// Evaluates the rules of the `Alerts` section on the fresh quotes, delivers the alerts, and saves the state for the next poll.
+async fn evaluate_alerts(
+    snapshots: &[BrokerSnapshot]
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let config: AlertConfig = AlertConfig::load()?;
+    if config.rules.is_empty() {
+        return Ok(());
+    }
+    let mut engine: AlertEngine = AlertEngine::from_config(&config)?;
+    let notifier: Notifier = Notifier::from_config(&config)?;
+
+    let quotes: Vec<Quote> = snapshots.iter().flat_map(Quote::from_snapshot).collect();
+    let alerts: Vec<Alert> = engine.evaluate(&quotes);
+    // Only the delivered alerts are notified, the others fire again on the next poll
+    let delivered: Vec<Alert> = notifier.notify(&alerts).await;
+    engine.acknowledge(&delivered);
+
+    if let Some(path) = &config.state {
+        engine.state().save(path)?;
+    }
+
+    Ok(())
+}
+
+/// Exports the stored quotes matching the filter, or the snapshots fetched now with `live`, and fails when none matches.
+async fn export(
+    out: PathBuf,
+    filter: &QuoteFilter,
+    live: bool
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let exporter: ParquetExporter = ParquetExporter::from_config(&out)?;
+
+    let files: Vec<PathBuf> = match live {
+        true => {
+            let snapshots: Vec<BrokerSnapshot> = fetch_snapshots().await?;
+
+            // The source ids come from the configured URLs, the same ones the snapshots were fetched from
+            let quotes: Vec<Quote> = snapshots.iter()
+                .flat_map(Quote::from_snapshot)
+                .filter(|quote| filter.matches(quote))
+                .collect();
+            info!("Fetched {} quote(s) from {} broker(s)", quotes.len(), snapshots.len());
+            exporter.export_quotes(&quotes)?
+        },
+        false => {
+            let db: Arc<dyn Db> = open_storage(&persisted_storage_config()?).await?;
+            exporter.export_db(db.as_ref(), filter).await?
+        }
+    };
+    if files.is_empty() {
+        return Err("No quote matches the filter, nothing was exported".into());
+    }
+
+    for file in &files {
+        info!("Wrote {}", file.display());
+    }
+
+    Ok(())
+}
+
+/// Applies the configured retention to the configured storage, which has to outlive the run.
+async fn retention() -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let db: Arc<dyn Db> = open_storage(&persisted_storage_config()?).await?;
+    let report: RetentionReport = RetentionPolicy::from_config(&RetentionConfig::load()?).run(db.as_ref()).await?;
+
+    info!("Retention done: {}", serde_json::to_string(&report)?);
+    Ok(())
+}
+
+/// Ranks the brokers of the symbol, live or from the configured storage, and prints the ranking.
+async fn rank(
+    symbol: &str,
+    ranker: Ranker,
+    stored: bool,
+    json: bool
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    // Every listed broker is expected, the ones that failed or do not quote the symbol show as missing
     let config: SpreadBrokerUrl = SpreadBrokerUrl::new();
+    let ranker: Ranker = ranker.expect_brokers(
+        default_brokers().into_iter().filter_map(|broker| extract_broker_name(&config.get_url(broker)).ok())
+    );
+
+    let now: DateTime<Utc> = Utc::now().trunc_subsecs(6);
+    let ranking: SymbolRanking = match stored {
+        true => {
+            let history: History = History::open(&persisted_storage_config()?).await?;
+            ranker.rank(symbol, &history.snapshot_at(now, None).await?, now)
+        },
+        false => ranker.rank_snapshots(symbol, &fetch_snapshots().await?, now)
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&ranking)?);
+        return Ok(());
+    }
+
+    let by: &str = match ranking.by {
+        RankBy::Spread => "spread",
+        RankBy::AllIn => "all-in cost"
+    };
+    println!("{} as of {}, by {}", ranking.symbol, ranking.as_of.format("%Y-%m-%d %H:%M:%S UTC"), by);
+    println!("{:<6} {:<24} {:>8} {:>11} {:>8} {:>9}", "rank", "broker", "pips", "commission", "cost", "age");
+    for broker in &ranking.brokers {
+        let rank: String = format!("{}{}", broker.rank, if broker.tied { "=" } else { "" });
+        let commission: String = broker.commission_pips.map(|pips| pips.to_string()).unwrap_or_else(|| "-".to_string());
+        let age: String = format!("{}s{}", broker.age_secs, if broker.stale { " stale" } else { "" });
+        println!("{:<6} {:<24} {:>8} {:>11} {:>8} {:>9}", rank, broker.broker, broker.pips, commission, broker.cost_pips, age);
+    }
+    for broker in &ranking.missing {
+        println!("{:<6} {:<24} {:>8}", "-", broker, "missing");
+    }
+
+    Ok(())
+}
+
+/// Prints the all-in cost of a round turn of the symbol at every broker, with the commissions of the configuration,
+/// live or from the configured storage.
+async fn cost(
+    symbol: &str,
+    lots: f64,
+    account: &str,
+    monthly_volume: Option<f64>,
+    stored: bool,
+    json: bool
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let quotes: Vec<Quote> = match stored {
+        true => History::open(&persisted_storage_config()?).await?.snapshot_at(Utc::now(), None).await?,
+        false => fetch_snapshots().await?.iter().flat_map(Quote::from_snapshot).collect()
+    };
+    let mut model: CostModel = CostModel::from_config(&CommissionConfig::load()?);
+    if let Some(lots) = monthly_volume {
+        model = model.monthly_volume(lots);
+    }
+    let costs: Vec<AllInCost> = model.all_in(symbol, lots, account, &quotes);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&costs)?);
+        return Ok(());
+    }
+
+    let account: String = account.to_ascii_uppercase();
+    println!("{} lot(s) of {}, round turn, in {}", lots, symbol.to_ascii_uppercase(), account);
+    let money = |value: Option<f64>| value.map(|value| format!("{:.2}", value)).unwrap_or_else(|| "-".to_string());
+    let pips = |value: Option<f64>| value.map(|value| value.to_string()).unwrap_or_else(|| "-".to_string());
+    println!("{:<24} {:>8} {:>11} {:>8} {:>16} {:>12} {:>12} {:>12}",
+        "broker", "pips", "commission", "all-in", "quote cost", "spread cost", "commission", "all-in");
+    for cost in &costs {
+        let spread_cost = &cost.spread_cost;
+        let quote_cost: String = format!("{:.2} {}", spread_cost.quote_cost, spread_cost.quote_currency.as_deref().unwrap_or(""));
+        println!("{:<24} {:>8} {:>11} {:>8} {:>16} {:>12} {:>12} {:>12}",
+            spread_cost.broker, spread_cost.pips, pips(cost.commission_pips), pips(cost.all_in_pips),
+            quote_cost.trim_end(), money(spread_cost.cost), money(cost.commission), money(cost.all_in));
+    }
+
+    Ok(())
+}
+
+/// Prints the statistics of the stored quotes over the windows, or the ones of the `Stats` section.
+async fn stats(
+    filter: &QuoteFilter,
+    windows: Vec<String>
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let config: StatsConfig = match windows.is_empty() {
+        true => StatsConfig::load()?,
+        false => StatsConfig { windows }
+    };
+    let db: Arc<dyn Db> = open_storage(&persisted_storage_config()?).await?;
+
+    let now: DateTime<Utc> = Utc::now().trunc_subsecs(6);
+    let mut result: Vec<Value> = Vec::new();
+    for (name, window) in config.windows()? {
+        let stats: Vec<SpreadStats> = stored_stats(db.as_ref(), filter, now, window).await?;
+        result.push(serde_json::json!({ "window": name, "stats": stats }));
+    }
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+/// Prints the statistics by session of the stored quotes over the window, and the spread around every rollover of the window.
+async fn sessions(
+    filter: &QuoteFilter,
+    window: &str,
+    widened: bool
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let detector: RolloverDetector = RolloverDetector::from_config(&RolloverConfig::load()?)?;
+    let db: Arc<dyn Db> = open_storage(&persisted_storage_config()?).await?;
+
+    // The statistics include the quotes at `to`, the end of a filter is excluded and the quotes are stored to the microsecond
+    let to: DateTime<Utc> = Utc::now().trunc_subsecs(6);
+    let from: DateTime<Utc> = to - parse_interval(window)?;
+    let until: DateTime<Utc> = to + Duration::microseconds(1);
+    let quotes: Vec<Quote> = db.query_quotes(&QuoteFilter { from: Some(from), to: Some(until), limit: None, ..filter.clone() }).await?;
+
+    let stats: Vec<SessionStats> = SessionCalendar::new().session_stats(&quotes, from, to);
+    let rollovers: Vec<RolloverWidening> = detector.detect(&quotes).into_iter()
+        .filter(|rollover| !widened || rollover.widened)
+        .collect();
+
+    println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "sessions": stats, "rollovers": rollovers }))?);
+    Ok(())
+}
+
+/// Replays the stored quotes of the window through the spike detection, and prints a JSON line per spike.
+async fn spikes(
+    filter: &QuoteFilter,
+    window: &str
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let mut detector: SpikeDetector = SpikeDetector::from_config(&SpikeConfig::load()?)?;
+    let db: Arc<dyn Db> = open_storage(&persisted_storage_config()?).await?;
+
+    let to: DateTime<Utc> = Utc::now().trunc_subsecs(6);
+    let from: DateTime<Utc> = to - parse_interval(window)?;
+    let quotes: Vec<Quote> = db.query_quotes(&QuoteFilter { from: Some(from), to: Some(to), limit: None, ..filter.clone() }).await?;
+
+    for spike in detector.push_all(&quotes) {
+        println!("{}", serde_json::to_string(&spike)?);
+    }
 
-    let brokers: Vec<Brokers> = vec![
+    Ok(())
+}
+
+/// Parses a `broker=pips` commission.
+fn parse_commission(
+    value: &str
+) -> Result<(String, f64), String> {
+    let (broker, pips): (&str, &str) = value.split_once('=').ok_or_else(|| format!("{:?} is not broker=pips", value))?;
+    let pips: f64 = pips.trim().parse::<f64>().map_err(|_| format!("{:?} is not a number of pips", pips))?;
+
+    Ok((broker.trim().to_string(), pips))
+}
+
+/// Parses a positive number of lots.
+fn parse_lots(
+    value: &str
+) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(lots) if lots.is_finite() && lots > 0.0 => Ok(lots),
+        Ok(_) => Err(format!("{:?} is not a positive number of lots", value)),
+        Err(_) => Err(format!("{:?} is not a number of lots", value))
+    }
+}
+
+/// Parses what to rank by, `spread` or `all-in`.
+fn parse_rank_by(
+    value: &str
+) -> Result<RankBy, String> {
+    match value.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+        "spread" => Ok(RankBy::Spread),
+        "all-in" => Ok(RankBy::AllIn),
+        _ => Err(format!("{:?} is not spread or all-in", value))
+    }
+}
+
+/// Fetches the snapshot of every listed broker, skipping the ones that fail.
+async fn fetch_snapshots() -> Result<Vec<BrokerSnapshot>, Box<dyn StdError + Send + Sync + 'static>> {
+    let tracker: SpreadTracker = SpreadTracker::new(SpreadBrokerUrl::new())?;
+
+    let mut snapshots: Vec<BrokerSnapshot> = Vec::new();
+    for broker in default_brokers() {
+        match tracker.snapshot(broker.clone()).await {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(err) => warn!("Skipping {}: {}", broker.to_string(), err)
+        }
+    }
+
+    Ok(snapshots)
+}
+
+/// Runs the query on the configured storage and prints the result.
+async fn query(
+    command: Query
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let history: History = History::open(&persisted_storage_config()?).await?;
+
+    let result: Value = match command {
+        Query::Spreads { symbol, from, to } => serde_json::to_value(history.symbol_spreads(&symbol, from, to).await?)?,
+        Query::Snapshot { at, broker } => {
+            let at: DateTime<Utc> = at.unwrap_or_else(Utc::now);
+            serde_json::to_value(history.snapshot_at(at, broker.as_deref()).await?)?
+        },
+        Query::Series { symbol, broker, from, to, interval } => {
+            let series: Vec<SeriesPoint> = history.series(&symbol, broker.as_deref(), from, to, interval).await?;
+            serde_json::to_value(series)?
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+fn default_brokers() -> Vec<Brokers> {
+    vec![
         Brokers::FusionMarkets,
         Brokers::IcMarkets,
         Brokers::Octa,
@@ -42,25 +727,21 @@ async fn main() {
         Brokers::Pepperstone,
         Brokers::FxPig,
         Brokers::FxPro
-    ];
-
-    let result: Value = SpreadTracker::get_spread(config, brokers).await.unwrap();
-
-    init_tracing();
+    ]
+}
 
-    info!("Spread yield: {:#?}", result);
+fn init_tracing() {
+    tracing_subscriber::fmt().with_env_filter(tracing_filter()).init();
+}
 
-    // // save to file json with indent 4
-    // let mut file: File = File::create("output.json").unwrap();
-    // file.write_all(serde_json::to_string_pretty(&result).unwrap().as_bytes()).unwrap();
+fn init_tracing_to_stderr() {
+    tracing_subscriber::fmt().with_env_filter(tracing_filter()).with_writer(std::io::stderr).init();
 }
 
-fn init_tracing() {
-    let filter: EnvFilter = EnvFilter::try_from_default_env().unwrap_or_else(|_|
+fn tracing_filter() -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_|
         EnvFilter::new("error")
             .add_directive("warn".parse().unwrap())
             .add_directive("info".parse().unwrap())
-    );
-
-    tracing_subscriber::fmt().with_env_filter(filter).init();
+    )
 }
@@ -11,6 +11,16 @@
 //! - Symbol
 //! - SymbolSpread
 //! - HttpsUrl
+//! - BrokerFetch
+//! - BrokerSnapshot
+//! - Quote
+//! - Resolution
+//! - SpreadAggregate
+//!
+//! ### Functions
+//! - pip_size
+//! - contract_size
+//! - symbol_currencies
 //!
 //! ### Traits
 //! - FromStr
@@ -20,6 +30,22 @@
 //!
 #![allow(dead_code)]
 
+use chrono::{
+    DateTime,
+    Duration,
+    DurationRound,
+    SubsecRound,
+    Utc
+};
+use std::collections::BTreeMap;
+use serde_derive::{
+    Deserialize,
+    Serialize
+};
+use serde_json::Value;
+
+use crate::caching::snapshot_cache::CacheStatus;
+
 
 /// Struct that represents a currency pair.
 /// Every major FX pair and few indices are included.
@@ -27,17 +53,17 @@
 /// ### Example
 ///
 /// ```
-/// use forex_model::Symbol;
+/// use spread_tracker::model::Symbol;
 ///
 /// let symbol = Symbol::EuroUsd;
 ///
-/// assert_eq!(symbol.get_symbol(), "EURUSD");
+/// assert_eq!(symbol.to_string(), "EURUSD");
 /// ```
 ///
 /// ### Errors
 /// provided symbol is not supported in this library
 /// `
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Symbol {
     EuroUsd,
     AudCad,
@@ -78,12 +104,12 @@ pub enum Symbol {
 /// ### Example
 ///
 /// ```
-/// use forex_model::{Symbol, SymbolSpread};
+/// use spread_tracker::model::{Symbol, SymbolSpread};
 ///
 /// let symbol = Symbol::EuroUsd;
 /// let spread = SymbolSpread::new(symbol, 0.0001, 1.1234, 1.1233);
 ///
-/// assert_eq!(spread.get_symbol(), "EURUSD");
+/// assert_eq!(spread.symbol.to_string(), "EURUSD");
 /// ```
 ///
 #[derive(Debug, Clone)]
@@ -105,7 +131,7 @@ pub struct SymbolSpread {
 /// let symbol = Symbol::EuroUsd;
 /// let spread = SymbolSpread::new(symbol, 0.0001, 1.1234, 1.1233);
 ///
-/// assert_eq!(spread.get_symbol(), "EURUSD");
+/// assert_eq!(spread.symbol.to_string(), "EURUSD");
 /// ```
 impl SymbolSpread {
     pub fn new(symbol: Symbol, spread: f64, ask: f64, bid: f64) -> Self {
@@ -129,7 +155,7 @@ impl SymbolSpread {
 ///
 /// let symbol = Symbol::EuroUsd;
 ///
-/// assert_eq!(symbol.get_symbol(), "EURUSD");
+/// assert_eq!(symbol.to_string(), "EURUSD");
 /// ```
 pub trait FromStr {
     fn from_str(symbol: &str) -> Result<Self, String> where Self: Sized;
@@ -296,11 +322,13 @@ pub struct HttpsUrl {
 /// ### Example
 ///
 /// ```
-/// use spread_tracker::model::SpreadBrokerUrl;
+/// use spread_tracker::model::HttpsUrl;
 ///
-/// let spread_broker_url = SpreadBrokerUrl::new();
+/// let https_url = HttpsUrl {
+///    url: "http://www.alphavantage.co/".to_string()
+/// };
 ///
-/// assert_eq!(spread_broker_url.vantage, "https://www.alphavantage.co/ ");
+/// assert_eq!(https_url.verify_url(), false);
 /// ```
 ///
 /// ### Errors
@@ -308,8 +336,369 @@ pub struct HttpsUrl {
 /// This method will return an error if the url is not a valid url.
 ///
 impl HttpsUrl {
-    fn verify_url(&self) -> bool {
+    pub fn verify_url(&self) -> bool {
         self.url.starts_with("https://")
     }
 }
 
+
+
+
+/// Struct that records how the page of a single broker was fetched.
+/// It is reported under the `fetch` key of the `get_spread` output, next to the `spread` key.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::model::BrokerFetch;
+///
+/// let fetch = BrokerFetch {
+///     broker: "vantage".to_string(),
+///     url: "https://www.myfxbook.com/forex-broker-quotes/vantage/6052".to_string(),
+///     attempts: 2,
+///     status: Some(200),
+///     error: None,
+///     cache: None,
+///     fetched_at: None
+/// };
+///
+/// assert_eq!(serde_json::to_value(&fetch).unwrap()["attempts"], 2);
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct BrokerFetch {
+    pub broker: String,
+    pub url: String,
+    pub attempts: u32,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub cache: Option<CacheStatus>,
+    pub fetched_at: Option<DateTime<Utc>>
+}
+
+impl BrokerFetch {
+    /// # Creates the record of a broker page that was not requested yet.
+    pub fn new(
+        broker: &str,
+        url: &str
+    ) -> Self {
+        Self {
+            broker: broker.to_string(),
+            url: url.to_string(),
+            attempts: 0,
+            status: None,
+            error: None,
+            cache: None,
+            fetched_at: None
+        }
+    }
+}
+
+
+/// Struct that holds the parsed quotes of a single broker, and when they were fetched.
+/// It is what the snapshot cache stores, see `caching`.
+///
+/// `quotes` is the JSON array reported under the broker name in the `spread` key of the `get_spread` output.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::model::BrokerSnapshot;
+///
+/// let snapshot = BrokerSnapshot {
+///     broker: "fxpro".to_string(),
+///     url: "https://www.myfxbook.com/forex-broker-quotes/fxpro/5168".to_string(),
+///     quotes: serde_json::json!([{ "symbol": "EURUSD", "ask": 1.0712, "bid": 1.0714, "spread": 0.2 }]),
+///     fetched_at: chrono::Utc::now()
+/// };
+///
+/// assert_eq!(snapshot.quotes[0]["symbol"], "EURUSD");
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrokerSnapshot {
+    pub broker: String,
+    pub url: String,
+    pub quotes: Value,
+    pub fetched_at: DateTime<Utc>
+}
+
+
+
+/// Struct that holds a single quote of a broker, as it is stored in a database.
+///
+/// `ts` is when the quote was fetched, truncated to microseconds like every database stores it.
+/// `(broker, symbol, ts)` identifies a quote.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::model::{ BrokerSnapshot, Quote };
+///
+/// let snapshot = BrokerSnapshot {
+///     broker: "fxpro".to_string(),
+///     url: "https://www.myfxbook.com/forex-broker-quotes/fxpro/5168".to_string(),
+///     quotes: serde_json::json!([{ "symbol": "EURUSD", "ask": 1.0712, "bid": 1.0714, "spread": 0.2 }]),
+///     fetched_at: chrono::Utc::now()
+/// };
+///
+/// let quotes = Quote::from_snapshot(&snapshot);
+/// assert_eq!(quotes[0].broker, "fxpro");
+/// assert_eq!(quotes[0].spread, 0.2);
+/// assert_eq!(quotes[0].ts.timestamp_micros(), snapshot.fetched_at.timestamp_micros());
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Quote {
+    pub ts: DateTime<Utc>,
+    pub broker: String,
+    pub symbol: String,
+    pub ask: f64,
+    pub bid: f64,
+    pub spread: f64
+}
+
+impl Quote {
+    /// # A quote of the broker and symbol at the time with the spread, a bid of 1 and an ask of 1 plus the spread,
+    /// for the examples where only the spread matters.
+    #[doc(hidden)]
+    pub fn sample(
+        broker: &str,
+        symbol: &str,
+        ts: DateTime<Utc>,
+        spread: f64
+    ) -> Self {
+        Self {
+            ts,
+            broker: broker.to_string(),
+            symbol: symbol.to_string(),
+            ask: 1.0 + spread,
+            bid: 1.0,
+            spread
+        }
+    }
+
+    /// # Flattens the quotes of a snapshot, every quote gets the fetch time of the snapshot.
+    ///
+    /// Entries that are not a quote object are skipped.
+    pub fn from_snapshot(
+        snapshot: &BrokerSnapshot
+    ) -> Vec<Quote> {
+        let entries: &[Value] = snapshot.quotes.as_array().map(|quotes| quotes.as_slice()).unwrap_or(&[]);
+        let ts: DateTime<Utc> = snapshot.fetched_at.trunc_subsecs(6);
+
+        entries.iter()
+            .filter_map(|quote| {
+                Some(Quote {
+                    ts,
+                    broker: snapshot.broker.clone(),
+                    symbol: quote["symbol"].as_str()?.to_string(),
+                    ask: quote["ask"].as_f64()?,
+                    bid: quote["bid"].as_f64()?,
+                    spread: quote["spread"].as_f64()?
+                })
+            })
+            .collect()
+    }
+
+    /// # Returns the spread in pips, `spread` divided by the pip size of the symbol.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use spread_tracker::model::Quote;
+    ///
+    /// let quote = Quote {
+    ///     ts: chrono::Utc::now(),
+    ///     broker: "fxpro".to_string(),
+    ///     symbol: "USDJPY".to_string(),
+    ///     ask: 155.12,
+    ///     bid: 155.15,
+    ///     spread: 0.03
+    /// };
+    ///
+    /// assert_eq!(quote.pips(), 3.0);
+    /// ```
+    pub fn pips(&self) -> f64 {
+        // Rounded to a tenth of a pip, the precision brokers quote at, so float noise does not show up
+        (self.spread / pip_size(&self.symbol) * 10.0).round() / 10.0
+    }
+}
+
+
+/// # Returns the size of a pip of the symbol, in units of its price.
+///
+/// `0.01` for pairs quoted in yen, `0.1` for gold, `0.01` for silver and `0.0001` for every other pair.
+pub fn pip_size(
+    symbol: &str
+) -> f64 {
+    match symbol {
+        symbol if symbol.starts_with("XAU") => 0.1,
+        symbol if symbol.starts_with("XAG") => 0.01,
+        symbol if symbol.ends_with("JPY") => 0.01,
+        _ => 0.0001
+    }
+}
+
+
+/// # Returns the units of the symbol in a standard lot.
+///
+/// `100` ounces for gold, `5000` ounces for silver and `100000` units of the base currency for every other pair.
+pub fn contract_size(
+    symbol: &str
+) -> f64 {
+    match symbol {
+        symbol if symbol.starts_with("XAU") => 100.0,
+        symbol if symbol.starts_with("XAG") => 5_000.0,
+        _ => 100_000.0
+    }
+}
+
+
+/// # Returns the base and quote currencies of a six-letter symbol, `None` for any other symbol.
+///
+/// ```
+/// use spread_tracker::model::symbol_currencies;
+///
+/// assert_eq!(symbol_currencies("USDJPY"), Some(("USD", "JPY")));
+/// assert_eq!(symbol_currencies("XAUUSD"), Some(("XAU", "USD")));
+/// assert_eq!(symbol_currencies("US30"), None);
+/// ```
+pub fn symbol_currencies(
+    symbol: &str
+) -> Option<(&str, &str)> {
+    match symbol.len() == 6 && symbol.bytes().all(|byte| byte.is_ascii_uppercase()) {
+        true => Some(symbol.split_at(3)),
+        false => None
+    }
+}
+
+
+
+/// Enum of the resolutions quotes are rolled up to, see `db::retention`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Minute,
+    Hour,
+    Day
+}
+
+impl Resolution {
+    /// Every resolution, finest first.
+    pub const ALL: [Resolution; 3] = [Resolution::Minute, Resolution::Hour, Resolution::Day];
+
+    /// # Returns the name of the resolution, as it is stored.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Minute => "minute",
+            Resolution::Hour => "hour",
+            Resolution::Day => "day"
+        }
+    }
+
+    /// # Returns the resolution with the name.
+    pub fn from_name(
+        name: &str
+    ) -> Option<Self> {
+        Self::ALL.into_iter().find(|resolution| resolution.as_str() == name)
+    }
+
+    /// # Returns the length of a bucket.
+    pub fn duration(&self) -> Duration {
+        match self {
+            Resolution::Minute => Duration::minutes(1),
+            Resolution::Hour => Duration::hours(1),
+            Resolution::Day => Duration::days(1)
+        }
+    }
+
+    /// # Returns the start of the bucket the time falls in, buckets are aligned on UTC.
+    pub fn truncate(
+        &self,
+        ts: DateTime<Utc>
+    ) -> DateTime<Utc> {
+        ts.duration_trunc(self.duration()).unwrap_or(ts)
+    }
+}
+
+
+/// The bucket, broker and symbol of an aggregate being rolled up.
+type BucketKey<'a> = (DateTime<Utc>, &'a str, &'a str);
+
+
+/// Struct that holds the spread statistics of a broker / symbol pair over a bucket of time.
+///
+/// `bucket` is the start of the bucket, `last` is the spread of the most recent quote in it.
+/// `(resolution, broker, symbol, bucket)` identifies an aggregate.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, TimeZone, Utc };
+/// use spread_tracker::model::{ Quote, Resolution, SpreadAggregate };
+///
+/// let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+/// let quote = |seconds: i64, spread: f64| Quote::sample("fxpro", "EURUSD", start + Duration::seconds(seconds), spread);
+///
+/// let quotes = [quote(0, 0.3), quote(30, 0.1), quote(59, 0.2), quote(60, 0.4)];
+/// let minutes = SpreadAggregate::from_quotes(Resolution::Minute, &quotes);
+///
+/// assert_eq!(minutes.len(), 2);
+/// assert_eq!((minutes[0].min, minutes[0].max, minutes[0].last, minutes[0].count), (0.1, 0.3, 0.2, 3));
+/// assert_eq!(SpreadAggregate::from_quotes(Resolution::Hour, &quotes)[0].count, 4);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpreadAggregate {
+    pub resolution: Resolution,
+    pub bucket: DateTime<Utc>,
+    pub broker: String,
+    pub symbol: String,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub last: f64,
+    pub count: u64
+}
+
+impl SpreadAggregate {
+    /// # Rolls the quotes up into one aggregate per bucket, broker and symbol, ordered by bucket, then broker, then symbol.
+    pub fn from_quotes(
+        resolution: Resolution,
+        quotes: &[Quote]
+    ) -> Vec<SpreadAggregate> {
+        // The running sum sits in `mean` until every quote is in, `last_ts` decides which quote is the last one
+        let mut buckets: BTreeMap<BucketKey, (SpreadAggregate, DateTime<Utc>)> = BTreeMap::new();
+
+        for quote in quotes {
+            let bucket: DateTime<Utc> = resolution.truncate(quote.ts);
+            let (aggregate, last_ts) = buckets.entry((bucket, &quote.broker, &quote.symbol)).or_insert_with(|| (
+                SpreadAggregate {
+                    resolution,
+                    bucket,
+                    broker: quote.broker.clone(),
+                    symbol: quote.symbol.clone(),
+                    min: quote.spread,
+                    max: quote.spread,
+                    mean: 0.0,
+                    last: quote.spread,
+                    count: 0
+                },
+                quote.ts
+            ));
+
+            aggregate.min = aggregate.min.min(quote.spread);
+            aggregate.max = aggregate.max.max(quote.spread);
+            aggregate.mean += quote.spread;
+            aggregate.count += 1;
+            if quote.ts >= *last_ts {
+                aggregate.last = quote.spread;
+                *last_ts = quote.ts;
+            }
+        }
+
+        buckets.into_values()
+            .map(|(mut aggregate, _)| {
+                aggregate.mean /= aggregate.count as f64;
+                aggregate
+            })
+            .collect()
+    }
+}
\ No newline at end of file
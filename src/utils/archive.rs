@@ -0,0 +1,359 @@
+//! # Raw response archive
+//!
+//! An opt-in archive of the raw broker pages, replacing the `body.txt` that used to be overwritten on every download.
+//! Every response is stored gzip-compressed, together with its URL, status code and headers, under
+//! `<dir>/<broker>/<timestamp>.json.gz`. Old entries are pruned by count and by age,
+//! and archived pages can be listed and parsed again later.
+
+use chrono::{
+    DateTime,
+    Utc
+};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde_derive::{
+    Deserialize,
+    Serialize
+};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::error::Error as StdError;
+use std::fs::{
+    self,
+    File,
+    OpenOptions
+};
+use std::io::{
+    BufReader,
+    BufWriter,
+    ErrorKind,
+    Write
+};
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::time::Duration;
+
+use crate::config::ArchiveConfig;
+use crate::utils::format::vec_to_json;
+use crate::utils::regex_finder::find_symbol_spread;
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The extension of every archived response.
+const ENTRY_EXTENSION: &str = ".json.gz";
+
+/// The timestamp format of the file names, it sorts in chronological order.
+const ENTRY_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+
+/// Struct that holds a raw broker response, as it is stored in the archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedResponse {
+    pub broker: String,
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub fetched_at: DateTime<Utc>,
+    pub body: String
+}
+
+
+/// Struct that points at a single archived response on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub broker: String,
+    pub fetched_at: DateTime<Utc>,
+    pub path: PathBuf
+}
+
+
+/// The `ResponseArchive` struct stores raw broker responses in a directory, one sub-directory per broker.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::utils::archive::{ ArchivedResponse, ResponseArchive };
+///
+/// let dir = std::env::temp_dir().join(format!("spread_archive_doc_{}", std::process::id()));
+/// let archive = ResponseArchive::new(&dir).max_entries_per_broker(Some(2));
+///
+/// let body = std::fs::read_to_string("body.txt").unwrap();
+/// for _ in 0..3 {
+///     archive.store(&ArchivedResponse {
+///         broker: "fxpro".to_string(),
+///         url: "https://www.myfxbook.com/forex-broker-quotes/fxpro/5168".to_string(),
+///         status: 200,
+///         headers: vec![("content-type".to_string(), "text/html".to_string())],
+///         fetched_at: chrono::Utc::now(),
+///         body: body.clone()
+///     }).unwrap();
+/// }
+///
+/// // The retention keeps the two most recent responses of the broker.
+/// let entries = archive.list(Some("fxpro")).unwrap();
+/// assert_eq!(entries.len(), 2);
+///
+/// let response = archive.load(&entries[1]).unwrap();
+/// assert_eq!(response.status, 200);
+/// assert_eq!(response.body, body);
+///
+/// // Archived pages go through the same parser as live ones.
+/// let spread = archive.reparse(&entries[1]).unwrap();
+/// assert!(spread.is_array());
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ResponseArchive {
+    dir: PathBuf,
+    max_entries_per_broker: Option<usize>,
+    max_age: Option<Duration>
+}
+
+impl ResponseArchive {
+    /// # Creates a `ResponseArchive` in the given directory, without retention limits.
+    pub fn new(
+        dir: impl AsRef<Path>
+    ) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_entries_per_broker: None,
+            max_age: None
+        }
+    }
+
+    /// # Creates a `ResponseArchive` from the `Archive` section of the configuration.
+    ///
+    /// Returns `None` when the archive is not enabled.
+    pub fn from_config(
+        config: &ArchiveConfig
+    ) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+
+        let max_entries_per_broker: Option<usize> = Some(config.max_entries_per_broker).filter(|max| *max > 0);
+        let max_age: Option<Duration> = Some(config.max_age_days)
+            .filter(|days| *days > 0)
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+
+        Some(
+            Self::new(&config.dir)
+                .max_entries_per_broker(max_entries_per_broker)
+                .max_age(max_age)
+        )
+    }
+
+    /// Keeps at most the given number of responses per broker, the oldest ones are pruned first.
+    pub fn max_entries_per_broker(mut self, max_entries: Option<usize>) -> Self {
+        self.max_entries_per_broker = max_entries;
+        self
+    }
+
+    /// Prunes the responses older than the given age.
+    pub fn max_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Returns the directory of the archive.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// # Stores a response, then prunes the entries of its broker that fall outside the retention.
+    ///
+    /// ### Errors
+    /// An error will be returned if the directory or the file could not be written.
+    pub fn store(
+        &self,
+        response: &ArchivedResponse
+    ) -> Result<ArchiveEntry, Box<dyn StdError + Send + Sync + 'static>> {
+        let broker: String = sanitize_broker(&response.broker);
+        let broker_dir: PathBuf = self.dir.join(&broker);
+        fs::create_dir_all(&broker_dir)?;
+
+        // Two responses of the same broker within the same millisecond get a counter suffix
+        let timestamp: String = response.fetched_at.format(ENTRY_TIMESTAMP_FORMAT).to_string();
+        let mut suffix: u32 = 0;
+        let (path, file): (PathBuf, File) = loop {
+            let file_name: String = match suffix {
+                0 => format!("{}{}", timestamp, ENTRY_EXTENSION),
+                _ => format!("{}-{}{}", timestamp, suffix, ENTRY_EXTENSION)
+            };
+            let path: PathBuf = broker_dir.join(file_name);
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => suffix += 1,
+                Err(error) => return Err(Box::new(error))
+            }
+        };
+
+        let mut encoder: GzEncoder<BufWriter<File>> = GzEncoder::new(BufWriter::new(file), Compression::default());
+        serde_json::to_writer(&mut encoder, response)?;
+        encoder.finish()?.flush()?;
+        info!("Archived the response of {} to {}", broker, path.display());
+
+        self.prune_broker(&broker)?;
+
+        Ok(ArchiveEntry {
+            broker,
+            fetched_at: response.fetched_at,
+            path
+        })
+    }
+
+    /// # Lists the archived responses, oldest first, of one broker or of every broker.
+    ///
+    /// ### Errors
+    /// An error will be returned if the directory could not be read.
+    pub fn list(
+        &self,
+        broker: Option<&str>
+    ) -> Result<Vec<ArchiveEntry>, Box<dyn StdError + Send + Sync + 'static>> {
+        let brokers: Vec<String> = match broker {
+            Some(broker) => vec![sanitize_broker(broker)],
+            None => match fs::read_dir(&self.dir) {
+                Ok(read_dir) => read_dir
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.path().is_dir())
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .collect(),
+                Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+                Err(error) => return Err(Box::new(error))
+            }
+        };
+
+        let mut entries: Vec<ArchiveEntry> = Vec::new();
+        for broker in brokers {
+            let read_dir = match fs::read_dir(self.dir.join(&broker)) {
+                Ok(read_dir) => read_dir,
+                Err(error) if error.kind() == ErrorKind::NotFound => continue,
+                Err(error) => return Err(Box::new(error))
+            };
+
+            for dir_entry in read_dir.filter_map(|entry| entry.ok()) {
+                let file_name: String = dir_entry.file_name().to_string_lossy().to_string();
+                if let Some(fetched_at) = parse_entry_timestamp(&file_name) {
+                    entries.push(ArchiveEntry {
+                        broker: broker.clone(),
+                        fetched_at,
+                        path: dir_entry.path()
+                    });
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| a.fetched_at.cmp(&b.fetched_at).then_with(|| a.path.cmp(&b.path)));
+
+        Ok(entries)
+    }
+
+    /// # Loads an archived response.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be read or decompressed.
+    pub fn load(
+        &self,
+        entry: &ArchiveEntry
+    ) -> Result<ArchivedResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        let file: File = File::open(&entry.path)?;
+        let response: ArchivedResponse = serde_json::from_reader(BufReader::new(GzDecoder::new(file)))?;
+
+        Ok(response)
+    }
+
+    /// # Parses an archived response again, with the same parser as a live response.
+    ///
+    /// Returns the JSON array of quotes, like the value of a broker under the `spread` key of `get_spread`.
+    ///
+    /// ### Errors
+    /// An error will be returned if the entry could not be loaded, or the quotes could not be parsed.
+    pub fn reparse(
+        &self,
+        entry: &ArchiveEntry
+    ) -> Result<Value, Box<dyn StdError + Send + Sync + 'static>> {
+        let response: ArchivedResponse = self.load(entry)?;
+        let results: Vec<String> = find_symbol_spread(&response.body);
+
+        vec_to_json(results).map_err(|error| error.to_string().into())
+    }
+
+    /// # Removes the entries of every broker that fall outside the retention, and returns how many were removed.
+    ///
+    /// ### Errors
+    /// An error will be returned if the directory could not be read or an entry could not be removed.
+    pub fn prune(
+        &self
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        // The entries are ordered by time, the set keeps every broker once
+        let brokers: BTreeSet<String> = self.list(None)?.into_iter().map(|entry| entry.broker).collect();
+
+        let mut removed: usize = 0;
+        for broker in brokers {
+            removed += self.prune_broker(&broker)?;
+        }
+
+        Ok(removed)
+    }
+
+    fn prune_broker(
+        &self,
+        broker: &str
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let entries: Vec<ArchiveEntry> = self.list(Some(broker))?;
+
+        let expired_before: Option<DateTime<Utc>> = self.max_age
+            .and_then(|max_age| chrono::Duration::from_std(max_age).ok())
+            .map(|max_age| Utc::now() - max_age);
+        let over_count: usize = self.max_entries_per_broker
+            .map(|max_entries| entries.len().saturating_sub(max_entries))
+            .unwrap_or(0);
+
+        let mut removed: usize = 0;
+        for (index, entry) in entries.iter().enumerate() {
+            let expired: bool = expired_before.is_some_and(|before| entry.fetched_at < before);
+            if index < over_count || expired {
+                fs::remove_file(&entry.path)?;
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            info!("Pruned {} archived response(s) of {}", removed, broker);
+        }
+
+        Ok(removed)
+    }
+}
+
+
+/// Keeps broker names safe to use as a directory name.
+pub(crate) fn sanitize_broker(
+    broker: &str
+) -> String {
+    broker.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+
+/// Reads the timestamp back from a file name written by `store`.
+fn parse_entry_timestamp(
+    file_name: &str
+) -> Option<DateTime<Utc>> {
+    let stem: &str = file_name.strip_suffix(ENTRY_EXTENSION)?;
+    let timestamp: &str = stem.split('-').next()?;
+
+    chrono::NaiveDateTime::parse_from_str(timestamp, ENTRY_TIMESTAMP_FORMAT)
+        .ok()
+        .map(|naive| naive.and_utc())
+}
@@ -0,0 +1,256 @@
+//! # Anti-bot challenge and block page detection
+//!
+//! myfxbook sits behind Cloudflare. A challenge, captcha, login wall or error page has no quote table,
+//! so without a check it would silently look like "zero symbols found".
+//! `detect_block` recognizes those pages before parsing, and `BlockBackoff` keeps a blocked broker
+//! out of the rotation for a while, so no requests are burned on a blocked source.
+//!
+//! Cloudflare also injects its scripts (`__cfRLUnblockHandlers`, `data-cf-modified`, `/cdn-cgi/`) into regular pages,
+//! so those markers alone never mark a page as blocked.
+
+use std::collections::HashMap;
+use std::sync::{
+    Arc,
+    Mutex,
+    OnceLock
+};
+use std::time::{
+    Duration,
+    Instant
+};
+
+use crate::config::BlockBackoffConfig;
+use crate::errors::ErrorsSpread;
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The backoff shared by every `SpreadTracker` that was not given its own backoff.
+static GLOBAL_BLOCK_BACKOFF: OnceLock<Arc<BlockBackoff>> = OnceLock::new();
+
+/// Present on every myfxbook quote page, a page with the table is never considered blocked.
+const QUOTE_TABLE_MARKERS: &[&str] = &["id=\"brokerTable\"", "id=\"brokerTableBody\""];
+
+const CHALLENGE_MARKERS: &[&str] = &[
+    "<title>Just a moment...</title>",
+    "cf_chl_opt",
+    "cf-browser-verification",
+    "cf-challenge",
+    "Checking your browser before accessing",
+    "Enable JavaScript and cookies to continue"
+];
+
+const CAPTCHA_MARKERS: &[&str] = &[
+    "g-recaptcha",
+    "h-captcha",
+    "cf-turnstile",
+    "captcha-delivery.com",
+    "Attention Required! | Cloudflare"
+];
+
+const LOGIN_MARKERS: &[&str] = &[
+    "type=\"password\"",
+    "Please login",
+    "Please log in",
+    "You must be logged in"
+];
+
+const ERROR_MARKERS: &[&str] = &[
+    "cf-error-details",
+    "Access denied",
+    "404 Not Found",
+    "Page not found",
+    "Internal Server Error",
+    "Service Unavailable",
+    "Bad Gateway"
+];
+
+
+/// The `BlockKind` enum describes why a page has no quotes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    /// A JavaScript challenge, like Cloudflare's "Just a moment..." interstitial.
+    Challenge,
+    /// A captcha, like reCAPTCHA, hCaptcha or Turnstile.
+    Captcha,
+    /// The page asks to sign in before showing the quotes.
+    LoginWall,
+    /// An error page, like a 404, a 5xx or a Cloudflare "Access denied".
+    ErrorPage
+}
+
+impl BlockKind {
+    /// # Returns the `ErrorsSpread` kind reported for this block.
+    pub fn to_error(
+        &self
+    ) -> ErrorsSpread {
+        match self {
+            BlockKind::Challenge => ErrorsSpread::AntiBotChallenge,
+            BlockKind::Captcha => ErrorsSpread::CaptchaRequired,
+            BlockKind::LoginWall => ErrorsSpread::LoginRequired,
+            BlockKind::ErrorPage => ErrorsSpread::ErrorPage
+        }
+    }
+}
+
+
+/// # Recognizes challenge, captcha, login-wall and error pages from the status code and the body.
+///
+/// A page that carries the quote table is never considered blocked.
+/// A page without the table is matched against the markers of each kind, in the order challenge, captcha, login wall, error page.
+/// A 4xx / 5xx status without any marker is an error page, a 2xx without any marker is not blocked.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::utils::block_detection::{ detect_block, BlockKind };
+///
+/// let challenge = "<html><head><title>Just a moment...</title></head><body></body></html>";
+/// assert_eq!(detect_block(403, challenge), Some(BlockKind::Challenge));
+///
+/// // A regular quote page served through Cloudflare is not blocked.
+/// let body = std::fs::read_to_string("body.txt").unwrap();
+/// assert_eq!(detect_block(200, &body), None);
+/// ```
+pub fn detect_block(
+    status: u16,
+    body: &str
+) -> Option<BlockKind> {
+    if QUOTE_TABLE_MARKERS.iter().any(|marker| body.contains(marker)) {
+        return None;
+    }
+
+    let matches = |markers: &[&str]| markers.iter().any(|marker| body.contains(marker));
+
+    if matches(CHALLENGE_MARKERS) {
+        return Some(BlockKind::Challenge);
+    }
+    if matches(CAPTCHA_MARKERS) {
+        return Some(BlockKind::Captcha);
+    }
+    if matches(LOGIN_MARKERS) {
+        return Some(BlockKind::LoginWall);
+    }
+    if matches(ERROR_MARKERS) || status >= 400 {
+        return Some(BlockKind::ErrorPage);
+    }
+
+    None
+}
+
+
+/// The backoff state of a single broker URL.
+#[derive(Debug, Clone, Copy)]
+struct Strike {
+    count: u32,
+    until: Instant
+}
+
+
+/// The `BlockBackoff` struct keeps track of the broker URLs that answered with a block page.
+///
+/// Every block doubles the time the URL is skipped, starting at the base delay and capped at the maximum delay.
+/// A successful fetch clears the strikes of the URL.
+///
+/// ### Example
+///
+/// ```
+/// use std::time::Duration;
+/// use spread_tracker::utils::block_detection::BlockBackoff;
+///
+/// let backoff = BlockBackoff::new(Duration::from_secs(60), Duration::from_secs(600));
+/// let url = "https://www.myfxbook.com/forex-broker-quotes/vantage/6052";
+///
+/// assert_eq!(backoff.record_block(url), Duration::from_secs(60));
+/// assert_eq!(backoff.record_block(url), Duration::from_secs(120));
+/// assert!(backoff.remaining(url).is_some());
+///
+/// backoff.record_success(url);
+/// assert!(backoff.remaining(url).is_none());
+/// ```
+#[derive(Debug)]
+pub struct BlockBackoff {
+    base_delay: Duration,
+    max_delay: Duration,
+    strikes: Mutex<HashMap<String, Strike>>
+}
+
+impl BlockBackoff {
+    /// # Creates a `BlockBackoff` with the given base and maximum delay.
+    pub fn new(
+        base_delay: Duration,
+        max_delay: Duration
+    ) -> Self {
+        Self {
+            base_delay,
+            max_delay,
+            strikes: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// # Creates a `BlockBackoff` from the `BlockBackoff` section of the configuration.
+    pub fn from_config(
+        config: &BlockBackoffConfig
+    ) -> Self {
+        Self::new(Duration::from_secs(config.base_secs), Duration::from_secs(config.max_secs))
+    }
+
+    /// # Returns the process-wide backoff, built from the `BlockBackoff` section of the configuration on first use.
+    pub fn global() -> Arc<BlockBackoff> {
+        GLOBAL_BLOCK_BACKOFF.get_or_init(|| {
+            let config: BlockBackoffConfig = BlockBackoffConfig::load().unwrap_or_else(|error| {
+                error!("Failed to read the BlockBackoff section, using the default backoff: {}", error);
+                BlockBackoffConfig::default()
+            });
+
+            Arc::new(BlockBackoff::from_config(&config))
+        }).clone()
+    }
+
+    /// # Returns how long the URL is still skipped, `None` when it may be fetched.
+    pub fn remaining(
+        &self,
+        url: &str
+    ) -> Option<Duration> {
+        let strikes = self.strikes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let strike: &Strike = strikes.get(url)?;
+
+        strike.until.checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// # Records a block page for the URL, and returns how long it is skipped from now on.
+    pub fn record_block(
+        &self,
+        url: &str
+    ) -> Duration {
+        let mut strikes = self.strikes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let count: u32 = strikes.get(url).map(|strike| strike.count).unwrap_or(0) + 1;
+
+        let delay: Duration = self.base_delay
+            .saturating_mul(1u32 << (count - 1).min(31))
+            .min(self.max_delay);
+        strikes.insert(url.to_string(), Strike {
+            count,
+            until: Instant::now() + delay
+        });
+        warn!("Backing off {} for {:?} after {} block page(s)", url, delay, count);
+
+        delay
+    }
+
+    /// # Clears the strikes of the URL after a successful fetch.
+    pub fn record_success(
+        &self,
+        url: &str
+    ) {
+        let mut strikes = self.strikes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if strikes.remove(url).is_some() {
+            info!("{} is no longer backed off", url);
+        }
+    }
+}
@@ -18,8 +18,10 @@ use std::collections::HashSet;
 /// ### Examples
 ///
 /// ```
-/// let cleaned_string = remove_banned_chars_async("Hello, World!", &[' ', ',']).await;
-/// assert_eq!(cleaned_string, "HelloWorld!");
+/// use spread_tracker::utils::cleaner::remove_banned_chars;
+///
+/// let cleaned_strings = remove_banned_chars(vec!["<td class=\"x\">\nEURUSD".to_string()]);
+/// assert_eq!(cleaned_strings, vec!["<td \"x\">EURUSD".to_string()]);
 /// ```
 pub fn remove_banned_chars(
     inputs: Vec<String>
@@ -0,0 +1,180 @@
+//! # HTTP conditional requests
+//!
+//! Remembers the `ETag` and `Last-Modified` validators of every broker URL, together with the snapshot parsed from that response.
+//! A refresh sends them back as `If-None-Match` / `If-Modified-Since`, and a `304 Not Modified`
+//! reuses the remembered snapshot instead of downloading and parsing the full page again.
+
+use std::collections::HashMap;
+use std::sync::{
+    Arc,
+    Mutex,
+    OnceLock
+};
+
+use crate::model::BrokerSnapshot;
+use crate::utils::transport::{
+    HttpRequest,
+    HttpResponse
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The store shared by every `SpreadTracker` that was not given its own store.
+static GLOBAL_VALIDATOR_STORE: OnceLock<Arc<ValidatorStore>> = OnceLock::new();
+
+
+/// Struct that holds the cache validators sent by the broker with a page.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::utils::conditional::Validators;
+/// use spread_tracker::utils::transport::{ HttpRequest, HttpResponse };
+///
+/// let mut response = HttpResponse::new(200, "<html></html>");
+/// response.headers.push(("ETag".to_string(), "\"5168-1714642200\"".to_string()));
+///
+/// let validators = Validators::from_response(&response).unwrap();
+/// let request = validators.apply(HttpRequest::get("https://www.myfxbook.com/forex-broker-quotes/fxpro/5168"));
+///
+/// assert_eq!(request.headers, vec![("If-None-Match".to_string(), "\"5168-1714642200\"".to_string())]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>
+}
+
+impl Validators {
+    /// # Reads the `ETag` and `Last-Modified` headers of a response.
+    ///
+    /// Returns `None` when the response has neither, such a page cannot be revalidated.
+    pub fn from_response(
+        response: &HttpResponse
+    ) -> Option<Self> {
+        let etag: Option<String> = response.header("etag").map(|value| value.to_string());
+        let last_modified: Option<String> = response.header("last-modified").map(|value| value.to_string());
+
+        if etag.is_none() && last_modified.is_none() {
+            return None;
+        }
+
+        Some(Self { etag, last_modified })
+    }
+
+    /// # Adds the `If-None-Match` and `If-Modified-Since` headers to the request.
+    pub fn apply(
+        &self,
+        mut request: HttpRequest
+    ) -> HttpRequest {
+        if let Some(etag) = &self.etag {
+            request = request.header("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header("If-Modified-Since", last_modified);
+        }
+
+        request
+    }
+}
+
+
+/// Struct that holds the validators of a broker URL and the snapshot parsed from the response they came with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidatedSnapshot {
+    pub validators: Validators,
+    pub snapshot: BrokerSnapshot
+}
+
+
+/// The `ValidatorStore` struct keeps the last validated snapshot of every broker URL.
+///
+/// ### Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use spread_tracker::SpreadTracker;
+/// use spread_tracker::config::{ SpreadBrokerUrl, Brokers };
+/// use spread_tracker::utils::conditional::ValidatorStore;
+/// use spread_tracker::utils::fetch_stats::FetchStats;
+/// use spread_tracker::utils::rate_limiter::RateLimiter;
+/// use spread_tracker::utils::transport::{ Cassette, HttpRequest, HttpResponse, ReplayTransport };
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let config = SpreadBrokerUrl::new();
+/// let url = config.get_url(Brokers::FxPro);
+///
+/// // The page is served once with an ETag, then answered with a 304.
+/// let mut page = HttpResponse::new(200, &std::fs::read_to_string("body.txt").unwrap());
+/// page.headers.push(("ETag".to_string(), "\"5168-1\"".to_string()));
+/// let mut cassette = Cassette::new();
+/// cassette.record(HttpRequest::get(&url), page);
+/// cassette.record(HttpRequest::get(&url), HttpResponse::new(304, ""));
+///
+/// let stats = Arc::new(FetchStats::new());
+/// let tracker = SpreadTracker::with_transport(config, Arc::new(ReplayTransport::new(cassette)))
+///     .with_rate_limiter(Arc::new(RateLimiter::unlimited()))
+///     .with_validator_store(Arc::new(ValidatorStore::new()))
+///     .with_fetch_stats(stats.clone());
+///
+/// let first = tracker.fetch_spread(vec![Brokers::FxPro]).await.unwrap();
+/// let second = tracker.fetch_spread(vec![Brokers::FxPro]).await.unwrap();
+///
+/// assert_eq!(second["fetch"]["fxpro"]["status"], 304);
+/// assert_eq!(second["spread"]["fxpro"], first["spread"]["fxpro"]);
+/// assert_eq!(stats.counts().not_modified, 1);
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct ValidatorStore {
+    entries: Mutex<HashMap<String, ValidatedSnapshot>>
+}
+
+impl ValidatorStore {
+    /// # Creates an empty `ValidatorStore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Returns the process-wide store.
+    pub fn global() -> Arc<ValidatorStore> {
+        GLOBAL_VALIDATOR_STORE.get_or_init(|| Arc::new(ValidatorStore::new())).clone()
+    }
+
+    /// # Returns the validated snapshot of the URL, if any.
+    pub fn get(
+        &self,
+        url: &str
+    ) -> Option<ValidatedSnapshot> {
+        let entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        entries.get(url).cloned()
+    }
+
+    /// # Remembers the validators of the URL and the snapshot parsed from the same response.
+    pub fn insert(
+        &self,
+        url: &str,
+        validators: Validators,
+        snapshot: BrokerSnapshot
+    ) {
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        entries.insert(url.to_string(), ValidatedSnapshot { validators, snapshot });
+    }
+
+    /// # Forgets the URL, the next request for it is unconditional.
+    pub fn remove(
+        &self,
+        url: &str
+    ) {
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if entries.remove(url).is_some() {
+            info!("Forgot the validators of {}", url);
+        }
+    }
+}
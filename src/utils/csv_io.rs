@@ -0,0 +1,328 @@
+//! # CSV export and import
+//!
+//! Writes quotes to CSV and reads them back into `Quote`s, to move spread history between spreadsheets,
+//! other tools and this crate.
+//!
+//! The delimiter, the columns and the timestamp format are configurable on `CsvFormat`,
+//! the same format reads back what it wrote.
+//!
+//! - The columns are `ts`, `broker`, `symbol`, `ask`, `bid`, `spread` and `pips`, written in the order given.
+//! - The timestamp format is a `chrono` format string, RFC 3339 in UTC with microseconds by default.
+//!   `%s` writes and reads Unix seconds.
+//! - With a header, the reader matches the columns by name, in any order and case.
+//!   `pips` is derived from `spread`, it is only read when `spread` is missing.
+
+use chrono::{
+    DateTime,
+    NaiveDateTime,
+    Utc
+};
+use std::error::Error as StdError;
+use std::fs::File;
+use std::io::{
+    BufReader,
+    BufWriter,
+    Read,
+    Write
+};
+use std::path::Path;
+
+use crate::model::{
+    Quote,
+    pip_size
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The default timestamp format, RFC 3339 in UTC with microseconds, the precision quotes are stored at.
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6fZ";
+
+
+/// The `CsvColumn` enum lists the columns a quote can be written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CsvColumn {
+    Ts,
+    Broker,
+    Symbol,
+    Ask,
+    Bid,
+    Spread,
+    /// The spread in pips, see `Quote::pips`.
+    Pips
+}
+
+impl CsvColumn {
+    /// Every column, in the default order.
+    pub const ALL: [CsvColumn; 7] = [
+        CsvColumn::Ts,
+        CsvColumn::Broker,
+        CsvColumn::Symbol,
+        CsvColumn::Ask,
+        CsvColumn::Bid,
+        CsvColumn::Spread,
+        CsvColumn::Pips
+    ];
+
+    /// # Returns the name of the column in the header.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CsvColumn::Ts => "ts",
+            CsvColumn::Broker => "broker",
+            CsvColumn::Symbol => "symbol",
+            CsvColumn::Ask => "ask",
+            CsvColumn::Bid => "bid",
+            CsvColumn::Spread => "spread",
+            CsvColumn::Pips => "pips"
+        }
+    }
+
+    /// # Returns the column with the name, ignoring case and surrounding spaces.
+    pub fn from_name(
+        name: &str
+    ) -> Option<Self> {
+        let name: String = name.trim().to_ascii_lowercase();
+        Self::ALL.into_iter().find(|column| column.name() == name)
+    }
+}
+
+
+/// The `CsvFormat` struct writes quotes to CSV and reads them back.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::model::Quote;
+/// use spread_tracker::utils::csv_io::{ CsvColumn, CsvFormat };
+///
+/// let quote = Quote {
+///     ts: "2024-05-01T12:00:00Z".parse().unwrap(),
+///     broker: "fxpro".to_string(),
+///     symbol: "EURUSD".to_string(),
+///     ask: 1.0712,
+///     bid: 1.0714,
+///     spread: 0.0002
+/// };
+///
+/// // Semicolons and dates the way European spreadsheets open them.
+/// let format = CsvFormat::new()
+///     .delimiter(b';')
+///     .timestamp_format("%d/%m/%Y %H:%M:%S")
+///     .columns(&[CsvColumn::Ts, CsvColumn::Broker, CsvColumn::Symbol, CsvColumn::Ask, CsvColumn::Bid, CsvColumn::Spread, CsvColumn::Pips]);
+///
+/// let mut csv = Vec::new();
+/// format.write(&mut csv, &[quote.clone()]).unwrap();
+/// assert_eq!(
+///     String::from_utf8(csv.clone()).unwrap(),
+///     "ts;broker;symbol;ask;bid;spread;pips\n01/05/2024 12:00:00;fxpro;EURUSD;1.0712;1.0714;0.0002;2\n"
+/// );
+///
+/// assert_eq!(format.read(csv.as_slice()).unwrap(), vec![quote]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvFormat {
+    pub delimiter: u8,
+    pub columns: Vec<CsvColumn>,
+    pub timestamp_format: String,
+    pub header: bool
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            columns: CsvColumn::ALL.to_vec(),
+            timestamp_format: DEFAULT_TIMESTAMP_FORMAT.to_string(),
+            header: true
+        }
+    }
+}
+
+impl CsvFormat {
+    /// # Creates the default format, comma separated, every column, RFC 3339 timestamps and a header.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the byte between the fields.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets the columns and their order. Without a header, the reader expects the same columns.
+    pub fn columns(mut self, columns: &[CsvColumn]) -> Self {
+        self.columns = columns.to_vec();
+        self
+    }
+
+    /// Sets the `chrono` format string of the `ts` column.
+    pub fn timestamp_format(mut self, timestamp_format: &str) -> Self {
+        self.timestamp_format = timestamp_format.to_string();
+        self
+    }
+
+    /// Sets whether the first row holds the column names.
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// # Writes the quotes, and returns how many rows were written.
+    ///
+    /// ### Errors
+    /// An error will be returned if the writer failed.
+    pub fn write<W: Write>(
+        &self,
+        writer: W,
+        quotes: &[Quote]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut csv: csv::Writer<W> = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .from_writer(writer);
+
+        if self.header {
+            csv.write_record(self.columns.iter().map(|column| column.name()))?;
+        }
+        for quote in quotes {
+            csv.write_record(self.columns.iter().map(|column| self.field(quote, *column)))?;
+        }
+        csv.flush()?;
+
+        Ok(quotes.len())
+    }
+
+    /// # Writes the quotes to the file, replacing it.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be written.
+    pub fn write_file(
+        &self,
+        path: impl AsRef<Path>,
+        quotes: &[Quote]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let written: usize = self.write(BufWriter::new(File::create(path.as_ref())?), quotes)?;
+        info!("Wrote {} quote(s) to {}", written, path.as_ref().display());
+
+        Ok(written)
+    }
+
+    /// # Reads the quotes back.
+    ///
+    /// ### Errors
+    /// An error will be returned if a required column is missing, or a row does not parse,
+    /// the message names the row and the column.
+    pub fn read<R: Read>(
+        &self,
+        reader: R
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut csv: csv::Reader<R> = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.header)
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+
+        let columns: Vec<Option<CsvColumn>> = match self.header {
+            true => csv.headers()?.iter().map(CsvColumn::from_name).collect(),
+            false => self.columns.iter().copied().map(Some).collect()
+        };
+        let position = |column: CsvColumn| columns.iter().position(|candidate| *candidate == Some(column));
+
+        let mut missing: Vec<&str> = [CsvColumn::Ts, CsvColumn::Broker, CsvColumn::Symbol, CsvColumn::Ask, CsvColumn::Bid]
+            .into_iter()
+            .filter(|column| position(*column).is_none())
+            .map(|column| column.name())
+            .collect();
+        if position(CsvColumn::Spread).is_none() && position(CsvColumn::Pips).is_none() {
+            missing.push("spread or pips");
+        }
+        if !missing.is_empty() {
+            return Err(format!("The CSV is missing the column(s): {}", missing.join(", ")).into());
+        }
+
+        let mut quotes: Vec<Quote> = Vec::new();
+        for (index, record) in csv.records().enumerate() {
+            let record: csv::StringRecord = record?;
+            let row: usize = index + 1 + usize::from(self.header);
+
+            let field = |column: CsvColumn| -> Result<&str, String> {
+                position(column)
+                    .and_then(|position| record.get(position))
+                    .ok_or_else(|| format!("Row {} has no `{}` field", row, column.name()))
+            };
+            let number = |column: CsvColumn| -> Result<f64, String> {
+                let value: &str = field(column)?;
+                value.parse::<f64>().map_err(|_| format!("Row {}: `{}` is not a number: {:?}", row, column.name(), value))
+            };
+
+            let symbol: String = field(CsvColumn::Symbol)?.to_string();
+            let spread: f64 = match position(CsvColumn::Spread) {
+                Some(_) => number(CsvColumn::Spread)?,
+                None => number(CsvColumn::Pips)? * pip_size(&symbol)
+            };
+
+            quotes.push(Quote {
+                ts: self.parse_timestamp(field(CsvColumn::Ts)?).map_err(|err| format!("Row {}: {}", row, err))?,
+                broker: field(CsvColumn::Broker)?.to_string(),
+                symbol,
+                ask: number(CsvColumn::Ask)?,
+                bid: number(CsvColumn::Bid)?,
+                spread
+            });
+        }
+
+        Ok(quotes)
+    }
+
+    /// # Reads the quotes of the file.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be opened, or the errors of `read`.
+    pub fn read_file(
+        &self,
+        path: impl AsRef<Path>
+    ) -> Result<Vec<Quote>, Box<dyn StdError + Send + Sync + 'static>> {
+        match self.read(BufReader::new(File::open(path.as_ref())?)) {
+            Ok(quotes) => Ok(quotes),
+            Err(err) => {
+                error!("Failed to read quotes from {}: {}", path.as_ref().display(), err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Formats a single field of the quote.
+    fn field(
+        &self,
+        quote: &Quote,
+        column: CsvColumn
+    ) -> String {
+        match column {
+            CsvColumn::Ts => quote.ts.format(&self.timestamp_format).to_string(),
+            CsvColumn::Broker => quote.broker.clone(),
+            CsvColumn::Symbol => quote.symbol.clone(),
+            CsvColumn::Ask => quote.ask.to_string(),
+            CsvColumn::Bid => quote.bid.to_string(),
+            CsvColumn::Spread => quote.spread.to_string(),
+            CsvColumn::Pips => quote.pips().to_string()
+        }
+    }
+
+    /// Parses a timestamp with the format, as UTC unless the format carries an offset.
+    fn parse_timestamp(
+        &self,
+        value: &str
+    ) -> Result<DateTime<Utc>, String> {
+        if let Ok(ts) = DateTime::parse_from_str(value, &self.timestamp_format) {
+            return Ok(ts.with_timezone(&Utc));
+        }
+
+        NaiveDateTime::parse_from_str(value, &self.timestamp_format)
+            .map(|ts| ts.and_utc())
+            .map_err(|err| format!("`ts` {:?} does not match {:?}: {}", value, self.timestamp_format, err))
+    }
+}
@@ -15,6 +15,8 @@ use std::collections::HashSet;
 /// ### Examples
 ///
 /// ```
+/// use spread_tracker::utils::duplicates::remove_duplicates;
+///
 /// let inputs = vec!["a b c a b".to_string(), "1 2 3 2 1".to_string()];
 /// let outputs = remove_duplicates(inputs);
 /// assert_eq!(outputs, vec!["a b c".to_string(), "1 2 3".to_string()]);
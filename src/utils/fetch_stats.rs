@@ -0,0 +1,102 @@
+//! # Fetch statistics
+//!
+//! Counters of the broker pages requested by the tracker: full downloads, `304 Not Modified` answers and failures.
+//! They tell how much the conditional requests and the snapshot cache actually save.
+
+use serde_derive::Serialize;
+use std::sync::{
+    Arc,
+    OnceLock
+};
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering
+};
+
+
+/// The statistics shared by every `SpreadTracker` that was not given its own statistics.
+static GLOBAL_FETCH_STATS: OnceLock<Arc<FetchStats>> = OnceLock::new();
+
+
+/// Struct that holds a copy of the counters of `FetchStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FetchCounts {
+    /// Every request sent, including retries.
+    pub requests: u64,
+    /// Responses that carried a full page.
+    pub downloaded: u64,
+    /// `304 Not Modified` responses, the previous snapshot was reused.
+    pub not_modified: u64,
+    /// Requests that failed, including block pages.
+    pub failed: u64,
+    /// Bytes of the full pages that were downloaded.
+    pub bytes_downloaded: u64
+}
+
+
+/// The `FetchStats` struct counts the broker pages requested by the tracker.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::utils::fetch_stats::FetchStats;
+///
+/// let stats = FetchStats::new();
+/// stats.record_downloaded(5120);
+/// stats.record_not_modified();
+///
+/// let counts = stats.counts();
+/// assert_eq!(counts.requests, 2);
+/// assert_eq!(counts.not_modified, 1);
+/// assert_eq!(counts.bytes_downloaded, 5120);
+/// ```
+#[derive(Debug, Default)]
+pub struct FetchStats {
+    requests: AtomicU64,
+    downloaded: AtomicU64,
+    not_modified: AtomicU64,
+    failed: AtomicU64,
+    bytes_downloaded: AtomicU64
+}
+
+impl FetchStats {
+    /// # Creates `FetchStats` with every counter at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Returns the process-wide statistics.
+    pub fn global() -> Arc<FetchStats> {
+        GLOBAL_FETCH_STATS.get_or_init(|| Arc::new(FetchStats::new())).clone()
+    }
+
+    /// Counts a response that carried a full page of the given size.
+    pub fn record_downloaded(&self, bytes: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.downloaded.fetch_add(1, Ordering::Relaxed);
+        self.bytes_downloaded.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a `304 Not Modified` response.
+    pub fn record_not_modified(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.not_modified.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a failed request.
+    pub fn record_failed(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// # Returns a copy of the counters.
+    pub fn counts(&self) -> FetchCounts {
+        FetchCounts {
+            requests: self.requests.load(Ordering::Relaxed),
+            downloaded: self.downloaded.load(Ordering::Relaxed),
+            not_modified: self.not_modified.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed)
+        }
+    }
+}
@@ -33,6 +33,8 @@ use tracing::{
 /// ### Examples
 ///
 /// ```
+/// use spread_tracker::utils::format::vec_to_json;
+///
 /// let input = vec!["AUDCHF 0.593 0.4821 1.4495".to_string()];
 /// let json_output = vec_to_json(input).unwrap();
 /// println!("{}", json_output);
@@ -130,6 +132,8 @@ pub fn vec_to_json(
 /// ### Examples
 ///
 /// ```
+/// use spread_tracker::utils::format::wrap_json_under_key;
+///
 /// let data = serde_json::json!({"name": "John Doe", "age": 30});
 /// let wrapped_data = wrap_json_under_key(data, "person".to_string()).unwrap();
 /// println!("{}", wrapped_data);
@@ -161,6 +165,8 @@ pub fn wrap_json_under_key(
 /// ### Examples
 ///
 /// ```
+/// use spread_tracker::utils::format::extract_broker_name;
+///
 /// let broker_url = "https://www.myfxbook.com/forex-broker-quotes/vantage/6052";
 /// let broker_name = extract_broker_name(broker_url).unwrap();
 /// println!("{}", broker_name);
@@ -182,3 +188,29 @@ pub fn extract_broker_name(
     }
     Err("Could not extract broker name from URL")
 }
+
+
+/// # Extracts the source id, the number at the end of the broker URL.
+///
+/// ### Examples
+///
+/// ```
+/// use spread_tracker::utils::format::extract_source_id;
+///
+/// let broker_url = "https://www.myfxbook.com/forex-broker-quotes/vantage/6052";
+/// assert_eq!(extract_source_id(broker_url).unwrap(), 6052);
+/// ```
+///
+/// ### Errors
+/// `Could not extract source id from URL` will be returned if the URL does not end with a number.
+///
+pub fn extract_source_id(
+    url: &str
+) -> Result<u32, &'static str> {
+    let re: Regex = regex::Regex::new(r"/(\d+)/?$").unwrap();
+
+    re.captures(url)
+        .and_then(|caps| caps.get(1))
+        .and_then(|matched| matched.as_str().parse::<u32>().ok())
+        .ok_or("Could not extract source id from URL")
+}
@@ -6,12 +6,33 @@
 //! - `vec_to_json` - Converts a vector of strings into a JSON string.
 //! - `wrap_json_under_key` - Wraps a JSON object under a key.
 //! - `extract_broker_name` - A function that extracts the broker name from a URL.
+//! - `extract_source_id` - A function that extracts the source id from a URL.
 //! - `find_symbol_spread` - A function that finds the symbol spread from a given URL.
+//! - `RequestBuilder` - Builds the HTTP client shared by every source.
+//! - `RetryPolicy` - Retries failed broker fetches with exponential backoff.
+//! - `RateLimiter` - Keeps the requests to every host within a shared budget.
+//! - `detect_block` - Recognizes anti-bot challenge, captcha, login-wall and error pages.
+//! - `ResponseArchive` - An opt-in, compressed archive of the raw broker responses.
+//! - `HttpTransport` - The HTTP layer of the tracker, with record / replay implementations for offline tests.
+//! - `ValidatorStore` - The `ETag` / `Last-Modified` of every broker URL, for conditional requests.
+//! - `FetchStats` - Counters of the downloaded, not modified and failed broker pages.
+//! - `ParquetExporter` - Exports quotes to Parquet files, partitioned by date and broker.
+//! - `NdjsonWriter` - Appends quotes or snapshots to rotating, optionally gzip-compressed NDJSON files.
+//! - `CsvFormat` - Writes quotes to CSV and reads them back, with a configurable delimiter, columns and timestamp format.
 //!
 
 
 pub mod save_to_json;
 pub mod request_builder;
+pub mod retry;
+pub mod rate_limiter;
+pub mod block_detection;
+pub mod archive;
+pub mod transport;
+pub mod conditional;
+pub mod fetch_stats;
+pub mod parquet_export;
+pub mod csv_io;
 pub mod regex_finder;
 pub mod cleaner;
 pub mod duplicates;
@@ -0,0 +1,389 @@
+//! # Parquet export
+//!
+//! Exports quotes to Parquet files, for loading the spread history into pandas, Polars or DuckDB.
+//!
+//! The files are partitioned by date and broker, Hive style, so the readers can prune them on both:
+//!
+//! ```text
+//! <dir>/date=2024-05-01/broker=fxpro/quotes.parquet
+//! ```
+//!
+//! Every file has the same typed schema:
+//!
+//! | column      | type                          |
+//! |-------------|-------------------------------|
+//! | `ts`        | timestamp, microseconds, UTC  |
+//! | `broker`    | string                        |
+//! | `symbol`    | string                        |
+//! | `ask`       | double                        |
+//! | `bid`       | double                        |
+//! | `spread`    | double                        |
+//! | `pips`      | double, see `Quote::pips`     |
+//! | `source_id` | int64, null when unknown      |
+//!
+//! The source id is the number at the end of the broker URL, `5168` for `.../forex-broker-quotes/fxpro/5168`.
+//! There is one file per partition. An export merges its quotes into the file already there, deduplicated on
+//! `(broker, symbol, ts)` with the exported quote winning, so overlapping exports never duplicate rows.
+//!
+//! ```python
+//! import duckdb
+//! duckdb.sql("select broker, symbol, avg(pips) from 'export/**/*.parquet' group by all")
+//! ```
+
+use arrow_array::{
+    Array,
+    ArrayRef,
+    Float64Array,
+    Int64Array,
+    RecordBatch,
+    StringArray,
+    TimestampMicrosecondArray
+};
+use arrow_schema::{
+    DataType,
+    Field,
+    Schema,
+    SchemaRef,
+    TimeUnit
+};
+use chrono::{
+    DateTime,
+    NaiveDate
+};
+use parquet::arrow::ArrowWriter;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::collections::{
+    BTreeMap,
+    HashMap
+};
+use std::error::Error as StdError;
+use std::fs::{
+    self,
+    File
+};
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::sync::Arc;
+
+use crate::config::load_section;
+use crate::db::storage::{
+    Db,
+    QuoteFilter,
+    dedupe_quotes
+};
+use crate::model::{
+    BrokerSnapshot,
+    Quote
+};
+use crate::utils::archive::sanitize_broker;
+use crate::utils::format::{
+    extract_broker_name,
+    extract_source_id
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The number of rows per row group, a day of a broker rarely needs more than one.
+const ROW_GROUP_SIZE: usize = 128 * 1024;
+
+/// The name of the file of every partition.
+pub const FILE_NAME: &str = "quotes.parquet";
+
+
+/// # Returns the schema of the exported files.
+pub fn quote_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("ts", DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())), false),
+        Field::new("broker", DataType::Utf8, false),
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("ask", DataType::Float64, false),
+        Field::new("bid", DataType::Float64, false),
+        Field::new("spread", DataType::Float64, false),
+        Field::new("pips", DataType::Float64, false),
+        Field::new("source_id", DataType::Int64, true)
+    ]))
+}
+
+
+/// The `ParquetExporter` struct writes quotes to Parquet files, partitioned by date and broker.
+///
+/// ### Example
+///
+/// ```
+/// use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+/// use spread_tracker::model::BrokerSnapshot;
+/// use spread_tracker::utils::parquet_export::ParquetExporter;
+///
+/// let dir = std::env::temp_dir().join(format!("spread_parquet_doc_{}", std::process::id()));
+/// let exporter = ParquetExporter::new(&dir);
+///
+/// let snapshot = BrokerSnapshot {
+///     broker: "fxpro".to_string(),
+///     url: "https://www.myfxbook.com/forex-broker-quotes/fxpro/5168".to_string(),
+///     quotes: serde_json::json!([
+///         { "symbol": "EURUSD", "ask": 1.0712, "bid": 1.0714, "spread": 0.0002 },
+///         { "symbol": "USDJPY", "ask": 155.12, "bid": 155.15, "spread": 0.03 }
+///     ]),
+///     fetched_at: "2024-05-01T12:00:00Z".parse().unwrap()
+/// };
+///
+/// let files = exporter.export_snapshots(&[snapshot.clone()]).unwrap();
+/// assert_eq!(files.len(), 1);
+/// assert!(files[0].starts_with(dir.join("date=2024-05-01").join("broker=fxpro")));
+///
+/// // The files read back with any Parquet reader, here the one of the `parquet` crate.
+/// let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&files[0]).unwrap()).unwrap().build().unwrap();
+/// let batch = reader.map(|batch| batch.unwrap()).next().unwrap();
+/// assert_eq!(batch.num_rows(), 2);
+/// assert_eq!(batch.schema().field(6).name(), "pips");
+///
+/// // Exporting overlapping quotes again merges them into the same file, without duplicates.
+/// let later = BrokerSnapshot {
+///     quotes: serde_json::json!([{ "symbol": "EURUSD", "ask": 1.0713, "bid": 1.0714, "spread": 0.0001 }]),
+///     fetched_at: "2024-05-01T12:05:00Z".parse().unwrap(),
+///     ..snapshot.clone()
+/// };
+/// assert_eq!(exporter.export_snapshots(&[snapshot, later]).unwrap(), files);
+/// let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&files[0]).unwrap()).unwrap().build().unwrap();
+/// assert_eq!(reader.map(|batch| batch.unwrap().num_rows()).sum::<usize>(), 3);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParquetExporter {
+    dir: PathBuf,
+    source_ids: HashMap<String, u32>
+}
+
+impl ParquetExporter {
+    /// # Creates an exporter that writes under the directory, without known source ids.
+    pub fn new(
+        dir: impl AsRef<Path>
+    ) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            source_ids: HashMap::new()
+        }
+    }
+
+    /// # Creates an exporter that takes the source id of every broker from the `BrokerSpreadUrls` section.
+    ///
+    /// ### Errors
+    /// `failed_to_read_yaml` will be returned if the section is not in the correct format.
+    pub fn from_config(
+        dir: impl AsRef<Path>
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let urls: HashMap<String, String> = load_section("BrokerSpreadUrls")?;
+
+        let source_ids: HashMap<String, u32> = urls.values()
+            .filter_map(|url| Some((extract_broker_name(url).ok()?, extract_source_id(url).ok()?)))
+            .collect();
+
+        Ok(Self::new(dir).with_source_ids(source_ids))
+    }
+
+    /// Sets the source id of every broker, keyed by the broker name of the quotes.
+    pub fn with_source_ids(mut self, source_ids: HashMap<String, u32>) -> Self {
+        self.source_ids = source_ids;
+        self
+    }
+
+    /// Returns the directory the files are written under.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// # Exports the quotes, and returns the files written, one per date and broker.
+    ///
+    /// ### Errors
+    /// An error will be returned if a file could not be written, the files written before it are kept.
+    pub fn export_quotes(
+        &self,
+        quotes: &[Quote]
+    ) -> Result<Vec<PathBuf>, Box<dyn StdError + Send + Sync + 'static>> {
+        self.export(quotes, &HashMap::new())
+    }
+
+    /// # Exports the quotes of the snapshots, the source id of each is taken from its URL.
+    ///
+    /// ### Errors
+    /// An error will be returned if a file could not be written, the files written before it are kept.
+    pub fn export_snapshots(
+        &self,
+        snapshots: &[BrokerSnapshot]
+    ) -> Result<Vec<PathBuf>, Box<dyn StdError + Send + Sync + 'static>> {
+        let source_ids: HashMap<String, u32> = snapshots.iter()
+            .filter_map(|snapshot| Some((snapshot.broker.clone(), extract_source_id(&snapshot.url).ok()?)))
+            .collect();
+        let quotes: Vec<Quote> = snapshots.iter().flat_map(Quote::from_snapshot).collect();
+
+        self.export(&quotes, &source_ids)
+    }
+
+    /// # Exports the stored quotes matching the filter.
+    ///
+    /// ### Errors
+    /// An error will be returned if the query failed or a file could not be written.
+    pub async fn export_db(
+        &self,
+        db: &dyn Db,
+        filter: &QuoteFilter
+    ) -> Result<Vec<PathBuf>, Box<dyn StdError + Send + Sync + 'static>> {
+        let quotes: Vec<Quote> = db.query_quotes(filter).await?;
+        if quotes.is_empty() {
+            warn!("No stored quote matches {:?}, nothing to export", filter);
+            return Ok(Vec::new());
+        }
+
+        let exporter: ParquetExporter = self.clone();
+        tokio::task::spawn_blocking(move || exporter.export_quotes(&quotes)).await?
+    }
+
+    /// Groups the quotes by date and broker, and merges every group into the file of its partition.
+    fn export(
+        &self,
+        quotes: &[Quote],
+        source_ids: &HashMap<String, u32>
+    ) -> Result<Vec<PathBuf>, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut partitions: BTreeMap<(NaiveDate, &str), Vec<&Quote>> = BTreeMap::new();
+        for quote in quotes {
+            partitions.entry((quote.ts.date_naive(), &quote.broker)).or_default().push(quote);
+        }
+
+        let mut files: Vec<PathBuf> = Vec::with_capacity(partitions.len());
+        for ((date, broker), rows) in partitions {
+            let path: PathBuf = self.dir
+                .join(format!("date={}", date))
+                .join(format!("broker={}", sanitize_broker(broker)))
+                .join(FILE_NAME);
+
+            // The quotes already exported to the partition come first, so the new ones win the dedupe
+            let (mut merged, existing_source_id): (Vec<Quote>, Option<u32>) = match path.exists() {
+                true => read_file(&path)?,
+                false => (Vec::new(), None)
+            };
+            merged.extend(rows.into_iter().cloned());
+            let mut rows: Vec<&Quote> = dedupe_quotes(&merged);
+            rows.sort_by(|a, b| (a.ts, &a.symbol).cmp(&(b.ts, &b.symbol)));
+            let source_id: Option<u32> = source_ids.get(broker)
+                .or_else(|| self.source_ids.get(broker))
+                .copied()
+                .or(existing_source_id);
+
+            if let Err(err) = write_file(&path, &rows, source_id) {
+                error!("Failed to export {} quote(s) to {}: {}", rows.len(), path.display(), err);
+                return Err(err);
+            }
+            files.push(path);
+        }
+        info!("Exported {} quote(s) to {} Parquet file(s) under {}", quotes.len(), files.len(), self.dir.display());
+
+        Ok(files)
+    }
+}
+
+
+/// Writes the quotes to a temporary file next to the path, then renames it, so readers never see half a file.
+fn write_file(
+    path: &Path,
+    quotes: &[&Quote],
+    source_id: Option<u32>
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let dir: &Path = path.parent().ok_or("The export path has no parent directory")?;
+    fs::create_dir_all(dir)?;
+
+    let batch: RecordBatch = record_batch(quotes, source_id)?;
+    let properties: WriterProperties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_size(ROW_GROUP_SIZE)
+        .build();
+
+    let temp_path: PathBuf = dir.join(format!(".{}.{}.tmp", path.file_name().unwrap_or_default().to_string_lossy(), std::process::id()));
+    let result: Result<(), Box<dyn StdError + Send + Sync + 'static>> = (|| {
+        let mut writer: ArrowWriter<File> = ArrowWriter::try_new(File::create(&temp_path)?, batch.schema(), Some(properties))?;
+        writer.write(&batch)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+
+/// Reads back the quotes of an exported file, and its source id.
+fn read_file(
+    path: &Path
+) -> Result<(Vec<Quote>, Option<u32>), Box<dyn StdError + Send + Sync + 'static>> {
+    let mut quotes: Vec<Quote> = Vec::new();
+    let mut source_id: Option<u32> = None;
+
+    for batch in ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()? {
+        let batch: RecordBatch = batch?;
+        let ts: &TimestampMicrosecondArray = column(&batch, "ts")?;
+        let broker: &StringArray = column(&batch, "broker")?;
+        let symbol: &StringArray = column(&batch, "symbol")?;
+        let ask: &Float64Array = column(&batch, "ask")?;
+        let bid: &Float64Array = column(&batch, "bid")?;
+        let spread: &Float64Array = column(&batch, "spread")?;
+        let source_ids: &Int64Array = column(&batch, "source_id")?;
+
+        for row in 0..batch.num_rows() {
+            quotes.push(Quote {
+                ts: DateTime::from_timestamp_micros(ts.value(row)).ok_or("The export holds a timestamp out of range")?,
+                broker: broker.value(row).to_string(),
+                symbol: symbol.value(row).to_string(),
+                ask: ask.value(row),
+                bid: bid.value(row),
+                spread: spread.value(row)
+            });
+            if source_id.is_none() && source_ids.is_valid(row) {
+                source_id = u32::try_from(source_ids.value(row)).ok();
+            }
+        }
+    }
+
+    Ok((quotes, source_id))
+}
+
+
+/// The column of the batch, as its array type.
+fn column<'a, T: 'static>(
+    batch: &'a RecordBatch,
+    name: &str
+) -> Result<&'a T, Box<dyn StdError + Send + Sync + 'static>> {
+    batch.column_by_name(name)
+        .and_then(|column| column.as_any().downcast_ref::<T>())
+        .ok_or_else(|| format!("The export has no {} column of the expected type", name).into())
+}
+
+
+/// Builds the columns of the quotes, in the order of `quote_schema`.
+fn record_batch(
+    quotes: &[&Quote],
+    source_id: Option<u32>
+) -> Result<RecordBatch, Box<dyn StdError + Send + Sync + 'static>> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(TimestampMicrosecondArray::from_iter_values(quotes.iter().map(|quote| quote.ts.timestamp_micros())).with_timezone("UTC")),
+        Arc::new(StringArray::from_iter_values(quotes.iter().map(|quote| &quote.broker))),
+        Arc::new(StringArray::from_iter_values(quotes.iter().map(|quote| &quote.symbol))),
+        Arc::new(Float64Array::from_iter_values(quotes.iter().map(|quote| quote.ask))),
+        Arc::new(Float64Array::from_iter_values(quotes.iter().map(|quote| quote.bid))),
+        Arc::new(Float64Array::from_iter_values(quotes.iter().map(|quote| quote.spread))),
+        Arc::new(Float64Array::from_iter_values(quotes.iter().map(|quote| quote.pips()))),
+        Arc::new(Int64Array::from(vec![source_id.map(i64::from); quotes.len()]))
+    ];
+
+    Ok(RecordBatch::try_new(quote_schema(), columns)?)
+}
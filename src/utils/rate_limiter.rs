@@ -0,0 +1,169 @@
+//! # Per-host rate limiter
+//!
+//! A token bucket per host, so polling all the myfxbook pages stays within one polite request budget.
+//! The process-wide limiter returned by `RateLimiter::global` is shared by every `SpreadTracker`,
+//! so several tasks polling at once still respect the same budget.
+
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::{
+    Arc,
+    Mutex,
+    OnceLock
+};
+use std::time::{
+    Duration,
+    Instant
+};
+
+use crate::config::RateLimitConfig;
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The limiter shared by every `SpreadTracker` that was not given its own limiter.
+static GLOBAL_RATE_LIMITER: OnceLock<Arc<RateLimiter>> = OnceLock::new();
+
+
+/// The token bucket of a single host.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant
+}
+
+
+/// The `RateLimiter` struct hands out request slots per host, with a steady rate and a burst.
+///
+/// A request that finds the bucket empty reserves the next free slot and waits for it,
+/// so concurrent callers are served in the order they arrived.
+///
+/// ### Example
+///
+/// ```
+/// use spread_tracker::utils::rate_limiter::RateLimiter;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let limiter = RateLimiter::new(1.0, 2);
+///
+/// // The burst is served right away, the third request waits for a slot.
+/// limiter.acquire("www.myfxbook.com").await;
+/// limiter.acquire("www.myfxbook.com").await;
+/// assert!(limiter.reserve("www.myfxbook.com").is_some());
+///
+/// // Other hosts have their own bucket.
+/// assert!(limiter.reserve("example.com").is_none());
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>
+}
+
+impl RateLimiter {
+    /// # Creates a `RateLimiter` with the given steady rate and burst per host.
+    ///
+    /// A rate of `0` disables the limiter, a burst of `0` is treated as `1`.
+    pub fn new(
+        requests_per_second: f64,
+        burst: u32
+    ) -> Self {
+        Self {
+            requests_per_second: requests_per_second.max(0.0),
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// # Creates a `RateLimiter` from the `RateLimit` section of the configuration.
+    pub fn from_config(
+        config: &RateLimitConfig
+    ) -> Self {
+        Self::new(config.requests_per_second, config.burst)
+    }
+
+    /// # A limiter that never waits.
+    pub fn unlimited() -> Self {
+        Self::new(0.0, 1)
+    }
+
+    /// # Returns the process-wide limiter, built from the `RateLimit` section of the configuration on first use.
+    ///
+    /// A section that is not in the correct format is logged and replaced by the default budget,
+    /// the limiter is never skipped because of a configuration mistake.
+    pub fn global() -> Arc<RateLimiter> {
+        GLOBAL_RATE_LIMITER.get_or_init(|| {
+            let config: RateLimitConfig = RateLimitConfig::load().unwrap_or_else(|error| {
+                error!("Failed to read the RateLimit section, using the default budget: {}", error);
+                RateLimitConfig::default()
+            });
+            info!("Rate limiting every host to {} request(s) per second, burst {}", config.requests_per_second, config.burst);
+
+            Arc::new(RateLimiter::from_config(&config))
+        }).clone()
+    }
+
+    /// # Reserves a request slot for the host, and returns how long the caller must wait for it.
+    ///
+    /// `None` means the slot is available right away.
+    pub fn reserve(
+        &self,
+        host: &str
+    ) -> Option<Duration> {
+        if self.requests_per_second <= 0.0 {
+            return None;
+        }
+
+        let now: Instant = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let bucket: &mut Bucket = buckets.entry(host.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now
+        });
+
+        let elapsed: f64 = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.requests_per_second).min(self.burst);
+        bucket.updated = now;
+        bucket.tokens -= 1.0;
+
+        if bucket.tokens >= 0.0 {
+            return None;
+        }
+
+        Some(Duration::from_secs_f64(-bucket.tokens / self.requests_per_second))
+    }
+
+    /// # Waits until a request to the host fits in the budget.
+    pub async fn acquire(
+        &self,
+        host: &str
+    ) {
+        if let Some(wait) = self.reserve(host) {
+            info!("Rate limit reached for {}, waiting {:?}", host, wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// # Waits until a request to the host of the URL fits in the budget.
+    ///
+    /// URLs without a host are not limited.
+    pub async fn acquire_url(
+        &self,
+        url: &str
+    ) {
+        let host: Option<String> = Url::parse(url).ok()
+            .and_then(|url| url.host_str().map(|host| host.to_string()));
+
+        match host {
+            Some(host) => self.acquire(&host).await,
+            None => warn!("Not rate limiting {}, it has no host", url)
+        }
+    }
+}
@@ -8,6 +8,7 @@ use crate::model::{
     IsSymbol
 };
 use regex::Regex;
+use std::collections::VecDeque;
 use crate::utils::cleaner::remove_banned_chars;
 use crate::utils::duplicates::remove_duplicates;
 
@@ -17,6 +18,10 @@ use tracing::{
     error
 };
 
+/// How many lines after the `Spread` anchor are looked at for the spread value.
+const FOLLOWING_LINES: usize = 2;
+
+
 /// # Find the symbol spread in the HTML body.
 ///
 ///
@@ -24,13 +29,29 @@ use tracing::{
 /// ```
 /// use spread_tracker::utils::regex_finder::find_symbol_spread;
 ///
-/// let body = "<span></span> 1.1234 1.1233 0.0001Spread";
+/// let body = "<span></span>\nAUDCAD\n0.90269\n1.40269\n<a id=\"5168_8Spread\"\n0.5";
 ///
 /// let spread = find_symbol_spread(& body);
 ///
-/// assert_eq!(spread, vec!["1.1234 1.1233 0.0001Spread"]);
+/// assert_eq!(spread, vec!["AUDCAD 0.90269 1.40269 0.5"]);
+///
+/// ```
+///
+/// On a broker page, the spread value sits two lines below its `Spread` anchor:
 ///
 /// ```
+/// use spread_tracker::utils::regex_finder::find_symbol_spread;
+///
+/// let body = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/body.txt")).unwrap();
+///
+/// let spread = find_symbol_spread(&body);
+///
+/// assert_eq!(spread.len(), 28);
+/// assert_eq!(spread[0], "XAUUSD 2803.38 2816.1073 12.7273");
+/// assert_eq!(spread[14], "EURUSD 1.02714 1.22714 0.2");
+/// assert_eq!(spread[16], "EURJPY 158.67 158.921 0.251");
+/// assert_eq!(spread[27], "AUDCAD 0.90269 1.40269 0.5");
+/// ```
 ///
 /// ### Errors
 /// `couldnt retrieve data` if the body is empty.
@@ -49,14 +70,16 @@ pub fn find_symbol_spread(
     let mut lines_with_spread: Vec<String> = Vec::new();
     let mut capture: bool = false;
     let mut captured_lines: Vec<String> = Vec::new();
-    let mut previous_line: String = String::new();
+    // the lines forward of the current one (previous lines in this reversed iteration), closest first;
+    // the spread value sits two lines after the `Spread` anchor, below its `href`
+    let mut following_lines: VecDeque<String> = VecDeque::with_capacity(FOLLOWING_LINES);
 
     for line in body.lines().rev() {
         if re.is_match(line) {
             capture = true;
         }
         if capture {
-            captured_lines.push(previous_line.clone()); // one line forward (previous line in this reversed iteration)
+            captured_lines.extend(following_lines.iter().rev().cloned()); // lines forward, furthest first
 
             captured_lines.push(line.to_string());
             if span_re.is_match(line) {
@@ -65,7 +88,11 @@ pub fn find_symbol_spread(
                 captured_lines.clear();
             }
         }
-        previous_line = line.to_string(); // track of the previous line
+        // track of the previous lines
+        if following_lines.len() == FOLLOWING_LINES {
+            following_lines.pop_back();
+        }
+        following_lines.push_front(line.to_string());
     }
 
 
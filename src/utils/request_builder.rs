@@ -1,25 +1,217 @@
 //! # Request Builder
+//!
+//! Builds the `reqwest::Client` that every source uses to download the broker pages.
+//! A single client keeps one connection pool and one cookie jar for the whole process,
+//! instead of a fresh pool for every request.
 
 #![allow(dead_code)]
 use reqwest::{
     Client,
-    Response
+    ClientBuilder,
+    Proxy,
+    redirect::Policy,
+    header::{
+        HeaderMap,
+        HeaderName,
+        HeaderValue
+    }
 };
+use std::error::Error as StdError;
+use std::sync::OnceLock;
+use std::time::Duration;
 
-use crate::model::{
-    Symbol,
-    SymbolSpread,
-    HttpsUrl
+use crate::config::HttpConfig;
+
+use tracing::{
+    info,
+    warn,
+    error
 };
-use crate::config::SpreadBrokerUrl;
 
-/// The `RequestBuilder` struct is used to build the request to get the spread data from the broker.
-/// This struct is used to build the request to get the spread data from the broker.
+
+/// The client shared by every `SpreadTracker` that was not given its own client.
+static SHARED_CLIENT: OnceLock<Client> = OnceLock::new();
+
+
+/// The `RequestBuilder` struct is used to build the HTTP client that downloads the spread data from the broker.
+/// Every setting has a sane default, so `RequestBuilder::new().build()` is enough for most use cases.
 ///
 /// ### Example
 ///
+/// ```
+/// use std::time::Duration;
+/// use spread_tracker::utils::request_builder::RequestBuilder;
 ///
+/// let client = RequestBuilder::new()
+///     .connect_timeout(Duration::from_secs(5))
+///     .read_timeout(Duration::from_secs(20))
+///     .user_agent("my-desk/1.0")
+///     .header("Accept-Language", "en-US")
+///     .max_redirects(3)
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// ### Errors
+/// `build` will return an error if a header name or value is not valid, or if the proxy URL could not be parsed.
+#[derive(Debug, Clone)]
 pub struct RequestBuilder {
-    client: Client,
-    spread_broker_url: SpreadBrokerUrl
-}
\ No newline at end of file
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    user_agent: String,
+    headers: Vec<(String, String)>,
+    compression: bool,
+    cookie_store: bool,
+    max_redirects: usize,
+    proxy: Option<String>
+}
+
+impl Default for RequestBuilder {
+    fn default() -> Self {
+        Self::from_config(&HttpConfig::default())
+    }
+}
+
+impl RequestBuilder {
+    /// # Creates a `RequestBuilder` with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Creates a `RequestBuilder` from the `Http` section of the configuration.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use spread_tracker::config::HttpConfig;
+    /// use spread_tracker::utils::request_builder::RequestBuilder;
+    ///
+    /// let client = RequestBuilder::from_config(&HttpConfig::load().unwrap()).build().unwrap();
+    /// ```
+    pub fn from_config(
+        config: &HttpConfig
+    ) -> Self {
+        let mut headers: Vec<(String, String)> = config.headers.iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        headers.sort();
+
+        Self {
+            connect_timeout: Duration::from_secs(config.connect_timeout_secs),
+            read_timeout: Duration::from_secs(config.read_timeout_secs),
+            user_agent: config.user_agent.clone(),
+            headers,
+            compression: config.compression,
+            cookie_store: config.cookie_store,
+            max_redirects: config.max_redirects,
+            proxy: config.proxy.clone()
+        }
+    }
+
+    /// Sets the maximum time spent on establishing a connection.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum time spent waiting on a single read of the response.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Sets the `User-Agent` header sent with every request.
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Adds a default header sent with every request.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Enables or disables gzip, brotli and deflate decompression of the response.
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    /// Enables or disables the cookie jar shared by all requests of the client.
+    pub fn cookie_store(mut self, enabled: bool) -> Self {
+        self.cookie_store = enabled;
+        self
+    }
+
+    /// Sets the maximum number of redirects to follow, `0` disables redirects.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Routes every request through the given proxy URL.
+    pub fn proxy(mut self, proxy: &str) -> Self {
+        self.proxy = Some(proxy.to_string());
+        self
+    }
+
+    /// # Builds the `reqwest::Client` from the settings.
+    ///
+    /// ### Errors
+    /// An error will be returned if a header name or value is not valid, or if the proxy URL could not be parsed.
+    pub fn build(
+        &self
+    ) -> Result<Client, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut headers: HeaderMap = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name: HeaderName = HeaderName::from_bytes(name.as_bytes())?;
+            let value: HeaderValue = HeaderValue::from_str(value)?;
+            headers.insert(name, value);
+        }
+
+        let redirect_policy: Policy = match self.max_redirects {
+            0 => Policy::none(),
+            max_redirects => Policy::limited(max_redirects)
+        };
+
+        let mut builder: ClientBuilder = Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+            .user_agent(self.user_agent.as_str())
+            .default_headers(headers)
+            .gzip(self.compression)
+            .brotli(self.compression)
+            .deflate(self.compression)
+            .cookie_store(self.cookie_store)
+            .redirect(redirect_policy);
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy.as_str())?);
+        }
+
+        let client: Client = builder.build()?;
+
+        Ok(client)
+    }
+}
+
+
+/// # Returns the process-wide client, built from the `Http` section of the configuration on first use.
+///
+/// Cloning a `reqwest::Client` is cheap and shares the connection pool and cookie jar,
+/// so every caller of this function talks through the same pool.
+///
+/// ### Errors
+/// `failed_to_read_yaml` will be returned if the `Http` section is not in the correct format.
+/// An error will also be returned if the client could not be built, see `RequestBuilder::build`.
+pub fn shared_client() -> Result<Client, Box<dyn StdError + Send + Sync + 'static>> {
+    if let Some(client) = SHARED_CLIENT.get() {
+        return Ok(client.clone());
+    }
+
+    let client: Client = RequestBuilder::from_config(&HttpConfig::load()?).build()?;
+    info!("Built the shared HTTP client");
+
+    Ok(SHARED_CLIENT.get_or_init(|| client).clone())
+}
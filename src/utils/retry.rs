@@ -0,0 +1,237 @@
+//! # Retry policy for broker fetches
+//!
+//! Decides whether a failed fetch is worth another attempt and how long to wait before it.
+//! Delays grow exponentially with jitter, and a `Retry-After` header sent with a 429 or 503 takes precedence.
+
+use rand::Rng;
+use reqwest::StatusCode;
+use std::error::Error as StdError;
+use std::time::{
+    Duration,
+    SystemTime
+};
+
+use crate::config::RetryConfig;
+use crate::errors::HttpStatusError;
+
+
+/// The `ErrorClass` enum tells whether a failed fetch may succeed when it is tried again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Timeouts, connection failures, 408, 425, 429 and 5xx gateway errors.
+    Retryable,
+    /// Everything else, like 404 or an invalid URL, trying again would only burn a request.
+    Permanent
+}
+
+
+/// The `RetryPolicy` struct holds the maximum number of attempts and the backoff settings.
+///
+/// ### Example
+///
+/// ```
+/// use std::time::Duration;
+/// use spread_tracker::utils::retry::RetryPolicy;
+///
+/// let policy = RetryPolicy::new()
+///     .max_attempts(4)
+///     .base_delay(Duration::from_millis(100))
+///     .jitter(false);
+///
+/// assert_eq!(policy.backoff(1), Duration::from_millis(100));
+/// assert_eq!(policy.backoff(3), Duration::from_millis(400));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    max_retry_after: Duration
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::from_config(&RetryConfig::default())
+    }
+}
+
+impl RetryPolicy {
+    /// # Creates a `RetryPolicy` with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Creates a `RetryPolicy` from the `Retry` section of the configuration.
+    pub fn from_config(
+        config: &RetryConfig
+    ) -> Self {
+        Self {
+            max_attempts: config.max_attempts.max(1),
+            base_delay: Duration::from_millis(config.base_delay_ms),
+            max_delay: Duration::from_millis(config.max_delay_ms),
+            jitter: config.jitter,
+            max_retry_after: Duration::from_secs(config.max_retry_after_secs)
+        }
+    }
+
+    /// # A policy that never retries, every fetch gets exactly one attempt.
+    pub fn no_retry() -> Self {
+        Self::new().max_attempts(1)
+    }
+
+    /// Sets the maximum number of attempts, including the first one.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry, it doubles with every following retry.
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// Sets the upper bound of the exponential backoff.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Enables or disables the random jitter applied to the backoff.
+    pub fn jitter(mut self, enabled: bool) -> Self {
+        self.jitter = enabled;
+        self
+    }
+
+    /// Sets the longest `Retry-After` delay that is honored, longer delays are clamped.
+    pub fn max_retry_after(mut self, delay: Duration) -> Self {
+        self.max_retry_after = delay;
+        self
+    }
+
+    /// Returns the maximum number of attempts, including the first one.
+    pub fn attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// # Returns the exponential backoff after the given (1-based) failed attempt.
+    ///
+    /// With jitter enabled, the delay is picked at random between half and the full backoff,
+    /// so concurrent pollers that failed together do not retry in lockstep.
+    pub fn backoff(
+        &self,
+        attempt: u32
+    ) -> Duration {
+        let exponent: u32 = attempt.saturating_sub(1).min(31);
+        let delay: Duration = self.base_delay
+            .saturating_mul(1u32 << exponent)
+            .min(self.max_delay);
+
+        if !self.jitter || delay.is_zero() {
+            return delay;
+        }
+
+        let half: Duration = delay / 2;
+        let spread: u64 = (delay - half).as_millis() as u64;
+        half + Duration::from_millis(rand::thread_rng().gen_range(0..=spread))
+    }
+
+    /// # Returns the delay before retrying a fetch that failed with the given error.
+    ///
+    /// A `Retry-After` sent with a 429 or 503 is honored up to `max_retry_after`, otherwise the backoff is used.
+    pub fn delay_for(
+        &self,
+        attempt: u32,
+        error: &(dyn StdError + 'static)
+    ) -> Duration {
+        if let Some(status_error) = error.downcast_ref::<HttpStatusError>() {
+            let honors_retry_after: bool = matches!(status_error.status, 429 | 503);
+            if let (true, Some(retry_after)) = (honors_retry_after, status_error.retry_after) {
+                return retry_after.min(self.max_retry_after);
+            }
+        }
+
+        self.backoff(attempt)
+    }
+}
+
+
+/// # Classifies an HTTP status code into retryable and permanent failures.
+///
+/// ### Example
+///
+/// ```
+/// use reqwest::StatusCode;
+/// use spread_tracker::utils::retry::{ classify_status, ErrorClass };
+///
+/// assert_eq!(classify_status(StatusCode::SERVICE_UNAVAILABLE), ErrorClass::Retryable);
+/// assert_eq!(classify_status(StatusCode::NOT_FOUND), ErrorClass::Permanent);
+/// ```
+pub fn classify_status(
+    status: StatusCode
+) -> ErrorClass {
+    match status.as_u16() {
+        408 | 425 | 429 | 500 | 502 | 503 | 504 => ErrorClass::Retryable,
+        _ => ErrorClass::Permanent
+    }
+}
+
+
+/// # Classifies a fetch error into retryable and permanent failures.
+///
+/// `HttpStatusError` is classified by its status code, timeouts and connection failures of `reqwest` are retryable,
+/// anything else is permanent.
+pub fn classify_error(
+    error: &(dyn StdError + 'static)
+) -> ErrorClass {
+    if let Some(status_error) = error.downcast_ref::<HttpStatusError>() {
+        return match StatusCode::from_u16(status_error.status) {
+            Ok(status) => classify_status(status),
+            Err(_) => ErrorClass::Permanent
+        };
+    }
+
+    if let Some(reqwest_error) = error.downcast_ref::<reqwest::Error>() {
+        if reqwest_error.is_timeout() || reqwest_error.is_connect() || reqwest_error.is_body() {
+            return ErrorClass::Retryable;
+        }
+        if let Some(status) = reqwest_error.status() {
+            return classify_status(status);
+        }
+        if reqwest_error.is_request() {
+            return ErrorClass::Retryable;
+        }
+    }
+
+    ErrorClass::Permanent
+}
+
+
+/// # Parses the value of a `Retry-After` header.
+///
+/// Both forms of the header are supported: a number of seconds, or an HTTP date.
+/// A date in the past yields a zero delay.
+///
+/// ### Example
+///
+/// ```
+/// use std::time::Duration;
+/// use spread_tracker::utils::retry::parse_retry_after;
+///
+/// assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+/// assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+/// assert_eq!(parse_retry_after("soon"), None);
+/// ```
+pub fn parse_retry_after(
+    value: &str
+) -> Option<Duration> {
+    let value: &str = value.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date: SystemTime = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+}
@@ -1,8 +1,524 @@
-#![allow(unused_imports)]
+//! # NDJSON history
+//!
+//! An append-only, newline-delimited JSON history of the fetched spreads, one record per line,
+//! readable with nothing more than a JSON parser, `jq` or `zcat`.
+//!
+//! A line holds a `Quote` or a `BrokerSnapshot`. The files are named after the UTC day they start and a counter:
+//!
+//! ```text
+//! <dir>/spreads-2024-05-01-000.ndjson.gz
+//! <dir>/spreads-2024-05-01-001.ndjson.gz
+//! <dir>/spreads-2024-05-02-000.ndjson
+//! ```
+//!
+//! A file is rotated once it reaches its size limit, and when the day of the records changes.
+//! Rotated files are finished: synced, and gzip-compressed when asked to. The file being written stays plain,
+//! so the next run appends to it. Every batch is flushed once written, and the writer flushes when dropped.
+//! A line cut short by a crash is cut off when the file is opened again, every line of a file is a complete record.
 
-use serde_json::{
-    Value,
-    json
+use chrono::{
+    DateTime,
+    NaiveDate,
+    Utc
 };
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::error::Error as StdError;
+use std::fs::{
+    self,
+    File,
+    OpenOptions
+};
+use std::io::{
+    self,
+    BufRead,
+    BufReader,
+    BufWriter,
+    ErrorKind,
+    Read,
+    Seek,
+    SeekFrom,
+    Write
+};
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::sync::Mutex;
+
+use crate::config::NdjsonConfig;
+use crate::model::{
+    BrokerSnapshot,
+    Quote
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// The extension of the file being written.
+const PLAIN_EXTENSION: &str = ".ndjson";
+
+/// The extension of a finished, compressed file.
+const GZIP_EXTENSION: &str = ".ndjson.gz";
+
+/// The date format of the file names, it sorts in chronological order.
+const FILE_DATE_FORMAT: &str = "%Y-%m-%d";
+
+
+/// Struct that points at a single file of the history on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NdjsonFile {
+    /// The UTC day of the first record of the file.
+    pub day: NaiveDate,
+    /// The counter of the file within its day.
+    pub index: u32,
+    pub gzip: bool,
+    pub path: PathBuf
+}
+
+
+/// The file being written.
+#[derive(Debug)]
+struct OpenFile {
+    day: NaiveDate,
+    index: u32,
+    path: PathBuf,
+    writer: BufWriter<File>,
+    bytes: u64
+}
+
+
+/// The `NdjsonWriter` struct appends records to rotating NDJSON files in a directory.
+///
+/// ### Example
+///
+/// ```
+/// use chrono::{ Duration, TimeZone, Utc };
+/// use spread_tracker::model::Quote;
+/// use spread_tracker::utils::save_to_json::{ NdjsonWriter, read_ndjson };
+///
+/// let dir = std::env::temp_dir().join(format!("spread_ndjson_doc_{}", std::process::id()));
+/// let writer = NdjsonWriter::new(&dir).max_bytes(Some(256)).gzip(true);
+///
+/// let start = Utc.with_ymd_and_hms(2024, 5, 1, 23, 57, 0).unwrap();
+/// let quotes: Vec<Quote> = (0..4)
+///     .map(|minute| Quote {
+///         ts: start + Duration::minutes(minute),
+///         broker: "fxpro".to_string(),
+///         symbol: "EURUSD".to_string(),
+///         ask: 1.0712,
+///         bid: 1.0714,
+///         spread: 0.0002
+///     })
+///     .collect();
+/// assert_eq!(writer.write_quotes(&quotes).unwrap(), 4);
+/// writer.close().unwrap();
+///
+/// // Two quotes a file on the 1st, then a new day. The finished files are compressed.
+/// let files = writer.files().unwrap();
+/// assert_eq!(files.iter().map(|file| (file.day.to_string(), file.index, file.gzip)).collect::<Vec<_>>(), vec![
+///     ("2024-05-01".to_string(), 0, true),
+///     ("2024-05-01".to_string(), 1, true),
+///     ("2024-05-02".to_string(), 0, false)
+/// ]);
+///
+/// let read: Vec<Quote> = files.iter().flat_map(|file| read_ndjson::<Quote>(&file.path).unwrap()).collect();
+/// assert_eq!(read, quotes);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct NdjsonWriter {
+    dir: PathBuf,
+    prefix: String,
+    max_bytes: Option<u64>,
+    rotate_daily: bool,
+    gzip: bool,
+    current: Mutex<Option<OpenFile>>
+}
+
+impl NdjsonWriter {
+    /// # Creates a writer in the given directory, rotating every day, without a size limit nor compression.
+    pub fn new(
+        dir: impl AsRef<Path>
+    ) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            prefix: "spreads".to_string(),
+            max_bytes: None,
+            rotate_daily: true,
+            gzip: false,
+            current: Mutex::new(None)
+        }
+    }
+
+    /// # Creates a writer from the `Ndjson` section of the configuration.
+    ///
+    /// Returns `None` when the history is not enabled.
+    pub fn from_config(
+        config: &NdjsonConfig
+    ) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+
+        let max_bytes: Option<u64> = Some(config.max_file_mb)
+            .filter(|mb| *mb > 0)
+            .map(|mb| mb * 1024 * 1024);
+
+        Some(
+            Self::new(&config.dir)
+                .max_bytes(max_bytes)
+                .rotate_daily(config.rotate_daily)
+                .gzip(config.gzip)
+        )
+    }
+
+    /// Sets the start of the file names, `spreads` by default.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Rotates a file before it grows past the given size. A record larger than the limit still gets a file of its own.
+    pub fn max_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Rotates the file when the UTC day of the records changes.
+    pub fn rotate_daily(mut self, rotate_daily: bool) -> Self {
+        self.rotate_daily = rotate_daily;
+        self
+    }
+
+    /// Compresses the rotated files to `.ndjson.gz`.
+    pub fn gzip(mut self, gzip: bool) -> Self {
+        self.gzip = gzip;
+        self
+    }
+
+    /// Returns the directory of the history.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// # Appends a line per quote, and returns how many were written.
+    ///
+    /// ### Errors
+    /// The errors of `write_records`.
+    pub fn write_quotes(
+        &self,
+        quotes: &[Quote]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        self.write_records(quotes, |quote| quote.ts)
+    }
+
+    /// # Appends a line per snapshot, and returns how many were written.
+    ///
+    /// ### Errors
+    /// The errors of `write_records`.
+    pub fn write_snapshots(
+        &self,
+        snapshots: &[BrokerSnapshot]
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        self.write_records(snapshots, |snapshot| snapshot.fetched_at)
+    }
+
+    /// # Appends a line per record, the time of each picks its file, then flushes the batch.
+    ///
+    /// ### Errors
+    /// An error will be returned if a record could not be serialized, or a file could not be written or rotated.
+    /// The records written before the error are kept.
+    pub fn write_records<T: Serialize>(
+        &self,
+        records: &[T],
+        ts: impl Fn(&T) -> DateTime<Utc>
+    ) -> Result<usize, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut current = self.current.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        for record in records {
+            let mut line: Vec<u8> = serde_json::to_vec(record)?;
+            line.push(b'\n');
+
+            let file: &mut OpenFile = self.file_for(&mut current, ts(record).date_naive(), line.len() as u64)?;
+            file.writer.write_all(&line)?;
+            file.bytes += line.len() as u64;
+        }
+
+        if let Some(file) = current.as_mut() {
+            file.writer.flush()?;
+        }
+
+        Ok(records.len())
+    }
+
+    /// # Flushes the file being written to the disk.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be flushed.
+    pub fn flush(
+        &self
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        let mut current = self.current.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(file) = current.as_mut() {
+            file.writer.flush()?;
+            file.writer.get_ref().sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    /// # Flushes and closes the file being written. It stays plain, the next write appends to it.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be flushed.
+    pub fn close(
+        &self
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        self.flush()?;
+        self.current.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+
+        Ok(())
+    }
+
+    /// # Lists the files of the history, oldest first.
+    ///
+    /// ### Errors
+    /// An error will be returned if the directory could not be read.
+    pub fn files(
+        &self
+    ) -> Result<Vec<NdjsonFile>, Box<dyn StdError + Send + Sync + 'static>> {
+        let read_dir = match fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(Box::new(error))
+        };
+
+        let mut files: Vec<NdjsonFile> = read_dir
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| self.parse_file_name(&entry.file_name().to_string_lossy(), entry.path()))
+            .collect();
+        files.sort_by_key(|file| (file.day, file.index, file.gzip));
+
+        Ok(files)
+    }
+
+    /// Returns the file a line of the day goes to, rotating the current one when it has to.
+    fn file_for<'a>(
+        &self,
+        current: &'a mut Option<OpenFile>,
+        day: NaiveDate,
+        line_bytes: u64
+    ) -> Result<&'a mut OpenFile, Box<dyn StdError + Send + Sync + 'static>> {
+        let rotate: bool = current.as_ref().is_some_and(|file|
+            (self.rotate_daily && file.day != day)
+                || self.max_bytes.is_some_and(|max_bytes| file.bytes > 0 && file.bytes + line_bytes > max_bytes)
+        );
+
+        if rotate {
+            if let Some(file) = current.take() {
+                self.finish(file)?;
+            }
+        }
+
+        match current {
+            Some(file) => Ok(file),
+            None => Ok(current.insert(self.open(day, !rotate)?))
+        }
+    }
+
+    /// Starts the next file of the day. With `resume`, the latest plain file of the day, or of any day without
+    /// daily rotation, is opened again instead when it has room left. Plain files left behind by an earlier run
+    /// are finished on the way.
+    fn open(
+        &self,
+        day: NaiveDate,
+        resume: bool
+    ) -> Result<OpenFile, Box<dyn StdError + Send + Sync + 'static>> {
+        fs::create_dir_all(&self.dir)?;
+
+        let files: Vec<NdjsonFile> = self.files()?;
+        let latest: Option<&NdjsonFile> = files.iter().rfind(|file| !self.rotate_daily || file.day == day);
+
+        let reopen: Option<&NdjsonFile> = latest.filter(|file|
+            resume && !file.gzip && self.max_bytes.is_none_or(|max_bytes| file.path.metadata().is_ok_and(|metadata| metadata.len() < max_bytes))
+        );
+        let (day, index, path): (NaiveDate, u32, PathBuf) = match reopen {
+            Some(file) => (file.day, file.index, file.path.clone()),
+            None => {
+                let index: u32 = files.iter()
+                    .filter(|file| file.day == day)
+                    .map(|file| file.index + 1)
+                    .max()
+                    .unwrap_or(0);
+                (day, index, self.dir.join(format!("{}-{}-{:03}{}", self.prefix, day.format(FILE_DATE_FORMAT), index, PLAIN_EXTENSION)))
+            }
+        };
+
+        if self.gzip {
+            for file in files.iter().filter(|file| !file.gzip && file.path != path) {
+                compress(&file.path)?;
+            }
+        }
+
+        let bytes: u64 = repair_last_line(&path)?;
+        let file: File = OpenOptions::new().create(true).append(true).open(&path)?;
+        info!("Writing the NDJSON history to {}", path.display());
+
+        Ok(OpenFile {
+            day,
+            index,
+            path,
+            writer: BufWriter::new(file),
+            bytes
+        })
+    }
+
+    /// Flushes and syncs a rotated file, then compresses it when asked to.
+    fn finish(
+        &self,
+        mut file: OpenFile
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        file.writer.flush()?;
+        file.writer.get_ref().sync_all()?;
+        info!("Rotated the NDJSON history file {} of {}", file.index, file.day);
+
+        if self.gzip {
+            compress(&file.path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the day and the counter back from a file name of the history.
+    fn parse_file_name(
+        &self,
+        file_name: &str,
+        path: PathBuf
+    ) -> Option<NdjsonFile> {
+        let (stem, gzip): (&str, bool) = match file_name.strip_suffix(GZIP_EXTENSION) {
+            Some(stem) => (stem, true),
+            None => (file_name.strip_suffix(PLAIN_EXTENSION)?, false)
+        };
+        let (day, index): (&str, &str) = stem.strip_prefix(&self.prefix)?.strip_prefix('-')?.rsplit_once('-')?;
+
+        Some(NdjsonFile {
+            day: NaiveDate::parse_from_str(day, FILE_DATE_FORMAT).ok()?,
+            index: index.parse::<u32>().ok()?,
+            gzip,
+            path
+        })
+    }
+}
+
+impl Drop for NdjsonWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            error!("Failed to flush the NDJSON history: {}", err);
+        }
+    }
+}
+
+
+/// # Reads the records of a history file, plain or gzip-compressed.
+///
+/// ### Errors
+/// An error will be returned if the file could not be read, or a line is not a record, the message names the line.
+pub fn read_ndjson<T: DeserializeOwned>(
+    path: impl AsRef<Path>
+) -> Result<Vec<T>, Box<dyn StdError + Send + Sync + 'static>> {
+    let path: &Path = path.as_ref();
+    let file: File = File::open(path)?;
+    let reader: Box<dyn Read> = match path.to_string_lossy().ends_with(".gz") {
+        true => Box::new(GzDecoder::new(file)),
+        false => Box::new(file)
+    };
+
+    let mut records: Vec<T> = Vec::new();
+    for (index, line) in BufReader::new(reader).lines().enumerate() {
+        let line: String = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: T = serde_json::from_str(&line)
+            .map_err(|err| format!("{} line {}: {}", path.display(), index + 1, err))?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+
+/// Compresses the file next to itself, then removes it.
+fn compress(
+    path: &Path
+) -> Result<PathBuf, Box<dyn StdError + Send + Sync + 'static>> {
+    let file_name: String = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let gzip_path: PathBuf = path.with_file_name(format!("{}.gz", file_name));
+    let temp_path: PathBuf = path.with_file_name(format!(".{}.gz.{}.tmp", file_name, std::process::id()));
+
+    let result: Result<(), Box<dyn StdError + Send + Sync + 'static>> = (|| {
+        let mut encoder: GzEncoder<BufWriter<File>> = GzEncoder::new(BufWriter::new(File::create(&temp_path)?), Compression::default());
+        io::copy(&mut File::open(path)?, &mut encoder)?;
+        encoder.finish()?.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+        fs::rename(&temp_path, &gzip_path)?;
+        fs::remove_file(path)?;
+        Ok(())
+    })();
+
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        error!("Failed to compress {}: {}", path.display(), err);
+        return Err(err);
+    }
+
+    Ok(gzip_path)
+}
+
+
+/// Cuts off a last line left without its newline by a crash, and returns the size of the file.
+fn repair_last_line(
+    path: &Path
+) -> Result<u64, Box<dyn StdError + Send + Sync + 'static>> {
+    let mut file: File = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(error) => return Err(Box::new(error))
+    };
+
+    let size: u64 = file.metadata()?.len();
+    let mut end: u64 = size;
+    let mut chunk: Vec<u8> = vec![0; 8 * 1024];
+
+    // Walks back from the end to the last newline
+    while end > 0 {
+        let start: u64 = end.saturating_sub(chunk.len() as u64);
+        let buffer: &mut [u8] = &mut chunk[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(buffer)?;
+
+        match buffer.iter().rposition(|byte| *byte == b'\n') {
+            Some(position) => {
+                end = start + position as u64 + 1;
+                break;
+            },
+            None => end = start
+        }
+    }
 
+    if end < size {
+        warn!("Cut off {} byte(s) of an incomplete last line of {}", size - end, path.display());
+        file.set_len(end)?;
+        file.sync_all()?;
+    }
 
+    Ok(end)
+}
@@ -0,0 +1,450 @@
+//! # HTTP transport
+//!
+//! The fetch path talks to the network through the `HttpTransport` trait, so it can run without the internet.
+//!
+//! ### Implementations
+//! - `ReqwestTransport` - Sends the requests with a `reqwest::Client`, this is what the tracker uses by default.
+//! - `RecordingTransport` - Wraps another transport and records every exchange to a cassette file.
+//! - `ReplayTransport` - Answers from a cassette, without any network access.
+//!
+//! ### Cassettes
+//! A cassette is a JSON file holding the recorded exchanges, in the order they happened:
+//! ```json
+//! {
+//!   "interactions": [
+//!     {
+//!       "request": { "method": "GET", "url": "https://www.myfxbook.com/...", "headers": [], "body": null },
+//!       "response": { "status": 200, "headers": [["content-type", "text/html"]], "body": "<html>..." }
+//!     }
+//!   ]
+//! }
+//! ```
+
+use async_trait::async_trait;
+use reqwest::Client;
+use reqwest::header::{
+    HeaderName,
+    HeaderValue
+};
+use serde_derive::{
+    Deserialize,
+    Serialize
+};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt::Debug;
+use std::fs::{
+    self,
+    File
+};
+use std::io::{
+    BufReader,
+    BufWriter,
+    ErrorKind,
+    Write
+};
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::sync::{
+    Arc,
+    Mutex
+};
+
+use tracing::{
+    info,
+    warn,
+    error
+};
+
+
+/// Struct that represents an HTTP request sent through a transport.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>
+}
+
+impl HttpRequest {
+    /// # Creates a `GET` request without headers.
+    pub fn get(
+        url: &str
+    ) -> Self {
+        Self {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: None
+        }
+    }
+
+    /// # Creates a request with the given method and body.
+    pub fn with_body(
+        method: &str,
+        url: &str,
+        body: String
+    ) -> Self {
+        Self {
+            method: method.to_uppercase(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: Some(body)
+        }
+    }
+
+    /// Adds a header to the request.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+
+/// Struct that represents an HTTP response received through a transport.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String
+}
+
+impl HttpResponse {
+    /// # Creates a response with the given status and body, without headers.
+    pub fn new(
+        status: u16,
+        body: &str
+    ) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.to_string()
+        }
+    }
+
+    /// Returns `true` for a 2xx status.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// # Returns the first value of the header, the name is matched case-insensitively.
+    pub fn header(
+        &self,
+        name: &str
+    ) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+
+/// The `HttpTransport` trait sends an `HttpRequest` and returns the `HttpResponse`.
+///
+/// A non-success status is not an error for the transport, it is returned as a response.
+/// Errors are reserved for requests that did not get any response, like a timeout.
+#[async_trait]
+pub trait HttpTransport: Debug + Send + Sync {
+    async fn send(
+        &self,
+        request: HttpRequest
+    ) -> Result<HttpResponse, Box<dyn StdError + Send + Sync + 'static>>;
+}
+
+
+/// The `ReqwestTransport` struct sends the requests with a `reqwest::Client`.
+///
+/// The `reqwest::Error` of a failed request is returned as is, so the retry policy can classify it.
+#[derive(Debug, Clone)]
+pub struct ReqwestTransport {
+    client: Client
+}
+
+impl ReqwestTransport {
+    /// # Creates a `ReqwestTransport` from the given client.
+    pub fn new(
+        client: Client
+    ) -> Self {
+        Self { client }
+    }
+
+    /// Returns the client of the transport.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+#[async_trait]
+impl HttpTransport for ReqwestTransport {
+    async fn send(
+        &self,
+        request: HttpRequest
+    ) -> Result<HttpResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        let method: reqwest::Method = reqwest::Method::from_bytes(request.method.as_bytes())?;
+        let mut builder: reqwest::RequestBuilder = self.client.request(method, &request.url);
+
+        for (name, value) in &request.headers {
+            builder = builder.header(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+
+        let response: reqwest::Response = builder.send().await?;
+        let status: u16 = response.status().as_u16();
+        let headers: Vec<(String, String)> = response.headers().iter()
+            .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).to_string()))
+            .collect();
+        let body: String = response.text().await?;
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body
+        })
+    }
+}
+
+
+/// Struct that holds a single recorded exchange.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: HttpRequest,
+    pub response: HttpResponse
+}
+
+
+/// Struct that holds the recorded exchanges of a cassette file, in the order they happened.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>
+}
+
+impl Cassette {
+    /// # Creates an empty cassette.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Adds an exchange to the cassette.
+    pub fn record(
+        &mut self,
+        request: HttpRequest,
+        response: HttpResponse
+    ) {
+        self.interactions.push(Interaction { request, response });
+    }
+
+    /// # Loads a cassette file.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be read or is not a cassette.
+    pub fn load(
+        path: impl AsRef<Path>
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let file: File = File::open(path.as_ref())?;
+        let cassette: Cassette = serde_json::from_reader(BufReader::new(file))?;
+
+        Ok(cassette)
+    }
+
+    /// # Writes the cassette to a file, creating the parent directory when needed.
+    ///
+    /// The file is written next to its destination first, then renamed over it.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be written.
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>
+    ) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+        let path: &Path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temporary_path: PathBuf = path.with_extension("json.tmp");
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(&temporary_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temporary_path, path)?;
+
+        Ok(())
+    }
+}
+
+
+/// The `RecordingTransport` struct forwards every request to another transport, and records the exchange to a cassette file.
+///
+/// The cassette is written after every exchange, so a crash never loses what was already recorded.
+/// Requests that did not get a response are not recorded.
+///
+/// ### Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use spread_tracker::SpreadTracker;
+/// use spread_tracker::config::{ SpreadBrokerUrl, Brokers };
+/// use spread_tracker::utils::request_builder::RequestBuilder;
+/// use spread_tracker::utils::transport::{ RecordingTransport, ReqwestTransport };
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let live = ReqwestTransport::new(RequestBuilder::new().build().unwrap());
+/// let recorder = RecordingTransport::new(Arc::new(live), "cassettes/vantage.json").unwrap();
+///
+/// let tracker = SpreadTracker::with_transport(SpreadBrokerUrl::new(), Arc::new(recorder));
+/// tracker.fetch_spread(vec![Brokers::Vantage]).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RecordingTransport {
+    inner: Arc<dyn HttpTransport>,
+    path: PathBuf,
+    cassette: Mutex<Cassette>
+}
+
+impl RecordingTransport {
+    /// # Creates a `RecordingTransport` that appends to the cassette file, or starts a new one if it does not exist.
+    ///
+    /// ### Errors
+    /// An error will be returned if the existing file could not be read or is not a cassette.
+    pub fn new(
+        inner: Arc<dyn HttpTransport>,
+        path: impl AsRef<Path>
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let cassette: Cassette = match Cassette::load(&path) {
+            Ok(cassette) => cassette,
+            Err(error) => match error.downcast_ref::<std::io::Error>() {
+                Some(io_error) if io_error.kind() == ErrorKind::NotFound => Cassette::new(),
+                _ => return Err(error)
+            }
+        };
+
+        Ok(Self {
+            inner,
+            path,
+            cassette: Mutex::new(cassette)
+        })
+    }
+
+    /// Returns a copy of the exchanges recorded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+#[async_trait]
+impl HttpTransport for RecordingTransport {
+    async fn send(
+        &self,
+        request: HttpRequest
+    ) -> Result<HttpResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        let response: HttpResponse = self.inner.send(request.clone()).await?;
+
+        let cassette: Cassette = {
+            let mut cassette = self.cassette.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            cassette.record(request, response.clone());
+            cassette.clone()
+        };
+        if let Err(error) = cassette.save(&self.path) {
+            error!("Failed to save the cassette {}: {}", self.path.display(), error);
+        }
+
+        Ok(response)
+    }
+}
+
+
+/// The `ReplayTransport` struct answers from a cassette, without any network access.
+///
+/// Requests are matched on method and URL. Exchanges of the same request are replayed in the order they were recorded,
+/// and the last one keeps being replayed once they are used up. A request that was never recorded is an error.
+///
+/// ### Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use spread_tracker::SpreadTracker;
+/// use spread_tracker::config::{ SpreadBrokerUrl, Brokers };
+/// use spread_tracker::utils::rate_limiter::RateLimiter;
+/// use spread_tracker::utils::transport::{ Cassette, HttpRequest, HttpResponse, ReplayTransport };
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let config = SpreadBrokerUrl::new();
+///
+/// // A cassette holding the captured myfxbook page of a broker.
+/// let mut cassette = Cassette::new();
+/// cassette.record(
+///     HttpRequest::get(&config.get_url(Brokers::FxPro)),
+///     HttpResponse::new(200, &std::fs::read_to_string("body.txt").unwrap())
+/// );
+///
+/// let tracker = SpreadTracker::with_transport(config, Arc::new(ReplayTransport::new(cassette)))
+///     .with_rate_limiter(Arc::new(RateLimiter::unlimited()));
+/// let spread = tracker.fetch_spread(vec![Brokers::FxPro]).await.unwrap();
+///
+/// let quotes = spread["spread"]["fxpro"].as_array().unwrap();
+/// assert_eq!(quotes.len(), 28);
+/// assert!(quotes.iter().any(|quote| quote["symbol"] == "EURUSD" && quote["spread"] == 0.2));
+/// assert_eq!(spread["fetch"]["fxpro"]["attempts"], 1);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ReplayTransport {
+    cassette: Cassette,
+    cursors: Mutex<HashMap<(String, String), usize>>
+}
+
+impl ReplayTransport {
+    /// # Creates a `ReplayTransport` from a cassette.
+    pub fn new(
+        cassette: Cassette
+    ) -> Self {
+        Self {
+            cassette,
+            cursors: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// # Creates a `ReplayTransport` from a cassette file.
+    ///
+    /// ### Errors
+    /// An error will be returned if the file could not be read or is not a cassette.
+    pub fn from_file(
+        path: impl AsRef<Path>
+    ) -> Result<Self, Box<dyn StdError + Send + Sync + 'static>> {
+        Ok(Self::new(Cassette::load(path)?))
+    }
+}
+
+#[async_trait]
+impl HttpTransport for ReplayTransport {
+    async fn send(
+        &self,
+        request: HttpRequest
+    ) -> Result<HttpResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        let matching: Vec<&Interaction> = self.cassette.interactions.iter()
+            .filter(|interaction| {
+                interaction.request.method.eq_ignore_ascii_case(&request.method) && interaction.request.url == request.url
+            })
+            .collect();
+
+        if matching.is_empty() {
+            warn!("No recorded exchange for {} {}", request.method, request.url);
+            return Err(format!("no recorded exchange for {} {}", request.method, request.url).into());
+        }
+
+        let mut cursors = self.cursors.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let cursor: &mut usize = cursors.entry((request.method.to_uppercase(), request.url.clone())).or_insert(0);
+        let interaction: &Interaction = matching[(*cursor).min(matching.len() - 1)];
+        *cursor += 1;
+
+        Ok(interaction.response.clone())
+    }
+}